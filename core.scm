@@ -1,9 +1,6 @@
 (let (not p) (p false true))
 (let (or p q) (p true q))
 (let (and p q) (p q false))
-(let (cons x y) (lambda (m) (m x y)))
-(let (car z) (z true))
-(let (cdr z) (z false))
 (let
     (fold m s z) (
         (= z nil)
@@ -35,6 +32,6 @@
     )
   )
 )
-(let (showlist z) (map print z))
+(let (showlist z) (map (lambda (x) (begin (display x) (newline))) z))
 (let (fact n) ((= n 0) 1 (* n (fact (- 1 n)))))
 
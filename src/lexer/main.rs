@@ -3,36 +3,46 @@ use std::iter::Peekable;
 use std::str::Chars;
 
 pub struct Lexer<'a> {
-    source: &'a String,
     buffer: Peekable<Chars<'a>>,
     current: char,
-    finished: bool,
-    verbosity: u8 // TODO: make verbosity enum
+    // bytes taken from `buffer` so far, including `current`
+    consumed: usize,
+    finished: bool
 }
 
 const OPERATORS: [char; 5] = ['+', '-' , '*', '/', '='];
+// characters allowed after the first letter of an identifier, e.g. `command-line`, `null?`
+const IDENT_EXTRAS: [char; 13] = ['-', '?', '!', '*', '/', '<', '>', '=', '+', '.', '_', ':', '%'];
 
 impl<'a> Lexer<'a> {
-    pub fn new (src: &'a String, verbosity: u8) -> Self {
+    pub fn new (src: &'a str) -> Self {
         let mut buff = src.chars().peekable();
         let current = *buff.peek().unwrap_or(&'\0');
         let mut l = Lexer {
-            source: src,
             finished: false,
             buffer: buff,
-            current: current,
-            verbosity: verbosity,
+            current,
+            consumed: 0,
         };
         l.next();
-        return l;
+        l
     }
     pub fn is_finished(&self) -> bool {
         self.finished
     }
+    // the byte offset of the next character to be lexed
+    pub fn position(&self) -> usize {
+        if self.finished {
+            self.consumed
+        } else {
+            self.consumed - self.current.len_utf8()
+        }
+    }
     fn next(&mut self) {
         match self.buffer.next() {
             Some(c) => {
                 self.current = c;
+                self.consumed += c.len_utf8();
             },
             None => {
                 self.finished = true;
@@ -48,31 +58,41 @@ impl<'a> Lexer<'a> {
     pub fn lex(&mut self) -> Option<Token> {
         self.next_nw();
         if self.current == '\0' {
-            return Some(Token::EOF);
+            Some(Token::Eof)
         } else if self.current == '"' {
-            return self.lex_string();
-        } else if self.current.is_alphabetic() {
-            return self.lex_ident_or_kw();
-        } else if self.current.is_digit(10) {
-            return self.lex_number();
+            self.lex_string()
+        } else if self.current == '#' {
+            self.lex_hash()
+        } else if self.current.is_alphabetic() || self.current == '<' {
+            self.lex_ident_or_kw()
+        } else if self.current.is_ascii_digit() {
+            self.lex_number()
         } else {
-            return match self.lex_special() {
+            match self.lex_special() {
                 Some(tok) => Some(tok),
                 None => {
-                    Some(Token::Unknown(self.current.to_string()))
+                    let lexeme = self.current.to_string();
+                    self.next();
+                    Some(Token::Unknown(lexeme))
                 }
-            };
+            }
         }
     }
     fn lex_string(&mut self) -> Option<Token> {
         let mut lexeme = String::new();
         self.next();
         while self.current != '"' {
+            if self.finished {
+                return Some(Token::UnterminatedString);
+            }
             if self.current == '\\' {
                 self.next();
-            }
-            if self.current == '\0' {
-                return None;
+                match self.lex_escape() {
+                    Ok(Some(c)) => lexeme.push(c),
+                    Ok(None) => {},
+                    Err(tok) => return Some(tok)
+                }
+                continue;
             }
             lexeme.push(self.current);
             self.next();
@@ -80,34 +100,182 @@ impl<'a> Lexer<'a> {
         self.next(); // move off of breaking '"'
         Some(Token::StringLiteral(lexeme))
     }
+    // called just past a backslash; line continuations produce no char
+    fn lex_escape(&mut self) -> Result<Option<char>, Token> {
+        if self.finished {
+            return Err(Token::UnterminatedString);
+        }
+        let escaped = match self.current {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\u{7}',
+            'b' => '\u{8}',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            'x' | 'X' => {
+                // R7RS `\x41;`
+                self.next();
+                let digits = self.lex_hex_digits(';')?;
+                return Lexer::code_point(&digits).map(Some);
+            },
+            'u' => {
+                // `\u{3bb}`
+                self.next();
+                if self.current != '{' {
+                    return Err(Token::BadEscape(String::from("\\u")));
+                }
+                self.next();
+                let digits = self.lex_hex_digits('}')?;
+                return Lexer::code_point(&digits).map(Some);
+            },
+            c if c == '\n' || c.is_whitespace() => {
+                // `\<intraline whitespace>*<newline><intraline whitespace>*` is a line continuation
+                while self.current != '\n' && self.current.is_whitespace() {
+                    self.next();
+                }
+                if self.current != '\n' {
+                    return Err(Token::BadEscape(String::from("\\ not followed by a newline")));
+                }
+                self.next();
+                while self.current != '\n' && self.current.is_whitespace() {
+                    self.next();
+                }
+                return Ok(None);
+            },
+            c => return Err(Token::BadEscape(format!("\\{}", c)))
+        };
+        self.next();
+        Ok(Some(escaped))
+    }
+    fn lex_hex_digits(&mut self, terminator: char) -> Result<String, Token> {
+        let mut digits = String::new();
+        while self.current.is_ascii_hexdigit() {
+            digits.push(self.current);
+            self.next();
+        }
+        if self.finished {
+            return Err(Token::UnterminatedString);
+        }
+        if self.current != terminator {
+            return Err(Token::BadEscape(format!("\\x{} missing '{}'", digits, terminator)));
+        }
+        self.next();
+        Ok(digits)
+    }
+    fn code_point(digits: &str) -> Result<char, Token> {
+        u32::from_str_radix(digits, 16).ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| Token::BadEscape(format!("\\x{}", digits)))
+    }
+    // `#` starts the reader syntax for literals other than numbers and strings
+    fn lex_hash(&mut self) -> Option<Token> {
+        self.next();
+        match self.current {
+            '\\' => {
+                self.next();
+                self.lex_char()
+            },
+            '(' => {
+                self.next();
+                Some(Token::OpenVector)
+            },
+            '{' => {
+                self.next();
+                Some(Token::OpenSet)
+            },
+            'u' => {
+                self.next();
+                if self.current != '8' {
+                    return Some(Token::Unknown(String::from("#u")));
+                }
+                self.next();
+                if self.current != '(' {
+                    return Some(Token::Unknown(String::from("#u8")));
+                }
+                self.next();
+                Some(Token::OpenBytevector)
+            },
+            't' | 'f' => {
+                let mut name = String::new();
+                while self.current.is_alphabetic() {
+                    name.push(self.current);
+                    self.next();
+                }
+                match name.as_str() {
+                    "t" | "true" => Some(Token::BoolLiteral(true)),
+                    "f" | "false" => Some(Token::BoolLiteral(false)),
+                    _ => Some(Token::Unknown(format!("#{}", name)))
+                }
+            },
+            _ => Some(Token::Unknown(String::from("#")))
+        }
+    }
+    // called just past `#\`, for `#\a`, `#\space` or `#\x3bb`
+    fn lex_char(&mut self) -> Option<Token> {
+        if self.finished {
+            return Some(Token::BadCharacter(String::new()));
+        }
+        let mut name = self.current.to_string();
+        self.next();
+        while self.current.is_alphanumeric() {
+            name.push(self.current);
+            self.next();
+        }
+        let mut chars = name.chars();
+        let first = chars.next().unwrap();
+        if chars.next().is_none() {
+            return Some(Token::CharLiteral(first));
+        }
+        let named = match name.as_str() {
+            "space" => Some(' '),
+            "newline" | "linefeed" => Some('\n'),
+            "tab" => Some('\t'),
+            "return" => Some('\r'),
+            "alarm" => Some('\u{7}'),
+            "backspace" => Some('\u{8}'),
+            "delete" => Some('\u{7f}'),
+            "escape" => Some('\u{1b}'),
+            "null" | "nul" => Some('\0'),
+            _ if first == 'x' => u32::from_str_radix(&name[1..], 16).ok().and_then(std::char::from_u32),
+            _ => None
+        };
+        Some(named.map_or_else(|| Token::BadCharacter(name), Token::CharLiteral))
+    }
     fn lex_ident_or_kw(&mut self) -> Option<Token> {
         let mut lexeme = String::new();
-        while self.current.is_alphabetic() || self.current.is_digit(10) {
+        while self.current.is_alphabetic() || self.current.is_ascii_digit()
+            || IDENT_EXTRAS.iter().any(|v| v == &self.current) {
             lexeme.push(self.current);
             self.next();
         }
         if lexeme == "let" {
-            Some(Token::KeywordToken(Keyword::Let))
+            Some(Token::Keyword(Keyword::Let))
         } else if lexeme == "lambda" {
-            Some(Token::KeywordToken(Keyword::Lambda))
+            Some(Token::Keyword(Keyword::Lambda))
+        } else if lexeme == "begin" {
+            Some(Token::Keyword(Keyword::Begin))
+        } else if lexeme == "include" {
+            Some(Token::Keyword(Keyword::Include))
         } else {
-            Some(Token::IdentifierToken(lexeme))
+            Some(Token::Identifier(lexeme))
         }
     }
     fn lex_number(&mut self) -> Option<Token> {
         let mut lexeme = String::new();
-        while self.current.is_digit(10) {
+        while self.current.is_ascii_digit() {
             lexeme.push(self.current);
             self.next()
         }
         if self.current == '.' {
             lexeme.push(self.current);
             self.next();
-            while self.current.is_digit(10) {
+            while self.current.is_ascii_digit() {
                 lexeme.push(self.current);
                 self.next()
             }
-            return Some(Token::FloatLiteral(lexeme));
+            Some(Token::FloatLiteral(lexeme))
         } else {
             Some(Token::IntLiteral(lexeme))
         }
@@ -119,13 +287,24 @@ impl<'a> Lexer<'a> {
         } else if self.current == ')' {
             self.next();
             Some(Token::ClosePar)
+        } else if self.current == '{' {
+            self.next();
+            Some(Token::OpenBrace)
+        } else if self.current == '}' {
+            self.next();
+            Some(Token::CloseBrace)
         } else if self.current == '\'' {
             self.next();
-            Some(Token::QuoteToken)
+            Some(Token::Quote)
         } else if OPERATORS.iter().any(|v| v == &self.current) {
-            let lexeme = self.current.to_string();
+            // an operator can run on into punctuation, as in `=>` or `->`
+            let mut lexeme = self.current.to_string();
             self.next();
-            Some(Token::IdentifierToken(lexeme))
+            while IDENT_EXTRAS.iter().any(|v| v == &self.current) {
+                lexeme.push(self.current);
+                self.next();
+            }
+            Some(Token::Identifier(lexeme))
         } else {
             None
         }
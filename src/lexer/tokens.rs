@@ -1,19 +1,31 @@
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Keyword {
     Let,
-    Lambda
+    Lambda,
+    Begin,
+    Include
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Token {
-    EOF,
-    IdentifierToken(String),
+    Eof,
+    Identifier(String),
     IntLiteral(String),
     FloatLiteral(String),
     StringLiteral(String),
+    CharLiteral(char),
+    BoolLiteral(bool),
     OpenPar,
     ClosePar,
-    QuoteToken,
+    OpenVector,
+    OpenBytevector,
+    OpenBrace,
+    CloseBrace,
+    OpenSet,
+    Quote,
     Unknown(String),
-    KeywordToken(Keyword)
+    UnterminatedString,
+    BadEscape(String),
+    BadCharacter(String),
+    Keyword(Keyword)
 }
@@ -3,41 +3,69 @@ extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
+use std::env::{args, split_paths, var_os};
+use std::path::{Path, PathBuf};
+use std::process::exit;
 use std::rc::Rc;
-use std::mem::size_of;
 use either::*;
 
 mod lexer;
 mod parser;
 mod runtime;
+use parser::expressions::Expression;
 use parser::main::Parser;
+use runtime::main::{Env, RuntimeError, Value};
+use runtime::stdlib::Prelude;
 
-fn main() {
+const USAGE: &str = "usage: rust-lisp [-e <expr>] [--prelude <file> | --no-prelude] [-L <dir>]... [script.scm] [args...]";
+// overrides the embedded core.scm, like --prelude
+const PRELUDE_VAR: &str = "RUST_LISP_PRELUDE";
+// extra library directories, searched after any -L flags
+const LIBRARY_PATH_VAR: &str = "RUST_LISP_PATH";
+
+// like Env::eval_source, but prints the result of every expression as the REPL does
+fn echo_source(mut env: Rc<Env>, source: &str) -> Result<Rc<Env>, RuntimeError> {
+    let mut p = Parser::new(source, 0);
+    while !p.is_finished() {
+        if let Either::Left(expr) = p.parse(false).map_err(RuntimeError::Syntax)? {
+            let expr = Rc::new(expr);
+            let (new_env, res) = env.eval(&expr)?;
+            // definitions have nothing worth showing, and multiple values get a line each
+            match &*res {
+                _ if matches!(*expr, Expression::LetExpr(_, _)) => {},
+                Value::Values(vals) => vals.iter().for_each(|val| println!("{}", val)),
+                _ => println!("{}", res)
+            }
+            env = new_env;
+        }
+    }
+    Ok(env)
+}
+
+fn run_script(env: Rc<Env>, path: &str) {
+    match env.eval_file(Path::new(path)) {
+        Ok(_) => {},
+        Err(RuntimeError::Exit(status)) => runtime::process::exit(status),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            runtime::process::exit(1);
+        }
+    }
+}
+
+fn run_repl(mut env: Rc<Env>) {
     let mut rl = Editor::<()>::new();
-    let mut env = runtime::stdlib::build_standard_library();
-    // let mut env = Rc::new(runtime::main::Env::new());
-    // println!("Size of env {}", size_of::<runtime::main::Env>());
     loop {
         match rl.readline("  > ") {
             Ok(buffer) => {
-                let mut p = Parser::new(&buffer, 0);
-                while !p.is_finished() {
-                    match p.parse(false) {
-                        Ok(tree) => match tree {
-                            Either::Left(expr) => {
-                                match env.eval(&expr) {
-                                    (new_env, res) => {
-                                        println!("{:?}", res);
-                                        env = new_env;
-                                    }
-                                }
-                                
-                            },
-                            Either::Right(_) => {}
-                        },
-                        Err(err) => println!("{:?}", err)
-                    };
-                }
+                env = match echo_source(env.clone(), &buffer) {
+                    Ok(new_env) => new_env,
+                    Err(RuntimeError::Exit(status)) => runtime::process::exit(status),
+                    Err(err) => {
+                        println!("{}", err);
+                        env
+                    }
+                };
             },
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -54,3 +82,78 @@ fn main() {
         }
     }
 }
+
+fn main() {
+    let mut argv: Vec<String> = args().collect();
+    let program = argv.remove(0);
+    let mut expression = None;
+    let mut library_path = Vec::new();
+    let mut prelude = match var_os(PRELUDE_VAR) {
+        Some(path) => Prelude::File(PathBuf::from(path)),
+        None => Prelude::Embedded
+    };
+    // options come before the script name; everything after it belongs to the script
+    while let Some(arg) = argv.first().cloned() {
+        match arg.as_str() {
+            "-e" | "--prelude" | "-L" if argv.len() < 2 => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            },
+            "-e" => {
+                expression = Some(argv.remove(1));
+            },
+            "--prelude" => {
+                prelude = Prelude::File(PathBuf::from(argv.remove(1)));
+            },
+            "-L" => {
+                library_path.push(PathBuf::from(argv.remove(1)));
+            },
+            "--no-prelude" => {
+                prelude = Prelude::Empty;
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => break
+        }
+        argv.remove(0);
+    }
+
+    if let Some(paths) = var_os(LIBRARY_PATH_VAR) {
+        library_path.extend(split_paths(&paths));
+    }
+    library_path.push(PathBuf::from("."));
+    runtime::modules::set_search_path(library_path);
+
+    let env = match runtime::stdlib::build_standard_library(prelude) {
+        Ok(env) => env,
+        Err(why) => {
+            eprintln!("{}", why);
+            exit(1);
+        }
+    };
+    match expression {
+        Some(expr) => {
+            argv.insert(0, program);
+            runtime::stdlib::set_command_line(argv);
+            match echo_source(env, &expr) {
+                Ok(_) => {},
+                Err(RuntimeError::Exit(status)) => runtime::process::exit(status),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    runtime::process::exit(1);
+                }
+            }
+        },
+        None if !argv.is_empty() => {
+            let script = argv[0].clone();
+            runtime::stdlib::set_command_line(argv);
+            run_script(env, &script);
+        },
+        None => {
+            runtime::stdlib::set_command_line(vec![program]);
+            run_repl(env);
+        }
+    }
+}
@@ -1,16 +1,22 @@
 use std::rc::Rc;
-use std::boxed::Box;
 
 #[derive(Debug, Clone)]
 pub enum Expression {
-    ListExpr(Vec<Box<Expression>>),
-    SExpr(Box<Expression>, Vec<Box<Expression>>),
-    LetExpr(String, Box<Expression>),
+    ListExpr(Vec<Rc<Expression>>),
+    VectorExpr(Vec<Rc<Expression>>),
+    BytevectorLiteral(Vec<u8>),
+    MapExpr(Vec<Rc<Expression>>),
+    SetExpr(Vec<Rc<Expression>>),
+    SExpr(Rc<Expression>, Vec<Rc<Expression>>),
+    LetExpr(String, Rc<Expression>),
     LambdaExpr(Vec<String>, Rc<Expression>),
+    BeginExpr(Vec<Rc<Expression>>),
     LookupExpr(String),
     IntegerLiteral(i32),
     FloatLiteral(f32),
-    StringLiteral(String)
+    StringLiteral(String),
+    CharLiteral(char),
+    BoolLiteral(bool)
 }
 
 pub mod literals {
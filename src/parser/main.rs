@@ -4,37 +4,95 @@ use super::super::lexer::tokens::Keyword;
 use super::expressions::{Expression, Expression::*, literals};
 
 use either::*;
-use std::boxed::Box;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum SyntaxError {
-    EOF,
+    Eof,
     MissingQuote,
     MissingParen,
-    UnexpectedParen,
     BadOperator,
     MalformedLet,
     MalformedLambda,
     BadArgumentName,
+    MalformedInclude,
+    MalformedMap,
+    MalformedRecordType(String),
+    MalformedGuard(String),
+    MalformedShift(String),
+    MalformedValues(String),
+    MalformedPromise(String),
+    MalformedParameterize(String),
+    BadByte(String),
+    UnterminatedString,
+    BadEscape(String),
+    BadCharacter(String),
+    BadInclude(String),
+    CyclicInclude(String),
     UnexpectedToken(Token)
 }
 
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::Eof => write!(f, "unexpected end of input"),
+            SyntaxError::MissingQuote => write!(f, "missing closing quote"),
+            SyntaxError::MissingParen => write!(f, "missing closing paren"),
+            SyntaxError::BadOperator => write!(f, "bad operator"),
+            SyntaxError::MalformedLet => write!(f, "malformed let"),
+            SyntaxError::MalformedLambda => write!(f, "malformed lambda"),
+            SyntaxError::BadArgumentName => write!(f, "bad argument name"),
+            SyntaxError::MalformedInclude => write!(f, "malformed include"),
+            SyntaxError::MalformedMap => write!(f, "malformed map literal"),
+            SyntaxError::MalformedRecordType(form) => write!(f, "malformed define-record-type: {}", form),
+            SyntaxError::MalformedGuard(form) => write!(f, "malformed guard: {}", form),
+            SyntaxError::MalformedShift(form) => write!(f, "malformed shift: {}", form),
+            SyntaxError::MalformedValues(form) => write!(f, "malformed multiple-values form: {}", form),
+            SyntaxError::MalformedPromise(msg) => write!(f, "malformed promise: {}", msg),
+            SyntaxError::MalformedParameterize(form) => write!(f, "malformed parameterize: {}", form),
+            SyntaxError::BadByte(byte) => write!(f, "bad byte: {}", byte),
+            SyntaxError::UnterminatedString => write!(f, "unterminated string"),
+            SyntaxError::BadEscape(escape) => write!(f, "bad escape: {}", escape),
+            SyntaxError::BadCharacter(name) => write!(f, "bad character: {}", name),
+            SyntaxError::BadInclude(msg) => write!(f, "bad include: {}", msg),
+            SyntaxError::CyclicInclude(cycle) => write!(f, "cyclic include: {}", cycle),
+            SyntaxError::UnexpectedToken(tok) => write!(f, "unexpected token: {:?}", tok)
+        }
+    }
+}
+
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current: Option<Token>,
+    // the file being parsed, which `include` paths are relative to
+    origin: Option<PathBuf>,
+    // the files being included into one another to get here, outermost first, which a file
+    // mustn't include again
+    including: Vec<PathBuf>,
     verbose: u8
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a String, verbose: u8) -> Self {
-        let mut lexer = Lexer::new(source, verbose);
+    pub fn new(source: &'a str, verbose: u8) -> Self {
+        let lexer = Lexer::new(source);
         Parser {
-            lexer: lexer,
+            lexer,
             current: None,
-            verbose: verbose
+            origin: None,
+            including: Vec::new(),
+            verbose
         }
     }
+    pub fn for_file(source: &'a str, path: &Path, verbose: u8) -> Self {
+        let mut parser = Parser::new(source, verbose);
+        parser.origin = Some(path.to_path_buf());
+        parser.including.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        parser
+    }
     fn next(&mut self) {
         self.current = self.lexer.lex();
     }
@@ -44,111 +102,196 @@ impl<'a> Parser<'a> {
     pub fn parse(&mut self, skip_quote: bool) -> Result<Either<Expression, Token>, SyntaxError> {
         self.next();
 
-        return match &self.current {
+        match &self.current {
             Some(Token::OpenPar) if skip_quote => self.parse_list_expr(skip_quote).map(Either::Left),
             Some(Token::OpenPar) => self.parse_sexpr(skip_quote).map(Either::Left),
-            Some(Token::QuoteToken) => self.parse_list_expr(skip_quote).map(Either::Left),
-            Some(Token::IdentifierToken(lexeme)) => Ok(Left(Expression::LookupExpr(lexeme.to_string()))),
+            Some(Token::Quote) => self.parse_list_expr(skip_quote).map(Either::Left),
+            Some(Token::OpenVector) => self.parse_vector_expr().map(Either::Left),
+            Some(Token::OpenBytevector) => self.parse_bytevector_expr().map(Either::Left),
+            Some(Token::OpenBrace) => self.parse_map_expr().map(Either::Left),
+            Some(Token::OpenSet) => self.parse_set_expr().map(Either::Left),
+            Some(Token::Identifier(lexeme)) => Ok(Left(Expression::LookupExpr(lexeme.to_string()))),
             Some(Token::IntLiteral(lexeme)) => Ok(Left(literals::integer(lexeme.to_string()))),
             Some(Token::FloatLiteral(lexeme)) => Ok(Left(literals::float(lexeme.to_string()))),
             Some(Token::StringLiteral(lexeme)) => Ok(Left(literals::string(lexeme.to_string()))),
+            Some(Token::CharLiteral(c)) => Ok(Left(Expression::CharLiteral(*c))),
+            Some(Token::BoolLiteral(b)) => Ok(Left(Expression::BoolLiteral(*b))),
             Some(Token::ClosePar) => Ok(Right(Token::ClosePar)),
-            Some(Token::QuoteToken) => Ok(Right(Token::QuoteToken)),
+            Some(Token::CloseBrace) => Ok(Right(Token::CloseBrace)),
             Some(Token::Unknown(lexeme)) => Ok(Right(Token::Unknown(lexeme.to_string()))),
-            Some(Token::KeywordToken(keyword)) => Ok(Right(Token::KeywordToken(*keyword))),
-            Some(Token::EOF) => Ok(Right(Token::EOF)),
-            None => Err(SyntaxError::EOF),
-        };
+            Some(Token::Keyword(keyword)) => Ok(Right(Token::Keyword(*keyword))),
+            Some(Token::UnterminatedString) => Err(SyntaxError::UnterminatedString),
+            Some(Token::BadEscape(escape)) => Err(SyntaxError::BadEscape(escape.to_string())),
+            Some(Token::BadCharacter(name)) => Err(SyntaxError::BadCharacter(name.to_string())),
+            Some(Token::Eof) => Ok(Right(Token::Eof)),
+            None => Err(SyntaxError::Eof),
+        }
     }
     fn parse_list_expr(&mut self, skip_quote: bool) -> Result<Expression, SyntaxError> {
-        if !skip_quote {
-            if self.current.as_ref().map_or(false, |c| *c != Token::QuoteToken) {
+        if !skip_quote
+            && self.current.as_ref().is_some_and(|c| *c != Token::Quote) {
                 return Err(SyntaxError::MissingQuote);
             }
-        }
-        if self.current.as_ref().map_or(false, |c| *c != Token::OpenPar) {
+        if self.current.as_ref().is_some_and(|c| *c != Token::OpenPar) {
                 return Err(SyntaxError::MissingParen);
         }
         let mut contents = Vec::new();
         let mut runner = self.parse(true)?;
-        while runner.as_ref().either(|expr| true, |tok| *tok != Token::ClosePar) {
+        while runner.as_ref().either(|_expr| true, |tok| *tok != Token::ClosePar) {
+            match runner {
+                Left(expr) => {
+                    contents.push(Rc::new(expr));
+                    runner = self.parse(true)?;
+                },
+                Right(tok) => return Err(SyntaxError::UnexpectedToken(tok)),
+            }
+        }
+        Ok(Expression::ListExpr(contents))
+    }
+    // the literal elements of `#(...)`, `#u8(...)`, `{...}` or `#{...}`, up to the `close` token
+    fn parse_literal_elements(&mut self, close: Token) -> Result<Vec<Rc<Expression>>, SyntaxError> {
+        let mut contents = Vec::new();
+        let mut runner = self.parse(true)?;
+        while runner.as_ref().either(|_expr| true, |tok| *tok != close) {
             match runner {
                 Left(expr) => {
-                    contents.push(Box::new(expr));
+                    contents.push(Rc::new(expr));
                     runner = self.parse(true)?;
                 },
                 Right(tok) => return Err(SyntaxError::UnexpectedToken(tok)),
             }
         }
-        return Ok(Expression::ListExpr(contents));
+        Ok(contents)
+    }
+    fn parse_vector_expr(&mut self) -> Result<Expression, SyntaxError> {
+        let contents = self.parse_literal_elements(Token::ClosePar)?;
+        Ok(Expression::VectorExpr(contents))
+    }
+    // `#u8(...)` holds only integer literals from 0 to 255
+    fn parse_bytevector_expr(&mut self) -> Result<Expression, SyntaxError> {
+        let contents = self.parse_literal_elements(Token::ClosePar)?;
+        let bytes: Result<Vec<u8>, SyntaxError> = contents.iter()
+            .map(|expr| match **expr {
+                Expression::IntegerLiteral(i) => u8::try_from(i).map_err(|_| SyntaxError::BadByte(i.to_string())),
+                ref other => Err(SyntaxError::BadByte(other.to_string()))
+            })
+            .collect();
+        Ok(Expression::BytevectorLiteral(bytes?))
+    }
+    fn parse_map_expr(&mut self) -> Result<Expression, SyntaxError> {
+        let contents = self.parse_literal_elements(Token::CloseBrace)?;
+        if contents.len() % 2 != 0 {
+            return Err(SyntaxError::MalformedMap);
+        }
+        Ok(Expression::MapExpr(contents))
+    }
+    fn parse_set_expr(&mut self) -> Result<Expression, SyntaxError> {
+        let contents = self.parse_literal_elements(Token::CloseBrace)?;
+        Ok(Expression::SetExpr(contents))
     }
     fn parse_sexpr(&mut self, skip_quote: bool) -> Result<Expression, SyntaxError> {
-        if self.current.as_ref().map_or(false, |c| *c != Token::OpenPar) {
+        if self.current.as_ref().is_some_and(|c| *c != Token::OpenPar) {
             return Err(SyntaxError::MissingParen);
         }
         let func = self.parse(skip_quote)?;
+        if let Right(Token::ClosePar) = func {
+            // `()` is the empty list, e.g. the argument list of a thunk
+            return Ok(Expression::ListExpr(Vec::new()));
+        }
         let mut args = Vec::new();
         let mut runner = self.parse(skip_quote)?;
-        while runner.as_ref().either(|expr| true, |tok| *tok != Token::ClosePar) {
+        while runner.as_ref().either(|_expr| true, |tok| *tok != Token::ClosePar) {
             match runner {
                 Left(expr) => {
-                    args.push(Box::new(expr));
+                    args.push(Rc::new(expr));
                     runner = self.parse(skip_quote)?;
                 },
                 Right(tok) => return Err(SyntaxError::UnexpectedToken(tok)),
             }
         }
         match func {
-            Right(Token::KeywordToken(keyword)) => match keyword {
+            Right(Token::Keyword(keyword)) => match keyword {
                 Keyword::Lambda => self.parse_lambda_expr(args),
                 Keyword::Let => self.parse_let_expr(args),
+                Keyword::Begin => Ok(Expression::BeginExpr(args)),
+                Keyword::Include => self.parse_include_expr(args),
             },
-            Left(expr) => Ok(Expression::SExpr(Box::new(expr), args)),
+            Left(expr) => Ok(Expression::SExpr(Rc::new(expr), args)),
             _ => Err(SyntaxError::BadOperator) 
         }
     }
-    fn parse_arg_names(arg_list: Vec<Box<Expression>>) -> Result<Vec<String>, SyntaxError> {
+    fn parse_arg_names(arg_list: Vec<Rc<Expression>>) -> Result<Vec<String>, SyntaxError> {
         // collecting into a Result < Vec<String>, SyntaxError > causes us to fail out at the first
         // error, as desired
-        let arg_names: Result<Vec<String>, SyntaxError> = arg_list.into_iter().map(|arg| match *arg {
-            Expression::LookupExpr(arg_name) => Ok(arg_name),
+        let arg_names: Result<Vec<String>, SyntaxError> = arg_list.iter().map(|arg| match &**arg {
+            Expression::LookupExpr(arg_name) => Ok(arg_name.to_string()),
             _ => Err(SyntaxError::BadArgumentName)
         }).collect();
-        return arg_names;
+        arg_names
     }
-    fn parse_let_expr(&mut self, mut args: Vec<Box<Expression>>) -> Result<Expression, SyntaxError> {
+    fn parse_let_expr(&mut self, mut args: Vec<Rc<Expression>>) -> Result<Expression, SyntaxError> {
         if args.len() != 2 {
             return Err(SyntaxError::MalformedLet);
         }
         let name = args.remove(0);
         let body = args.remove(0);
-        match *name {
-            Expression::LookupExpr(lexeme) => Ok(Expression::LetExpr(lexeme, body)),
-            Expression::SExpr(rator, arg_list) => match *rator {
+        match &*name {
+            Expression::LookupExpr(lexeme) => Ok(Expression::LetExpr(lexeme.to_string(), body)),
+            Expression::SExpr(rator, arg_list) => match &**rator {
                 Expression::LookupExpr(func_name) => {
-                    let arg_names = Parser::parse_arg_names(arg_list)?;
-                    Ok(Expression::LetExpr(func_name, Box::new(Expression::LambdaExpr(arg_names, Rc::new(*body)))))
+                    let arg_names = Parser::parse_arg_names(arg_list.to_vec())?;
+                    Ok(Expression::LetExpr(func_name.to_string(), Rc::new(Expression::LambdaExpr(arg_names, body))))
                 },
                 _ => Err(SyntaxError::MalformedLet)
             },
             _ => Err(SyntaxError::MalformedLet)
         }
     }
-    fn parse_lambda_expr(&mut self, mut args: Vec<Box<Expression>>) -> Result<Expression, SyntaxError> {
+    fn parse_lambda_expr(&mut self, mut args: Vec<Rc<Expression>>) -> Result<Expression, SyntaxError> {
         if args.len() != 2 {
             return Err(SyntaxError::MalformedLambda)
         }
         let arg_list = args.remove(0);
         let body = args.remove(0);
-        match *arg_list {
-            SExpr(car, mut cdr) => {
+        match &*arg_list {
+            SExpr(car, cdr) => {
                 // TODO: PARSE LAMBDA DIFFERENTLY (w/ skip quote)
-                let mut arg_list = vec![car];
-                arg_list.append(&mut cdr);
+                let mut arg_list = vec![car.clone()];
+                arg_list.extend(cdr.iter().cloned());
                 let arg_names = Parser::parse_arg_names(arg_list)?;
-                Ok(LambdaExpr(arg_names, Rc::new(*body)))
+                Ok(LambdaExpr(arg_names, body))
             },
+            ListExpr(contents) if contents.is_empty() => Ok(LambdaExpr(Vec::new(), body)),
             _ => Err(SyntaxError::MalformedLambda)
         }
     }
+    fn parse_include_expr(&mut self, args: Vec<Rc<Expression>>) -> Result<Expression, SyntaxError> {
+        let mut contents = Vec::new();
+        for arg in args {
+            let name = match &*arg {
+                Expression::StringLiteral(name) => name.to_string(),
+                _ => return Err(SyntaxError::MalformedInclude)
+            };
+            let path = match self.origin.as_ref().and_then(|origin| origin.parent()) {
+                Some(dir) => dir.join(&name),
+                None => PathBuf::from(&name)
+            };
+            let source = fs::read_to_string(&path)
+                .map_err(|why| SyntaxError::BadInclude(format!("{}: {}", path.display(), why)))?;
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if self.including.contains(&canonical) {
+                return Err(SyntaxError::CyclicInclude(format!("{} is already being included", path.display())));
+            }
+            let mut p = Parser::for_file(&source, &path, self.verbose);
+            // the included file starts its own stack, and everything that got here goes under it
+            p.including.splice(0..0, self.including.iter().cloned());
+            while !p.is_finished() {
+                match p.parse(false)? {
+                    Left(expr) => contents.push(Rc::new(expr)),
+                    Right(_) => {}
+                }
+            }
+        }
+        Ok(Expression::BeginExpr(contents))
+    }
 }
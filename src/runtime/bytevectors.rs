@@ -0,0 +1,239 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_arity, expect_str, expect_int};
+use super::vectors::expect_range;
+
+use std::cell::RefCell;
+use std::convert::{TryFrom, TryInto};
+use std::rc::Rc;
+
+type Bytes = Rc<RefCell<Vec<u8>>>;
+
+fn new_bytevector(bytes: Vec<u8>) -> Rc<Value> {
+    Rc::new(Value::Bytevector(Rc::new(RefCell::new(bytes))))
+}
+
+fn expect_bytevector(name: &str, val: &Value) -> Result<Bytes, RuntimeError> {
+    match val {
+        Value::Bytevector(bytes) => Ok(bytes.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a bytevector, got {}", name, val)))
+    }
+}
+
+fn expect_byte(name: &str, val: &Value) -> Result<u8, RuntimeError> {
+    let i = expect_int(name, val)?;
+    u8::try_from(i).map_err(|_| RuntimeError::RangeError(format!("{}: {} is not a byte", name, i)))
+}
+
+// the index of a `size` byte field, which has to fit inside `len` bytes
+fn expect_field(name: &str, val: &Value, size: usize, len: usize) -> Result<usize, RuntimeError> {
+    let index = expect_int(name, val)?;
+    if index < 0 || index as usize + size > len {
+        return Err(RuntimeError::RangeError(format!("{}: {} bytes at index {} don't fit in 0..{}", name, size, index, len)));
+    }
+    Ok(index as usize)
+}
+
+fn fn_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let bytes: Result<Vec<u8>, RuntimeError> = args.iter().map(|arg| expect_byte("bytevector", arg)).collect();
+    Ok((env, new_bytevector(bytes?)))
+}
+
+fn fn_is_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("bytevector?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Bytevector(_)));
+    Ok((env, res))
+}
+
+fn fn_make_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("make-bytevector", &args, 1, 2)?;
+    let k = expect_int("make-bytevector", &args[0])?;
+    if k < 0 {
+        return Err(RuntimeError::RangeError(format!("make-bytevector: negative length {}", k)));
+    }
+    let fill = match args.get(1) {
+        Some(fill) => expect_byte("make-bytevector", fill)?,
+        None => 0
+    };
+    Ok((env, new_bytevector(vec![fill; k as usize])))
+}
+
+fn fn_bytevector_length(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("bytevector-length", &args, 1, 1)?;
+    let bytes = expect_bytevector("bytevector-length", &args[0])?;
+    let len = bytes.borrow().len();
+    Ok((env, Rc::new(Value::Int(len as i32))))
+}
+
+fn fn_bytevector_u8_ref(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("bytevector-u8-ref", &args, 2, 2)?;
+    let bytes = expect_bytevector("bytevector-u8-ref", &args[0])?;
+    let bytes = bytes.borrow();
+    let k = expect_field("bytevector-u8-ref", &args[1], 1, bytes.len())?;
+    Ok((env, Rc::new(Value::Int(bytes[k] as i32))))
+}
+
+fn fn_bytevector_u8_set(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("bytevector-u8-set!", &args, 3, 3)?;
+    let bytes = expect_bytevector("bytevector-u8-set!", &args[0])?;
+    let mut bytes = bytes.borrow_mut();
+    let k = expect_field("bytevector-u8-set!", &args[1], 1, bytes.len())?;
+    bytes[k] = expect_byte("bytevector-u8-set!", &args[2])?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_bytevector_copy(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("bytevector-copy", &args, 1, 3)?;
+    let bytes = expect_bytevector("bytevector-copy", &args[0])?;
+    let bytes = bytes.borrow();
+    let (start, end) = expect_range("bytevector-copy", &args[1..], bytes.len())?;
+    Ok((env, new_bytevector(bytes[start..end].to_vec())))
+}
+
+fn fn_bytevector_append(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let mut res = Vec::new();
+    for arg in args.iter() {
+        res.extend_from_slice(&expect_bytevector("bytevector-append", arg)?.borrow());
+    }
+    Ok((env, new_bytevector(res)))
+}
+
+fn fn_utf8_to_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("utf8->string", &args, 1, 3)?;
+    let bytes = expect_bytevector("utf8->string", &args[0])?;
+    let bytes = bytes.borrow();
+    let (start, end) = expect_range("utf8->string", &args[1..], bytes.len())?;
+    let s = String::from_utf8(bytes[start..end].to_vec())
+        .map_err(|why| RuntimeError::TypeError(format!("utf8->string: {}", why)))?;
+    Ok((env, Rc::new(Value::Str(s))))
+}
+
+fn fn_string_to_utf8(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string->utf8", &args, 1, 1)?;
+    let s = expect_str("string->utf8", &args[0])?;
+    Ok((env, new_bytevector(s.as_bytes().to_vec())))
+}
+
+// the name of a sized accessor, e.g. `bytevector-s16-be-ref`
+fn accessor_name(kind: char, size: usize, big: bool, op: &str) -> String {
+    format!("bytevector-{}{}-{}-{}", kind, size * 8, if big { "be" } else { "le" }, op)
+}
+
+// the `size` bytes at `k` in the field's byte order, most significant first
+fn read_field(bytes: &[u8], k: usize, size: usize, big: bool) -> Vec<u8> {
+    let mut field = bytes[k..k + size].to_vec();
+    if !big {
+        field.reverse();
+    }
+    field
+}
+
+fn write_field(bytes: &mut [u8], k: usize, mut field: Vec<u8>, big: bool) {
+    if !big {
+        field.reverse();
+    }
+    bytes[k..k + field.len()].copy_from_slice(&field);
+}
+
+// (bytevector-u16-le-ref bv k) and friends read SIZE bytes as an integer
+fn fn_int_ref<const SIZE: usize, const SIGNED: bool, const BIG: bool>(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let name = accessor_name(if SIGNED { 's' } else { 'u' }, SIZE, BIG, "ref");
+    expect_arity(&name, &args, 2, 2)?;
+    let bytes = expect_bytevector(&name, &args[0])?;
+    let bytes = bytes.borrow();
+    let k = expect_field(&name, &args[1], SIZE, bytes.len())?;
+    let raw = read_field(&bytes, k, SIZE, BIG).iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+    let shift = 64 - SIZE * 8;
+    let n = if SIGNED { ((raw << shift) as i64) >> shift } else { raw as i64 };
+    let n = i32::try_from(n).map_err(|_| RuntimeError::RangeError(format!("{}: {} doesn't fit in an integer", name, n)))?;
+    Ok((env, Rc::new(Value::Int(n))))
+}
+
+fn fn_int_set<const SIZE: usize, const SIGNED: bool, const BIG: bool>(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let name = accessor_name(if SIGNED { 's' } else { 'u' }, SIZE, BIG, "set!");
+    expect_arity(&name, &args, 3, 3)?;
+    let bytes = expect_bytevector(&name, &args[0])?;
+    let mut bytes = bytes.borrow_mut();
+    let k = expect_field(&name, &args[1], SIZE, bytes.len())?;
+    let n = expect_int(&name, &args[2])? as i64;
+    let bits = SIZE as u32 * 8;
+    let (min, max) = if SIGNED { (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1) } else { (0, (1i64 << bits) - 1) };
+    if n < min || n > max {
+        return Err(RuntimeError::RangeError(format!("{}: {} not in {}..={}", name, n, min, max)));
+    }
+    let field = (0..SIZE).rev().map(|i| (n >> (i * 8)) as u8).collect();
+    write_field(&mut bytes, k, field, BIG);
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+// floats are read and written as IEEE 754 singles (SIZE 4) or doubles (SIZE 8)
+fn fn_float_ref<const SIZE: usize, const BIG: bool>(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let name = accessor_name('f', SIZE, BIG, "ref");
+    expect_arity(&name, &args, 2, 2)?;
+    let bytes = expect_bytevector(&name, &args[0])?;
+    let bytes = bytes.borrow();
+    let k = expect_field(&name, &args[1], SIZE, bytes.len())?;
+    let field = read_field(&bytes, k, SIZE, BIG);
+    let f = match SIZE {
+        4 => f32::from_be_bytes(field.try_into().unwrap()),
+        _ => f64::from_be_bytes(field.try_into().unwrap()) as f32
+    };
+    Ok((env, Rc::new(Value::Float(f))))
+}
+
+fn fn_float_set<const SIZE: usize, const BIG: bool>(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let name = accessor_name('f', SIZE, BIG, "set!");
+    expect_arity(&name, &args, 3, 3)?;
+    let bytes = expect_bytevector(&name, &args[0])?;
+    let mut bytes = bytes.borrow_mut();
+    let k = expect_field(&name, &args[1], SIZE, bytes.len())?;
+    let f = match &*args[2] {
+        Value::Int(i) => *i as f64,
+        Value::Float(f) => *f as f64,
+        other => return Err(RuntimeError::TypeError(format!("{} expects a number, got {}", name, other)))
+    };
+    let field = match SIZE {
+        4 => (f as f32).to_be_bytes().to_vec(),
+        _ => f.to_be_bytes().to_vec()
+    };
+    write_field(&mut bytes, k, field, BIG);
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector))));
+    table.insert(String::from("bytevector?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_bytevector))));
+    table.insert(String::from("make-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_make_bytevector))));
+    table.insert(String::from("bytevector-length"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector_length))));
+    table.insert(String::from("bytevector-u8-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector_u8_ref))));
+    table.insert(String::from("bytevector-u8-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector_u8_set))));
+    table.insert(String::from("bytevector-copy"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector_copy))));
+    table.insert(String::from("bytevector-append"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_bytevector_append))));
+    table.insert(String::from("utf8->string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_utf8_to_string))));
+    table.insert(String::from("string->utf8"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_to_utf8))));
+    table.insert(String::from("bytevector-u16-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<2, false, false>))));
+    table.insert(String::from("bytevector-u16-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<2, false, true>))));
+    table.insert(String::from("bytevector-s16-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<2, true, false>))));
+    table.insert(String::from("bytevector-s16-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<2, true, true>))));
+    table.insert(String::from("bytevector-u32-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<4, false, false>))));
+    table.insert(String::from("bytevector-u32-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<4, false, true>))));
+    table.insert(String::from("bytevector-s32-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<4, true, false>))));
+    table.insert(String::from("bytevector-s32-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_ref::<4, true, true>))));
+    table.insert(String::from("bytevector-u16-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<2, false, false>))));
+    table.insert(String::from("bytevector-u16-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<2, false, true>))));
+    table.insert(String::from("bytevector-s16-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<2, true, false>))));
+    table.insert(String::from("bytevector-s16-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<2, true, true>))));
+    table.insert(String::from("bytevector-u32-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<4, false, false>))));
+    table.insert(String::from("bytevector-u32-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<4, false, true>))));
+    table.insert(String::from("bytevector-s32-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<4, true, false>))));
+    table.insert(String::from("bytevector-s32-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_int_set::<4, true, true>))));
+    table.insert(String::from("bytevector-f32-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_ref::<4, false>))));
+    table.insert(String::from("bytevector-f32-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_ref::<4, true>))));
+    table.insert(String::from("bytevector-f64-le-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_ref::<8, false>))));
+    table.insert(String::from("bytevector-f64-be-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_ref::<8, true>))));
+    table.insert(String::from("bytevector-f32-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_set::<4, false>))));
+    table.insert(String::from("bytevector-f32-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_set::<4, true>))));
+    table.insert(String::from("bytevector-f64-le-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_set::<8, false>))));
+    table.insert(String::from("bytevector-f64-be-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_float_set::<8, true>))));
+}
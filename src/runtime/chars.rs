@@ -0,0 +1,118 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_arity, expect_int};
+
+use std::rc::Rc;
+
+pub fn expect_char(name: &str, val: &Value) -> Result<char, RuntimeError> {
+    match val {
+        Value::Char(c) => Ok(*c),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a character, got {}", name, val)))
+    }
+}
+
+fn fn_is_char(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("char?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Char(_)));
+    Ok((env, res))
+}
+
+fn fn_char_to_integer(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("char->integer", &args, 1, 1)?;
+    let c = expect_char("char->integer", &args[0])?;
+    Ok((env, Rc::new(Value::Int(c as i32))))
+}
+
+fn fn_integer_to_char(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("integer->char", &args, 1, 1)?;
+    let i = expect_int("integer->char", &args[0])?;
+    match std::char::from_u32(i as u32) {
+        Some(c) if i >= 0 => Ok((env, Rc::new(Value::Char(c)))),
+        _ => Err(RuntimeError::RangeError(format!("integer->char: {} is not a unicode scalar value", i)))
+    }
+}
+
+// case mappings which would expand to several chars, like 'ß' to "SS", leave the char alone
+fn single_char(mut mapped: impl Iterator<Item = char>, c: char) -> char {
+    match (mapped.next(), mapped.next()) {
+        (Some(m), None) => m,
+        _ => c
+    }
+}
+
+fn fn_char_upcase(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("char-upcase", &args, 1, 1)?;
+    let c = expect_char("char-upcase", &args[0])?;
+    Ok((env, Rc::new(Value::Char(single_char(c.to_uppercase(), c)))))
+}
+
+fn fn_char_downcase(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("char-downcase", &args, 1, 1)?;
+    let c = expect_char("char-downcase", &args[0])?;
+    Ok((env, Rc::new(Value::Char(single_char(c.to_lowercase(), c)))))
+}
+
+fn char_class(env: Rc<Env>, args: Vec<Rc<Value>>, name: &str, class: fn (char) -> bool) -> EvalResult {
+    expect_arity(name, &args, 1, 1)?;
+    let c = expect_char(name, &args[0])?;
+    let res = boolean(&env, class(c));
+    Ok((env, res))
+}
+
+fn fn_char_alphabetic(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    char_class(env, args, "char-alphabetic?", char::is_alphabetic)
+}
+
+fn fn_char_numeric(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    char_class(env, args, "char-numeric?", char::is_numeric)
+}
+
+fn fn_char_whitespace(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    char_class(env, args, "char-whitespace?", char::is_whitespace)
+}
+
+// the char comparison predicates hold when every adjacent pair is ordered by `cmp`
+fn compare_chars(env: Rc<Env>, args: Vec<Rc<Value>>, name: &str, cmp: fn (&char, &char) -> bool) -> EvalResult {
+    expect_arity(name, &args, 1, usize::MAX)?;
+    let chars: Result<Vec<char>, RuntimeError> = args.iter().map(|arg| expect_char(name, arg)).collect();
+    let ordered = chars?.windows(2).all(|pair| cmp(&pair[0], &pair[1]));
+    let res = boolean(&env, ordered);
+    Ok((env, res))
+}
+
+fn fn_char_eq(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_chars(env, args, "char=?", |a, b| a == b)
+}
+
+fn fn_char_lt(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_chars(env, args, "char<?", |a, b| a < b)
+}
+
+fn fn_char_gt(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_chars(env, args, "char>?", |a, b| a > b)
+}
+
+fn fn_char_le(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_chars(env, args, "char<=?", |a, b| a <= b)
+}
+
+fn fn_char_ge(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_chars(env, args, "char>=?", |a, b| a >= b)
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("char?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_char))));
+    table.insert(String::from("char->integer"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_to_integer))));
+    table.insert(String::from("integer->char"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_integer_to_char))));
+    table.insert(String::from("char-upcase"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_upcase))));
+    table.insert(String::from("char-downcase"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_downcase))));
+    table.insert(String::from("char-alphabetic?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_alphabetic))));
+    table.insert(String::from("char-numeric?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_numeric))));
+    table.insert(String::from("char-whitespace?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_whitespace))));
+    table.insert(String::from("char=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_eq))));
+    table.insert(String::from("char<?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_lt))));
+    table.insert(String::from("char>?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_gt))));
+    table.insert(String::from("char<=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_le))));
+    table.insert(String::from("char>=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_char_ge))));
+}
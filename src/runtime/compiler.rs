@@ -0,0 +1,547 @@
+use super::super::parser::expressions::{Expression, Expression::*};
+use super::main::{Env, Value, RuntimeFunctionWrapper};
+use super::stdlib::{is_true, is_false};
+use super::values::items;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// Expressions are compiled to code for the machine's stack-based interpreter. Each procedure
+// body gets code of its own, with its variables resolved to numbered slots in its activation,
+// and the variables it uses from the procedures around it copied into slots when it's made,
+// which is the snapshot of its surroundings a procedure has always taken. Anything not found
+// that way is looked up by name in the procedure's env, as top-level code does for everything.
+//
+// An s-expression's operator can't be told apart until it's been evaluated: its operands are
+// evaluated for a procedure, left unevaluated for a syntax builtin, and only one of them
+// evaluated for a boolean. So the operands of a two-operand s-expression, which is all a boolean
+// takes, are compiled as blocks of their own ending in `Return`, run with `Gosub` to pass them to
+// a procedure and jumped to as the branches of a boolean, so a branch is in tail position
+// whenever the s-expression is. Everything else is compiled in place.
+//
+// Syntax can't be told apart before it's evaluated either, but the names syntax is bound to are
+// known by the time code using them is compiled. So an s-expression whose operator is one of
+// them, or isn't bound yet, also gets a form: the code for each of its parts, compiled as
+// top-level code for the syntax builtin to run in an env of its own, and the slots of the
+// variables it uses, which are all that's put in that env.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    // pushes constant `i`
+    Const(usize),
+    // pushes a new copy of constant `i`, for strings and the like which are new objects each
+    // time they're evaluated
+    Fresh(usize),
+    // pushes slot `i`, or looks its name up in the env if nothing's been put there yet
+    Local(usize),
+    // pushes the value of name `i` in the env
+    Global(usize),
+    DefineLocal(usize),
+    DefineGlobal(usize),
+    // pushes a procedure made from prototype `i`
+    Closure(usize),
+    Pop,
+    // runs the block at `pc`, coming back here when it returns
+    Gosub(usize),
+    // returns from the innermost block, or from the code when there isn't one
+    Return,
+    // checks the operator of site `i`, on top of the stack: a boolean jumps to one of the
+    // site's branches, and syntax runs on the site's form, leaving its value after the site
+    Operator(usize),
+    // calls the procedure under the `n` arguments on top of the stack, as a tail call if it's
+    // followed by the code's last `Return`
+    Call(usize),
+    // builds the list, vector, map or set literal of site `i` from its elements on the stack
+    Literal(usize),
+    // site `i` has an operator which can't be evaluated
+    BadRator(usize)
+}
+
+// an expression given to a syntax builtin, with code for each of its parts to evaluate them
+// by. The whole expression of a syntax site isn't evaluated, so has no code of its own
+pub struct Form {
+    pub expr: Rc<Expression>,
+    pub code: Rc<Code>,
+    pub entry: Option<usize>,
+    // the forms of the elements of a parenthesised expression
+    pub parts: Vec<Rc<Form>>
+}
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Form({})", self.expr)
+    }
+}
+
+// an s-expression or literal, for what the code can't do without the expression itself
+pub struct Site {
+    pub expr: Rc<Expression>,
+    // where the operands of a two-operand s-expression start, for a boolean operator to go to
+    pub branches: Option<(usize, usize)>,
+    // where the code carries on after the site; a site in tail position ends at its `Return`
+    pub after: usize,
+    // a site whose value the code after it uses, so a branch is run with `Gosub` to come back
+    pub inline: bool,
+    // for an operator which may be syntax: the slots of the variables the site uses, bound by
+    // name for a syntax builtin, and the site's form
+    pub uses: Vec<usize>,
+    pub form: Option<Rc<Form>>
+}
+
+pub struct Code {
+    pub ops: Vec<Op>,
+    pub entry: usize,
+    pub consts: Vec<Rc<Value>>,
+    // the names looked up or defined by `Global` and `DefineGlobal`
+    pub names: Vec<String>,
+    pub sites: Vec<Site>,
+    pub protos: Vec<Rc<Proto>>,
+    // the name of each slot
+    pub slots: Vec<String>,
+    // the env each name was last looked up in and what it was there, which holds for as long as
+    // that env is about, as envs don't change. Neither is kept alive by it, so a procedure
+    // looking itself up doesn't keep itself alive
+    globals: RefCell<Vec<Option<Lookup>>>
+}
+
+type Lookup = (Weak<Env>, Weak<Value>);
+
+impl Code {
+    // the value of name `i` in `env`
+    pub fn global(&self, i: usize, env: &Rc<Env>) -> Rc<Value> {
+        let mut globals = self.globals.borrow_mut();
+        if let Some((last, val)) = &globals[i] {
+            if Weak::as_ptr(last) == Rc::as_ptr(env) {
+                if let Some(val) = val.upgrade() {
+                    return val;
+                }
+            }
+        }
+        match env.get(&self.names[i]) {
+            Some(val) => {
+                globals[i] = Some((Rc::downgrade(env), Rc::downgrade(&val)));
+                val
+            },
+            None => Rc::new(Value::Nil)
+        }
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Code")
+            .field("ops", &self.ops)
+            .field("entry", &self.entry)
+            .field("slots", &self.slots)
+            .finish()
+    }
+}
+
+// what a lambda expression compiles to, and a procedure is made from
+#[derive(Debug)]
+pub struct Proto {
+    pub code: Rc<Code>,
+    pub arg_names: Vec<String>,
+    // the slot each argument goes in
+    pub params: Vec<usize>,
+    // a named procedure's own name, bound to itself
+    pub own: Option<(String, usize)>,
+    // the slots copied from the code around it, and the slots they go in
+    pub captures: Vec<(usize, usize)>
+}
+
+// a site being compiled, whose branches are block numbers and which ends at an op in a block
+struct PendingSite {
+    expr: Rc<Expression>,
+    branches: Option<(usize, usize)>,
+    after: (usize, usize),
+    inline: bool,
+    uses: Vec<usize>,
+    form: Option<Rc<Form>>
+}
+
+// a form whose code is still being compiled, with the block each part starts at
+struct PendingForm {
+    expr: Rc<Expression>,
+    block: Option<usize>,
+    parts: Vec<PendingForm>
+}
+
+// the code being compiled for one procedure body, or for top-level code
+struct Unit {
+    blocks: Vec<Vec<Op>>,
+    consts: Vec<Rc<Value>>,
+    names: Vec<String>,
+    sites: Vec<PendingSite>,
+    protos: Vec<Rc<Proto>>,
+    slots: Vec<String>,
+    // the slots each name refers to at this point in the code, innermost last
+    scope: Vec<(String, usize)>,
+    captures: Vec<(usize, usize)>,
+    // top-level code keeps everything in the env
+    toplevel: bool
+}
+
+impl Unit {
+    fn new(toplevel: bool) -> Self {
+        Unit {
+            blocks: Vec::new(),
+            consts: Vec::new(),
+            names: Vec::new(),
+            sites: Vec::new(),
+            protos: Vec::new(),
+            slots: Vec::new(),
+            scope: Vec::new(),
+            captures: Vec::new(),
+            toplevel
+        }
+    }
+    fn visible(&self, name: &str) -> Option<usize> {
+        self.scope.iter().rev().find(|(bound, _)| bound == name).map(|(_, slot)| *slot)
+    }
+    fn declare(&mut self, name: &str) -> usize {
+        let slot = self.slots.len();
+        self.slots.push(name.to_string());
+        self.scope.push((name.to_string(), slot));
+        slot
+    }
+    // a definition replaces the value of a name already in a slot here, as it would in an env
+    fn define(&mut self, name: &str) -> usize {
+        match self.visible(name) {
+            Some(slot) => slot,
+            None => self.declare(name)
+        }
+    }
+    fn name(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|known| known == name) {
+            Some(i) => i,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+    fn constant(&mut self, val: Value) -> usize {
+        self.consts.push(Rc::new(val));
+        self.consts.len() - 1
+    }
+    // where each block starts once they're laid out one after another
+    fn starts(&self) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let mut len = 0;
+        for block in self.blocks.iter() {
+            starts.push(len);
+            len += block.len();
+        }
+        starts
+    }
+    // lays the blocks out, turning block numbers into addresses
+    fn finish(self, entry: usize) -> Code {
+        let starts = self.starts();
+        let ops = self.blocks.into_iter().flatten().map(|op| match op {
+            Op::Gosub(block) => Op::Gosub(starts[block]),
+            op => op
+        }).collect();
+        let sites = self.sites.into_iter().map(|PendingSite { expr, branches, after: (block, index), inline, uses, form }| Site {
+            expr,
+            branches: branches.map(|(a, b)| (starts[a], starts[b])),
+            after: starts[block] + index,
+            inline,
+            uses,
+            form
+        }).collect();
+        Code {
+            ops,
+            entry: starts[entry],
+            consts: self.consts,
+            globals: RefCell::new(vec![None; self.names.len()]),
+            names: self.names,
+            sites,
+            protos: self.protos,
+            slots: self.slots
+        }
+    }
+}
+
+// constants and variables, which are evaluated in place rather than as blocks
+fn is_simple(expr: &Expression) -> bool {
+    matches!(expr, LookupExpr(_) | IntegerLiteral(_) | FloatLiteral(_) | StringLiteral(_)
+        | CharLiteral(_) | BoolLiteral(_) | BytevectorLiteral(_))
+}
+
+// every name `expr` looks up, including those of the lambda expressions in it
+fn names_used(expr: &Expression, names: &mut Vec<String>) {
+    match expr {
+        LookupExpr(name) => names.push(name.to_string()),
+        BoolLiteral(b) => names.push(String::from(if *b { "true" } else { "false" })),
+        SExpr(rator, rands) => {
+            names_used(rator, names);
+            rands.iter().for_each(|rand| names_used(rand, names));
+        },
+        ListExpr(items) | VectorExpr(items) | MapExpr(items) | SetExpr(items) | BeginExpr(items) =>
+            items.iter().for_each(|item| names_used(item, names)),
+        LetExpr(_, rhs) => names_used(rhs, names),
+        LambdaExpr(_, body) => names_used(body, names),
+        _ => {}
+    }
+}
+
+struct Compiler<'a> {
+    // the unit being compiled last, inside those of the lambda expressions around it
+    units: Vec<Unit>,
+    // the env the code is compiled in, for telling which operators are syntax
+    env: &'a Env
+}
+
+impl Compiler<'_> {
+    fn unit(&mut self) -> &mut Unit {
+        self.units.last_mut().expect("there's always a unit being compiled")
+    }
+    fn emit(&mut self, block: usize, op: Op) {
+        self.unit().blocks[block].push(op);
+    }
+    // the slot `name` is in at `level`, copying it in from the levels outside if need be
+    fn resolve(&mut self, level: usize, name: &str) -> Option<usize> {
+        if let Some(slot) = self.units[level].visible(name) {
+            return Some(slot);
+        }
+        if level == 0 || self.units[level].toplevel {
+            return None;
+        }
+        let outer = self.resolve(level - 1, name)?;
+        let unit = &mut self.units[level];
+        let slot = unit.declare(name);
+        unit.captures.push((outer, slot));
+        Some(slot)
+    }
+    fn variable(&mut self, block: usize, name: &str) {
+        let level = self.units.len() - 1;
+        let op = match self.resolve(level, name) {
+            Some(slot) => Op::Local(slot),
+            None => Op::Global(self.unit().name(name))
+        };
+        self.emit(block, op);
+    }
+    fn lambda(&mut self, arg_names: &[String], body: &Rc<Expression>, own: Option<&String>) -> Rc<Proto> {
+        let mut unit = Unit::new(false);
+        let params = arg_names.iter().map(|arg| unit.declare(arg)).collect();
+        let own = own.map(|name| (name.to_string(), unit.define(name)));
+        self.units.push(unit);
+        let entry = self.block(body);
+        let unit = self.units.pop().expect("the lambda's unit was pushed above");
+        let captures = unit.captures.clone();
+        Rc::new(Proto {
+            code: Rc::new(unit.finish(entry)),
+            arg_names: arg_names.to_vec(),
+            params,
+            own,
+            captures
+        })
+    }
+    fn closure(&mut self, block: usize, arg_names: &[String], body: &Rc<Expression>, own: Option<&String>) {
+        let proto = self.lambda(arg_names, body, own);
+        let unit = self.unit();
+        unit.protos.push(proto);
+        let i = unit.protos.len() - 1;
+        self.emit(block, Op::Closure(i));
+    }
+    fn new_block(&mut self) -> usize {
+        let unit = self.unit();
+        unit.blocks.push(Vec::new());
+        unit.blocks.len() - 1
+    }
+    // compiles `expr` as a block of its own
+    fn block(&mut self, expr: &Rc<Expression>) -> usize {
+        let block = self.new_block();
+        self.tail(block, expr);
+        block
+    }
+    fn simple(&mut self, block: usize, expr: &Expression) {
+        let op = match expr {
+            LookupExpr(name) => return self.variable(block, name),
+            // `#t` and `#f` are whatever `true` and `false` are bound to
+            BoolLiteral(b) => return self.variable(block, if *b { "true" } else { "false" }),
+            IntegerLiteral(i) => Op::Const(self.unit().constant(Value::Int(*i))),
+            CharLiteral(c) => Op::Const(self.unit().constant(Value::Char(*c))),
+            FloatLiteral(f) => Op::Fresh(self.unit().constant(Value::Float(*f))),
+            StringLiteral(s) => Op::Fresh(self.unit().constant(Value::Str(s.to_string()))),
+            BytevectorLiteral(bytes) => Op::Fresh(self.unit().constant(Value::Bytevector(Rc::new(RefCell::new(bytes.to_vec()))))),
+            _ => unreachable!("only simple expressions are compiled this way")
+        };
+        self.emit(block, op);
+    }
+    fn site(&mut self, expr: &Rc<Expression>, inline: bool) -> usize {
+        let unit = self.unit();
+        unit.sites.push(PendingSite { expr: expr.clone(), branches: None, after: (0, 0), inline, uses: Vec::new(), form: None });
+        unit.sites.len() - 1
+    }
+    // the site carries on from the next op to go in `block`
+    fn end_site(&mut self, block: usize, site: usize) {
+        let unit = self.unit();
+        unit.sites[site].after = (block, unit.blocks[block].len());
+    }
+    // compiles `expr` into `block` in tail position, ending the block
+    fn tail(&mut self, block: usize, expr: &Rc<Expression>) {
+        match &**expr {
+            SExpr(_, _) => self.application(block, expr, false),
+            BeginExpr(body) if !body.is_empty() => {
+                let (last, rest) = body.split_last().expect("the body isn't empty");
+                for child in rest.iter() {
+                    self.value(block, child);
+                    self.emit(block, Op::Pop);
+                }
+                return self.tail(block, last);
+            },
+            _ => self.value(block, expr)
+        }
+        self.emit(block, Op::Return);
+    }
+    // compiles `expr` into `block` to push its value
+    fn value(&mut self, block: usize, expr: &Rc<Expression>) {
+        match &**expr {
+            SExpr(_, _) => self.application(block, expr, true),
+            BeginExpr(body) => match body.split_last() {
+                None => {
+                    let nil = self.unit().constant(Value::Nil);
+                    self.emit(block, Op::Const(nil));
+                },
+                Some((last, rest)) => {
+                    for child in rest.iter() {
+                        self.value(block, child);
+                        self.emit(block, Op::Pop);
+                    }
+                    self.value(block, last);
+                }
+            },
+            LetExpr(name, rhs) => {
+                match &**rhs {
+                    LambdaExpr(arg_names, body) => self.closure(block, arg_names, body, Some(name)),
+                    _ => self.value(block, rhs)
+                }
+                let op = if self.unit().toplevel {
+                    Op::DefineGlobal(self.unit().name(name))
+                } else {
+                    Op::DefineLocal(self.unit().define(name))
+                };
+                self.emit(block, op);
+            },
+            LambdaExpr(arg_names, body) => self.closure(block, arg_names, body, None),
+            ListExpr(items) | VectorExpr(items) | MapExpr(items) | SetExpr(items) => {
+                let site = self.site(expr, true);
+                for item in items.iter() {
+                    self.value(block, item);
+                }
+                self.emit(block, Op::Literal(site));
+                self.end_site(block, site);
+            },
+            _ => self.simple(block, expr)
+        }
+    }
+    // an s-expression, whose value is pushed if it's `inline` and returned otherwise
+    fn application(&mut self, block: usize, expr: &Rc<Expression>, inline: bool) {
+        let (rator, rands) = match &**expr {
+            SExpr(rator, rands) => (rator, rands),
+            _ => unreachable!("only s-expressions are applications")
+        };
+        let site = self.site(expr, inline);
+        match &**rator {
+            SExpr(_, _) | LookupExpr(_) | LambdaExpr(_, _) | LetExpr(_, _) | BeginExpr(_) => {},
+            _ => {
+                self.emit(block, Op::BadRator(site));
+                return self.end_site(block, site);
+            }
+        }
+        self.value(block, rator);
+        self.emit(block, Op::Operator(site));
+        if rands.len() == 2 {
+            let mut branches = Vec::new();
+            for rand in rands.iter() {
+                if is_simple(rand) {
+                    self.simple(block, rand);
+                    let branch = self.new_block();
+                    self.simple(branch, rand);
+                    self.emit(branch, Op::Return);
+                    branches.push(branch);
+                } else {
+                    let inner = self.block(rand);
+                    self.emit(block, Op::Gosub(inner));
+                    branches.push(inner);
+                }
+            }
+            self.unit().sites[site].branches = Some((branches[0], branches[1]));
+        } else {
+            for rand in rands.iter() {
+                self.value(block, rand);
+            }
+        }
+        self.emit(block, Op::Call(rands.len()));
+        self.end_site(block, site);
+        if let LookupExpr(name) = &**rator {
+            self.syntax(site, name, expr);
+        }
+    }
+    // gives the site what a syntax builtin would need, if `name` might be bound to one
+    fn syntax(&mut self, site: usize, name: &str, expr: &Rc<Expression>) {
+        let level = self.units.len() - 1;
+        if self.resolve(level, name).is_some() {
+            return;
+        }
+        let form = match self.env.get(name).as_deref() {
+            None | Some(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(_))) => Some(form(expr, self.env)),
+            Some(val @ Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(_))) if !is_true(val) && !is_false(val) => None,
+            Some(_) => return
+        };
+        let mut names = Vec::new();
+        names_used(expr, &mut names);
+        let mut uses = Vec::new();
+        for name in names.iter() {
+            if let Some(slot) = self.resolve(level, name) {
+                if !uses.contains(&slot) {
+                    uses.push(slot);
+                }
+            }
+        }
+        let site = &mut self.unit().sites[site];
+        site.uses = uses;
+        site.form = form;
+    }
+    // gives each part of `expr` a block, and its parts theirs
+    fn parts(&mut self, expr: &Rc<Expression>) -> Vec<PendingForm> {
+        items(expr).unwrap_or_default().iter()
+            .map(|part| PendingForm { expr: part.clone(), block: Some(self.block(part)), parts: self.parts(part) })
+            .collect()
+    }
+}
+
+fn finish_form(pending: PendingForm, code: &Rc<Code>, starts: &[usize]) -> Rc<Form> {
+    Rc::new(Form {
+        expr: pending.expr,
+        code: code.clone(),
+        entry: pending.block.map(|block| starts[block]),
+        parts: pending.parts.into_iter().map(|part| finish_form(part, code, starts)).collect()
+    })
+}
+
+// the form of a syntax site, whose parts are compiled together as top-level code
+fn form(expr: &Rc<Expression>, env: &Env) -> Rc<Form> {
+    let mut compiler = Compiler { units: vec![Unit::new(true)], env };
+    let parts = compiler.parts(expr);
+    let unit = compiler.units.pop().expect("the form's unit");
+    let starts = unit.starts();
+    let code = Rc::new(unit.finish(0));
+    finish_form(PendingForm { expr: expr.clone(), block: None, parts }, &code, &starts)
+}
+
+// compiles a top-level expression in `env`
+pub fn compile(expr: &Rc<Expression>, env: &Env) -> Code {
+    let mut compiler = Compiler { units: vec![Unit::new(true)], env };
+    let entry = compiler.block(expr);
+    compiler.units.pop().expect("the top-level unit").finish(entry)
+}
+
+// compiles a lambda expression evaluated outside of any procedure
+pub fn compile_lambda(arg_names: &[String], body: &Rc<Expression>, own: Option<&String>, env: &Env) -> Rc<Proto> {
+    let mut compiler = Compiler { units: vec![Unit::new(true)], env };
+    compiler.lambda(arg_names, body, own)
+}
@@ -0,0 +1,64 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, StepResult};
+use super::machine::Machine;
+use super::stdlib::expect_arity;
+use super::compiler::Form;
+
+use std::rc::Rc;
+
+// (call-with-current-continuation proc) calls proc with the continuation of the call, which
+// can be resumed any number of times, even after the call has returned
+fn fn_call_cc(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("call-with-current-continuation", &args, 1, 1)?;
+    machine.call_cc(args[0].clone(), env)
+}
+
+// (call-with-escape-continuation proc), like call/cc but only good for leaving the call, and
+// only until it returns
+fn fn_call_ec(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("call-with-escape-continuation", &args, 1, 1)?;
+    machine.call_ec(args[0].clone(), env)
+}
+
+// (dynamic-wind before thunk after); before runs whenever the thunk is entered, including by
+// resuming a continuation, and after whenever it's left
+fn fn_dynamic_wind(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("dynamic-wind", &args, 3, 3)?;
+    machine.dynamic_wind(args[0].clone(), args[1].clone(), args[2].clone(), env)
+}
+
+// (call-with-prompt tag thunk handler) calls thunk under a prompt. An (abort-to-prompt tag value ...)
+// inside it leaves for the prompt, calling (handler k value ...) with k the continuation up to
+// the prompt, which can be called like a procedure. Tags are compared with equal?
+fn fn_call_with_prompt(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("call-with-prompt", &args, 3, 3)?;
+    machine.call_with_prompt(args[0].clone(), args[1].clone(), Some(args[2].clone()), env)
+}
+
+fn fn_abort_to_prompt(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("abort-to-prompt", &args, 1, usize::MAX)?;
+    machine.abort_to_prompt(args[0].clone(), args[1..].to_vec(), env)
+}
+
+// (reset body ...)
+fn fn_reset(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    machine.reset(form, env)
+}
+
+// (shift k body ...)
+fn fn_shift(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    machine.shift(form, env)
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("call-with-current-continuation"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_cc))));
+    table.insert(String::from("call/cc"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_cc))));
+    table.insert(String::from("call-with-escape-continuation"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_ec))));
+    table.insert(String::from("call/ec"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_ec))));
+    table.insert(String::from("dynamic-wind"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_dynamic_wind))));
+    table.insert(String::from("call-with-prompt"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_with_prompt))));
+    table.insert(String::from("abort-to-prompt"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_abort_to_prompt))));
+    table.insert(String::from("reset"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_reset))));
+    table.insert(String::from("shift"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_shift))));
+}
@@ -0,0 +1,168 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, EvalResult};
+use super::stdlib::{boolean, expect_arity};
+
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// how deep into nested pairs and vectors `hash_value` looks before giving up
+const HASH_DEPTH: usize = 4;
+
+// identity: the same object, or the same immediate (nil, integer or character)
+pub fn is_eq(a: &Rc<Value>, b: &Rc<Value>) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+    match (&**a, &**b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Eof, Value::Eof) => true,
+        (Value::Symbol(x), Value::Symbol(y)) => x == y,
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Char(x), Value::Char(y)) => x == y,
+        (Value::Lambda(x), Value::Lambda(y)) => Rc::ptr_eq(x, y),
+        (Value::Vector(x), Value::Vector(y)) => Rc::ptr_eq(x, y),
+        (Value::Bytevector(x), Value::Bytevector(y)) => Rc::ptr_eq(x, y),
+        (Value::HashTable(x), Value::HashTable(y)) => Rc::ptr_eq(x, y),
+        (Value::Record(x), Value::Record(y)) => Rc::ptr_eq(x, y),
+        (Value::RecordType(x), Value::RecordType(y)) => Rc::ptr_eq(x, y),
+        (Value::Port(x), Value::Port(y)) => Rc::ptr_eq(x, y),
+        (Value::Condition(x), Value::Condition(y)) => Rc::ptr_eq(x, y),
+        (Value::Continuation(x), Value::Continuation(y)) => Rc::ptr_eq(x, y),
+        (Value::Promise(x), Value::Promise(y)) => Rc::ptr_eq(x, y),
+        (Value::Parameter(x), Value::Parameter(y)) => Rc::ptr_eq(x, y),
+        (Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(x)), Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(y))) =>
+            std::ptr::fn_addr_eq(*x, *y),
+        (Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(x)), Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(y))) =>
+            std::ptr::fn_addr_eq(*x, *y),
+        (Value::RuntimeFunction(RuntimeFunctionWrapper::Control(x)), Value::RuntimeFunction(RuntimeFunctionWrapper::Control(y))) =>
+            std::ptr::fn_addr_eq(*x, *y),
+        (Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(x)), Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(y))) =>
+            std::ptr::fn_addr_eq(*x, *y),
+        _ => false
+    }
+}
+
+// identity, plus floats with the same value; 0.0 and -0.0 stay distinct
+pub fn is_eqv(a: &Rc<Value>, b: &Rc<Value>) -> bool {
+    match (&**a, &**b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        _ => is_eq(a, b)
+    }
+}
+
+// structure: strings, pairs, vectors, bytevectors, records, maps and sets with equal contents
+pub fn is_equal(a: &Rc<Value>, b: &Rc<Value>) -> bool {
+    equal_seen(a, b, &mut HashSet::new())
+}
+
+// `seen` holds the mutable containers already being compared, by address, which are assumed
+// equal when met again, so cyclic vectors and records compare without looping forever
+fn equal_seen(a: &Rc<Value>, b: &Rc<Value>, seen: &mut HashSet<(usize, usize)>) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    // walks down the cdrs in a loop, so long lists don't use up the stack
+    loop {
+        if is_eqv(&a, &b) {
+            return true;
+        }
+        let (next_a, next_b) = match (&*a, &*b) {
+            (Value::Pair(x_car, x_cdr), Value::Pair(y_car, y_cdr)) => {
+                if !equal_seen(x_car, y_car, seen) {
+                    return false;
+                }
+                (x_cdr.clone(), y_cdr.clone())
+            },
+            (Value::Str(x), Value::Str(y)) => return x == y,
+            (Value::Bytevector(x), Value::Bytevector(y)) => return *x.borrow() == *y.borrow(),
+            (Value::Vector(x), Value::Vector(y)) => {
+                if !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    return true;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                return x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| equal_seen(a, b, seen));
+            },
+            (Value::Record(x), Value::Record(y)) => {
+                if !Rc::ptr_eq(x.record_type(), y.record_type()) {
+                    return false;
+                }
+                if !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    return true;
+                }
+                return x.fields().iter().zip(y.fields().iter()).all(|((_, a), (_, b))| equal_seen(a, b, seen));
+            },
+            (Value::Map(x), Value::Map(y)) => return x.same_entries(y),
+            (Value::Set(x), Value::Set(y)) => return x == y,
+            _ => return false
+        };
+        a = next_a;
+        b = next_b;
+    }
+}
+
+// a hash consistent with `is_equal` (or `is_eqv` when `structural` is false)
+pub fn hash_value<H: Hasher>(val: &Rc<Value>, structural: bool, state: &mut H) {
+    hash_to_depth(val, structural, HASH_DEPTH, state);
+}
+
+fn hash_to_depth<H: Hasher>(val: &Rc<Value>, structural: bool, depth: usize, state: &mut H) {
+    std::mem::discriminant(&**val).hash(state);
+    match &**val {
+        Value::Nil | Value::Eof => {},
+        Value::Int(i) => i.hash(state),
+        Value::Float(f) => f.to_bits().hash(state),
+        Value::Char(c) => c.hash(state),
+        Value::Symbol(s) => s.hash(state),
+        Value::Lambda(lambda) => Rc::as_ptr(lambda).hash(state),
+        Value::Vector(slots) if !structural => Rc::as_ptr(slots).hash(state),
+        Value::Bytevector(bytes) if !structural => Rc::as_ptr(bytes).hash(state),
+        Value::HashTable(table) => Rc::as_ptr(table).hash(state),
+        Value::Record(record) if !structural => Rc::as_ptr(record).hash(state),
+        Value::RecordType(rtd) => Rc::as_ptr(rtd).hash(state),
+        Value::Port(port) => Rc::as_ptr(port).hash(state),
+        Value::Condition(condition) => Rc::as_ptr(condition).hash(state),
+        Value::Continuation(k) => Rc::as_ptr(k).hash(state),
+        Value::Promise(promise) => Rc::as_ptr(promise).hash(state),
+        Value::Parameter(param) => Rc::as_ptr(param).hash(state),
+        Value::RuntimeFunction(_) => {},
+        _ if !structural => Rc::as_ptr(val).hash(state),
+        Value::Str(s) => s.hash(state),
+        Value::Bytevector(bytes) => bytes.borrow().hash(state),
+        Value::Pair(car, cdr) if depth > 0 => {
+            hash_to_depth(car, structural, depth - 1, state);
+            hash_to_depth(cdr, structural, depth - 1, state);
+        },
+        // equal records share a type, so the type is enough to hash them by
+        Value::Record(record) => Rc::as_ptr(record.record_type()).hash(state),
+        Value::Vector(slots) if depth > 0 => {
+            let slots = slots.borrow();
+            slots.len().hash(state);
+            slots.iter().for_each(|slot| hash_to_depth(slot, structural, depth - 1, state));
+        },
+        _ => {}
+    }
+}
+
+fn fn_is_eq(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("eq?", &args, 2, 2)?;
+    let res = boolean(&env, is_eq(&args[0], &args[1]));
+    Ok((env, res))
+}
+
+fn fn_is_eqv(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("eqv?", &args, 2, 2)?;
+    let res = boolean(&env, is_eqv(&args[0], &args[1]));
+    Ok((env, res))
+}
+
+fn fn_is_equal(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("equal?", &args, 2, 2)?;
+    let res = boolean(&env, is_equal(&args[0], &args[1]));
+    Ok((env, res))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("eq?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_eq))));
+    table.insert(String::from("eqv?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_eqv))));
+    table.insert(String::from("equal?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_equal))));
+}
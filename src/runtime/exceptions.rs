@@ -0,0 +1,151 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::Machine;
+use super::stdlib::{boolean, expect_arity};
+use super::compiler::Form;
+
+use std::fmt;
+use std::rc::Rc;
+
+// the error objects made by `error`, and by builtins failing
+#[derive(Debug)]
+pub struct Condition {
+    // how the error reads when nothing handles it, e.g. "type error"
+    kind: &'static str,
+    message: String,
+    irritants: Vec<Rc<Value>>
+}
+
+impl Condition {
+    pub fn kind(&self) -> &str {
+        self.kind
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn irritants(&self) -> &[Rc<Value>] {
+        &self.irritants
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)?;
+        for irritant in self.irritants.iter() {
+            write!(f, " {}", irritant)?;
+        }
+        Ok(())
+    }
+}
+
+fn condition(kind: &'static str, message: String, irritants: Vec<Rc<Value>>) -> Rc<Value> {
+    Rc::new(Value::Condition(Rc::new(Condition { kind, message, irritants })))
+}
+
+// what a handler or guard is given for an error: the raised object itself, or a condition
+// standing in for a builtin's error
+pub fn error_value(err: RuntimeError) -> Rc<Value> {
+    let (kind, message) = match err {
+        RuntimeError::Raised(val) => return val,
+        RuntimeError::Syntax(err) => ("syntax error", err.to_string()),
+        RuntimeError::BadRator(msg) => ("not a procedure", msg),
+        RuntimeError::TypeError(msg) => ("type error", msg),
+        RuntimeError::ArityError(msg) => ("arity error", msg),
+        RuntimeError::RangeError(msg) => ("out of range", msg),
+        RuntimeError::ModuleError(msg) => ("module error", msg),
+        RuntimeError::IOError(msg) => ("i/o error", msg),
+        RuntimeError::ReadError(msg) => ("read error", msg),
+        RuntimeError::ControlError(msg) => ("control error", msg),
+        // handlers never see an exit or a continuation being resumed, but they're RuntimeErrors all the same
+        RuntimeError::Exit(status) => ("exit", status.to_string()),
+        RuntimeError::Transfer(transfer) => ("continuation", format!("{:?}", transfer))
+    };
+    condition(kind, message, Vec::new())
+}
+
+pub fn non_continuable(obj: Rc<Value>) -> Rc<Value> {
+    condition("error", String::from("handler returned from non-continuable raise"), vec![obj])
+}
+
+// (error message irritant ...)
+fn fn_error(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("error", &args, 1, usize::MAX)?;
+    let message = format!("{:#}", args[0]);
+    machine.raise(condition("error", message, args[1..].to_vec()), false, env)
+}
+
+fn fn_raise(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("raise", &args, 1, 1)?;
+    machine.raise(args[0].clone(), false, env)
+}
+
+fn fn_raise_continuable(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("raise-continuable", &args, 1, 1)?;
+    machine.raise(args[0].clone(), true, env)
+}
+
+// (with-exception-handler handler thunk)
+fn fn_with_exception_handler(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("with-exception-handler", &args, 2, 2)?;
+    machine.with_exception_handler(args[0].clone(), args[1].clone(), env)
+}
+
+// (guard (var clause ...) body ...)
+fn fn_guard(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    machine.guard(form, env)
+}
+
+fn expect_condition<'a>(name: &str, val: &'a Value) -> Result<&'a Rc<Condition>, RuntimeError> {
+    match val {
+        Value::Condition(condition) => Ok(condition),
+        _ => Err(RuntimeError::TypeError(format!("{} expects an error object, got {}", name, val)))
+    }
+}
+
+fn fn_is_error_object(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("error-object?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Condition(_)));
+    Ok((env, res))
+}
+
+fn fn_error_object_message(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("error-object-message", &args, 1, 1)?;
+    let condition = expect_condition("error-object-message", &args[0])?;
+    Ok((env, Rc::new(Value::Str(condition.message().to_string()))))
+}
+
+fn fn_error_object_irritants(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("error-object-irritants", &args, 1, 1)?;
+    let condition = expect_condition("error-object-irritants", &args[0])?;
+    Ok((env, Value::list(condition.irritants().to_vec())))
+}
+
+fn condition_is(env: Rc<Env>, args: Vec<Rc<Value>>, name: &str, kinds: &[&str]) -> EvalResult {
+    expect_arity(name, &args, 1, 1)?;
+    let res = boolean(&env, matches!(&*args[0], Value::Condition(condition) if kinds.contains(&condition.kind())));
+    Ok((env, res))
+}
+
+fn fn_is_file_error(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    condition_is(env, args, "file-error?", &["i/o error"])
+}
+
+// bad data given to `read`, or bad code given to `load`
+// bad data given to `read`, or bad code given to `load`
+fn fn_is_read_error(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    condition_is(env, args, "read-error?", &["read error", "syntax error"])
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("error"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_error))));
+    table.insert(String::from("raise"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_raise))));
+    table.insert(String::from("raise-continuable"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_raise_continuable))));
+    table.insert(String::from("with-exception-handler"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_with_exception_handler))));
+    table.insert(String::from("guard"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_guard))));
+    table.insert(String::from("error-object?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_error_object))));
+    table.insert(String::from("error-object-message"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_error_object_message))));
+    table.insert(String::from("error-object-irritants"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_error_object_irritants))));
+    table.insert(String::from("file-error?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_file_error))));
+    table.insert(String::from("read-error?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_read_error))));
+}
@@ -0,0 +1,150 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::stdlib::{boolean, expect_arity, expect_str};
+use super::ports::{PortRef, open_input_file, open_output_file, input_binding, output_binding};
+use super::machine::Machine;
+use super::parameters::Parameter;
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+fn fs_error(name: &str, path: &str, why: io::Error) -> RuntimeError {
+    RuntimeError::IOError(format!("{}: {}: {}", name, path, why))
+}
+
+fn fn_file_exists(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("file-exists?", &args, 1, 1)?;
+    let path = expect_str("file-exists?", &args[0])?;
+    let res = boolean(&env, fs::metadata(path).is_ok());
+    Ok((env, res))
+}
+
+fn fn_delete_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("delete-file", &args, 1, 1)?;
+    let path = expect_str("delete-file", &args[0])?;
+    fs::remove_file(path).map_err(|why| fs_error("delete-file", path, why))?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_rename_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("rename-file", &args, 2, 2)?;
+    let from = expect_str("rename-file", &args[0])?;
+    let to = expect_str("rename-file", &args[1])?;
+    fs::rename(from, to).map_err(|why| fs_error("rename-file", from, why))?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+// (directory-list [path]) is the names in a directory, sorted, without `.` and `..`
+fn fn_directory_list(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("directory-list", &args, 0, 1)?;
+    let path = match args.first() {
+        Some(path) => expect_str("directory-list", path)?.clone(),
+        None => String::from(".")
+    };
+    let entries = fs::read_dir(&path).map_err(|why| fs_error("directory-list", &path, why))?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|why| fs_error("directory-list", &path, why))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok((env, Value::list(names.into_iter().map(|name| Rc::new(Value::Str(name))).collect())))
+}
+
+fn fn_make_directory(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("make-directory", &args, 1, 1)?;
+    let path = expect_str("make-directory", &args[0])?;
+    fs::create_dir(path).map_err(|why| fs_error("make-directory", path, why))?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn metadata(name: &str, path: &Value) -> Result<fs::Metadata, RuntimeError> {
+    let path = expect_str(name, path)?;
+    fs::metadata(path).map_err(|why| fs_error(name, path, why))
+}
+
+fn fn_file_size(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("file-size", &args, 1, 1)?;
+    let size = metadata("file-size", &args[0])?.len();
+    let size = i32::try_from(size)
+        .map_err(|_| RuntimeError::RangeError(format!("file-size: {} bytes is too large for an integer", size)))?;
+    Ok((env, Rc::new(Value::Int(size))))
+}
+
+// seconds since the Unix epoch
+fn fn_file_modification_time(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("file-modification-time", &args, 1, 1)?;
+    let path = expect_str("file-modification-time", &args[0])?;
+    let modified = metadata("file-modification-time", &args[0])?.modified()
+        .map_err(|why| fs_error("file-modification-time", path, why))?;
+    let seconds = modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let seconds = i32::try_from(seconds)
+        .map_err(|_| RuntimeError::RangeError(format!("file-modification-time: {} is too late for an integer", seconds)))?;
+    Ok((env, Rc::new(Value::Int(seconds))))
+}
+
+// (current-directory [path]) is the working directory, changing it first when given a path
+fn fn_current_directory(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("current-directory", &args, 0, 1)?;
+    if let Some(path) = args.first() {
+        let path = expect_str("current-directory", path)?;
+        env::set_current_dir(path).map_err(|why| fs_error("current-directory", path, why))?;
+    }
+    let dir = env::current_dir().map_err(|why| fs_error("current-directory", ".", why))?;
+    Ok((env, Rc::new(Value::Str(dir.to_string_lossy().into_owned()))))
+}
+
+// calls `proc` with `bindings` parameterized, closing `port` once the call returns. A call left
+// by a continuation leaves the port open, to be resumed
+fn call_with_port(machine: &mut Machine, env: Rc<Env>, port: PortRef, bindings: Vec<(Rc<Parameter>, Rc<Value>)>, proc: Rc<Value>, args: Vec<Rc<Value>>) -> StepResult {
+    machine.call_parameterized(bindings, proc, args, env, move |machine, val, env| {
+        port.borrow_mut().close()?;
+        machine.ret(val, env)
+    })
+}
+
+fn fn_call_with_input_file(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("call-with-input-file", &args, 2, 2)?;
+    let port = open_input_file("call-with-input-file", expect_str("call-with-input-file", &args[0])?)?;
+    call_with_port(machine, env, port.clone(), Vec::new(), args[1].clone(), vec![Rc::new(Value::Port(port))])
+}
+
+fn fn_call_with_output_file(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("call-with-output-file", &args, 2, 2)?;
+    let port = open_output_file("call-with-output-file", expect_str("call-with-output-file", &args[0])?)?;
+    call_with_port(machine, env, port.clone(), Vec::new(), args[1].clone(), vec![Rc::new(Value::Port(port))])
+}
+
+fn fn_with_input_from_file(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("with-input-from-file", &args, 2, 2)?;
+    let port = open_input_file("with-input-from-file", expect_str("with-input-from-file", &args[0])?)?;
+    let bindings = vec![input_binding(port.clone())];
+    call_with_port(machine, env, port, bindings, args[1].clone(), Vec::new())
+}
+
+fn fn_with_output_to_file(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("with-output-to-file", &args, 2, 2)?;
+    let port = open_output_file("with-output-to-file", expect_str("with-output-to-file", &args[0])?)?;
+    let bindings = vec![output_binding(port.clone())];
+    call_with_port(machine, env, port, bindings, args[1].clone(), Vec::new())
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("file-exists?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_file_exists))));
+    table.insert(String::from("delete-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_delete_file))));
+    table.insert(String::from("rename-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_rename_file))));
+    table.insert(String::from("directory-list"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_directory_list))));
+    table.insert(String::from("make-directory"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_make_directory))));
+    table.insert(String::from("file-size"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_file_size))));
+    table.insert(String::from("file-modification-time"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_file_modification_time))));
+    table.insert(String::from("current-directory"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_current_directory))));
+    table.insert(String::from("call-with-input-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_with_input_file))));
+    table.insert(String::from("call-with-output-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_with_output_file))));
+    table.insert(String::from("with-input-from-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_with_input_from_file))));
+    table.insert(String::from("with-output-to-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_with_output_to_file))));
+}
@@ -0,0 +1,198 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::Machine;
+use super::stdlib::{boolean, expect_arity};
+use super::equality::{is_eq, is_eqv, is_equal, hash_value};
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::rc::Rc;
+
+type Bucket = Vec<(Rc<Value>, Rc<Value>)>;
+
+// entries are bucketed by hash, then compared with `equal?` or `eqv?`
+#[derive(Debug)]
+pub struct HashTable {
+    structural: bool,
+    buckets: std::collections::HashMap<u64, Bucket>,
+    count: usize
+}
+
+impl HashTable {
+    fn new(structural: bool) -> Self {
+        HashTable {
+            structural,
+            buckets: std::collections::HashMap::new(),
+            count: 0
+        }
+    }
+    fn hash(&self, key: &Rc<Value>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_value(key, self.structural, &mut hasher);
+        hasher.finish()
+    }
+    fn same_key(&self, a: &Rc<Value>, b: &Rc<Value>) -> bool {
+        if self.structural {
+            is_equal(a, b)
+        } else {
+            is_eqv(a, b)
+        }
+    }
+    // where `key` sits in the bucket for `hash`, if it's present
+    fn position(&self, hash: u64, key: &Rc<Value>) -> Option<usize> {
+        self.buckets.get(&hash)
+            .and_then(|bucket| bucket.iter().position(|(k, _)| self.same_key(k, key)))
+    }
+    fn get(&self, key: &Rc<Value>) -> Option<Rc<Value>> {
+        let hash = self.hash(key);
+        self.position(hash, key).map(|i| self.buckets[&hash][i].1.clone())
+    }
+    fn set(&mut self, key: Rc<Value>, value: Rc<Value>) {
+        let hash = self.hash(&key);
+        match self.position(hash, &key) {
+            Some(i) => self.buckets.get_mut(&hash).unwrap()[i].1 = value,
+            None => {
+                self.buckets.entry(hash).or_default().push((key, value));
+                self.count += 1;
+            }
+        }
+    }
+    fn delete(&mut self, key: &Rc<Value>) {
+        let hash = self.hash(key);
+        if let Some(i) = self.position(hash, key) {
+            let bucket = self.buckets.get_mut(&hash).unwrap();
+            bucket.remove(i);
+            if bucket.is_empty() {
+                self.buckets.remove(&hash);
+            }
+            self.count -= 1;
+        }
+    }
+    fn entries(&self) -> Vec<(Rc<Value>, Rc<Value>)> {
+        self.buckets.values().flat_map(|bucket| bucket.iter().cloned()).collect()
+    }
+}
+
+fn expect_table(name: &str, val: &Value) -> Result<Rc<RefCell<HashTable>>, RuntimeError> {
+    match val {
+        Value::HashTable(table) => Ok(table.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a hash table, got {}", name, val)))
+    }
+}
+
+// (make-hash-table [equal?]) compares keys with `equal?` unless given `eq?` or `eqv?`, which both compare with `eqv?`
+fn fn_make_hash_table(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("make-hash-table", &args, 0, 1)?;
+    let structural = match args.first() {
+        None => true,
+        Some(cmp) if is_eq(cmp, &env.lookup(&String::from("equal?"))) => true,
+        Some(cmp) if is_eq(cmp, &env.lookup(&String::from("eq?"))) => false,
+        Some(cmp) if is_eq(cmp, &env.lookup(&String::from("eqv?"))) => false,
+        Some(cmp) => return Err(RuntimeError::TypeError(format!("make-hash-table expects equal?, eqv? or eq?, got {}", cmp)))
+    };
+    Ok((env, Rc::new(Value::HashTable(Rc::new(RefCell::new(HashTable::new(structural)))))))
+}
+
+fn fn_hash_table_set(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-set!", &args, 3, 3)?;
+    let table = expect_table("hash-table-set!", &args[0])?;
+    table.borrow_mut().set(args[1].clone(), args[2].clone());
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+// (hash-table-ref table key [thunk]) calls the thunk when the key is missing
+fn fn_hash_table_ref(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("hash-table-ref", &args, 2, 3)?;
+    let table = expect_table("hash-table-ref", &args[0])?;
+    let found = table.borrow().get(&args[1]);
+    match (found, args.get(2)) {
+        (Some(val), _) => machine.ret(val, env),
+        (None, Some(thunk)) => machine.tail_apply(thunk.clone(), Vec::new(), env),
+        (None, None) => Err(missing_key("hash-table-ref", &args[1]))
+    }
+}
+
+fn missing_key(name: &str, key: &Value) -> RuntimeError {
+    RuntimeError::RangeError(format!("{}: no value for key {}", name, key))
+}
+
+fn fn_hash_table_delete(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-delete!", &args, 2, 2)?;
+    let table = expect_table("hash-table-delete!", &args[0])?;
+    table.borrow_mut().delete(&args[1]);
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_hash_table_contains(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-contains?", &args, 2, 2)?;
+    let table = expect_table("hash-table-contains?", &args[0])?;
+    let found = table.borrow().get(&args[1]).is_some();
+    let res = boolean(&env, found);
+    Ok((env, res))
+}
+
+fn fn_hash_table_keys(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-keys", &args, 1, 1)?;
+    let table = expect_table("hash-table-keys", &args[0])?;
+    let keys = table.borrow().entries().into_iter().map(|(k, _)| k).collect();
+    Ok((env, Value::list(keys)))
+}
+
+fn fn_hash_table_values(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-values", &args, 1, 1)?;
+    let table = expect_table("hash-table-values", &args[0])?;
+    let values = table.borrow().entries().into_iter().map(|(_, v)| v).collect();
+    Ok((env, Value::list(values)))
+}
+
+fn fn_hash_table_to_alist(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table->alist", &args, 1, 1)?;
+    let table = expect_table("hash-table->alist", &args[0])?;
+    let pairs = table.borrow().entries().into_iter().map(|(k, v)| Rc::new(Value::Pair(k, v))).collect();
+    Ok((env, Value::list(pairs)))
+}
+
+// (hash-table-update! table key proc [thunk]) stores (proc current), using the thunk when missing
+fn fn_hash_table_update(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("hash-table-update!", &args, 3, 4)?;
+    let table = expect_table("hash-table-update!", &args[0])?;
+    let found = table.borrow().get(&args[1]);
+    let (key, proc) = (args[1].clone(), args[2].clone());
+    match (found, args.get(3)) {
+        (Some(current), _) => update(machine, table, key, proc, current, env),
+        (None, Some(thunk)) => machine.call_then(thunk.clone(), Vec::new(), env, move |machine, current, env| {
+            update(machine, table.clone(), key.clone(), proc.clone(), current, env)
+        }),
+        (None, None) => Err(missing_key("hash-table-update!", &key))
+    }
+}
+
+// stores what `proc` makes of the key's current value
+fn update(machine: &mut Machine, table: Rc<RefCell<HashTable>>, key: Rc<Value>, proc: Rc<Value>, current: Rc<Value>, env: Rc<Env>) -> StepResult {
+    machine.call_then(proc, vec![current], env, move |machine, updated, env| {
+        table.borrow_mut().set(key.clone(), updated);
+        machine.ret(Rc::new(Value::Nil), env)
+    })
+}
+
+fn fn_hash_table_count(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("hash-table-count", &args, 1, 1)?;
+    let table = expect_table("hash-table-count", &args[0])?;
+    let count = table.borrow().count;
+    Ok((env, Rc::new(Value::Int(count as i32))))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("make-hash-table"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_make_hash_table))));
+    table.insert(String::from("hash-table-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_set))));
+    table.insert(String::from("hash-table-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_hash_table_ref))));
+    table.insert(String::from("hash-table-delete!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_delete))));
+    table.insert(String::from("hash-table-contains?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_contains))));
+    table.insert(String::from("hash-table-keys"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_keys))));
+    table.insert(String::from("hash-table-values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_values))));
+    table.insert(String::from("hash-table->alist"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_to_alist))));
+    table.insert(String::from("hash-table-update!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_hash_table_update))));
+    table.insert(String::from("hash-table-count"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_table_count))));
+}
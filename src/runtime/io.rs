@@ -0,0 +1,111 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_arity, expect_str, expect_int};
+use super::chars::expect_char;
+use super::ports::{Input, port_arg, current_input, current_output};
+
+use std::rc::Rc;
+
+fn eof_or(val: Option<Rc<Value>>) -> Rc<Value> {
+    val.unwrap_or_else(|| Rc::new(Value::Eof))
+}
+
+// writes `text` to the optional port argument at `k`, or the current output port
+fn output(name: &str, args: &[Rc<Value>], k: usize, text: &str) -> Result<(), RuntimeError> {
+    port_arg(name, args, k, current_output)?.borrow_mut().write_text(name, text)
+}
+
+// runs `read` on the textual input port at `k`, or the current input port
+fn input<T>(name: &str, args: &[Rc<Value>], k: usize, read: impl FnOnce(&mut Input) -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+    let port = port_arg(name, args, k, current_input)?;
+    let mut port = port.borrow_mut();
+    read(port.text_input(name)?)
+}
+
+fn fn_display(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("display", &args, 1, 2)?;
+    output("display", &args, 1, &format!("{:#}", args[0]))?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_write(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("write", &args, 1, 2)?;
+    output("write", &args, 1, &format!("{}", args[0]))?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_newline(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("newline", &args, 0, 1)?;
+    output("newline", &args, 0, "\n")?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_write_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("write-string", &args, 1, 2)?;
+    output("write-string", &args, 1, expect_str("write-string", &args[0])?)?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_write_char(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("write-char", &args, 1, 2)?;
+    output("write-char", &args, 1, &expect_char("write-char", &args[0])?.to_string())?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_read_line(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read-line", &args, 0, 1)?;
+    let line = input("read-line", &args, 0, |input| input.read_line())?;
+    Ok((env, eof_or(line.map(|line| Rc::new(Value::Str(line))))))
+}
+
+fn fn_read_char(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read-char", &args, 0, 1)?;
+    let c = input("read-char", &args, 0, |input| input.read_char())?;
+    Ok((env, eof_or(c.map(|c| Rc::new(Value::Char(c))))))
+}
+
+fn fn_peek_char(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("peek-char", &args, 0, 1)?;
+    let c = input("peek-char", &args, 0, |input| input.peek_char())?;
+    Ok((env, eof_or(c.map(|c| Rc::new(Value::Char(c))))))
+}
+
+fn fn_read_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read-string", &args, 1, 2)?;
+    let k = expect_int("read-string", &args[0])?.max(0) as usize;
+    let s = input("read-string", &args, 1, |input| input.read_string(k))?;
+    Ok((env, eof_or(s.map(|s| Rc::new(Value::Str(s))))))
+}
+
+fn fn_read(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read", &args, 0, 1)?;
+    let val = input("read", &args, 0, |input| input.read())?;
+    Ok((env, eof_or(val)))
+}
+
+fn fn_eof_object(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("eof-object", &args, 0, 0)?;
+    Ok((env, Rc::new(Value::Eof)))
+}
+
+fn fn_is_eof_object(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("eof-object?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Eof));
+    Ok((env, res))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("display"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_display))));
+    table.insert(String::from("write"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_write))));
+    table.insert(String::from("newline"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_newline))));
+    table.insert(String::from("write-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_write_string))));
+    table.insert(String::from("write-char"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_write_char))));
+    table.insert(String::from("read-line"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read_line))));
+    table.insert(String::from("read-char"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read_char))));
+    table.insert(String::from("peek-char"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_peek_char))));
+    table.insert(String::from("read-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read_string))));
+    table.insert(String::from("read"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read))));
+    table.insert(String::from("eof-object"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_eof_object))));
+    table.insert(String::from("eof-object?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_eof_object))));
+}
@@ -0,0 +1,1173 @@
+extern crate im;
+use im::hashmap::HashMap;
+
+use super::super::parser::expressions::{Expression, Expression::*};
+use super::super::parser::main::SyntaxError;
+use super::main::{Env, Value, LambdaFunction, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::equality::is_equal;
+use super::exceptions::{error_value, non_continuable};
+use super::persistent;
+use super::stdlib::{expect_arity, is_true, is_false};
+use super::compiler::{self, Code, Form, Op, Site};
+use super::values::{self, Formals};
+use super::promises::{Promise, PromiseState};
+use super::parameters::Parameter;
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+// Evaluation runs on an explicit stack of frames rather than the Rust stack. The frames form
+// a linked list in which a captured continuation shares its tail with the running machine, so
+// call/cc only has to keep hold of the top frame, and resuming a continuation any number of
+// times is just making its frames the current ones again. The dynamic state lives in the frames
+// too, as the winds, handlers, guards and prompts whose extent the running code is in, so it's
+// always that of whichever frames are current.
+//
+// Expressions run as the code the compiler makes of them, each call between procedures leaving a
+// frame holding the caller's activation for the callee to return to.
+
+type Frames = Option<Rc<Frame>>;
+
+pub struct Frame {
+    kind: FrameKind,
+    // how many frames there are from this one down, for finding where two stacks meet
+    depth: usize,
+    next: Frames
+}
+
+// dropping a long chain of frames one at a time, so deep recursion can't overflow the stack
+impl Drop for Frame {
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(frame) = next {
+            match Rc::try_unwrap(frame) {
+                Ok(mut frame) => next = frame.next.take(),
+                Err(_) => break
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum FrameKind {
+    // the bottom of a run, handing its value back to the Rust code which started it. A run started
+    // by a builtin sits on the frames of the run which called the builtin, so the dynamic state
+    // carries on into it, and `base` is the env the run started from
+    Halt { base: Rc<Env> },
+    // a procedure's value goes back to the env its call was evaluated in
+    RestoreEnv(Rc<Env>),
+    // compiled code waiting on a call. It carries on in its own env when a procedure returns to
+    // it, and in the env it's returned with when a builtin or special form does, if `defines`
+    // is set, as that's where the names they define are
+    Code { act: Activation, defines: bool },
+    // the rest of a body, from part `index` of the form
+    Sequence { form: Rc<Form>, index: usize },
+    // the thunk of a with-exception-handler is running
+    Handler(Rc<Value>),
+    // a handler called by `raise` is running, so it and any handlers inside it are skipped, the
+    // handler's frame being `skip` frames below this one
+    RaiseReturn { obj: Rc<Value>, continuable: bool, skip: usize, env: Rc<Env> },
+    // the body of a guard is running
+    Guard { form: Rc<Form>, env: Rc<Env> },
+    // the test of clause `index` of a guard is being evaluated
+    GuardClause { form: Rc<Form>, index: usize, env: Rc<Env>, obj: Rc<Value> },
+    // the receiver of a `=>` clause is being evaluated
+    GuardReceiver(Rc<Value>),
+    // the phases of dynamic-wind: the before thunk, the body, and the after thunk
+    WindBefore { before: Rc<Value>, thunk: Rc<Value>, after: Rc<Value>, env: Rc<Env> },
+    Wind { before: Rc<Value>, after: Rc<Value>, env: Rc<Env> },
+    WindAfter { value: Rc<Value>, env: Rc<Env> },
+    // the thunk of a call-with-prompt or the body of a reset is running; a reset has no handler
+    Prompt { tag: Rc<Value>, handler: Option<Rc<Value>>, env: Rc<Env> },
+    // an after thunk is running on the way to somewhere else
+    Transfer(Rc<Transfer>),
+    // a before thunk is running on the way into `wind`
+    Rewind { wind: Rc<Frame>, transfer: Rc<Transfer> },
+    // a before thunk is running while the frames of a delimited continuation are put back, up
+    // to `index`
+    Reinstate { segment: Rc<Vec<FrameKind>>, index: usize, value: Rc<Value>, env: Rc<Env> },
+    // an escape continuation stops working when its call/ec returns
+    EscapeEnd(Rc<Cell<bool>>),
+    // a builtin carrying on in Rust once a procedure it called returns
+    Then(Then),
+    // the producer of a call-with-values is running
+    Consumer { consumer: Rc<Value>, env: Rc<Env> },
+    // the producer of `(receive formals producer body ...)` is being evaluated
+    Receive { formals: Rc<Formals>, form: Rc<Form>, env: Rc<Env> },
+    // the init of binding `index` of a let-values is being evaluated, `scope` holding the names
+    // bound so far
+    LetValues { bindings: Rc<Vec<(Formals, Rc<Form>)>>, form: Rc<Form>, index: usize, sequential: bool, scope: Rc<Env>, env: Rc<Env> },
+    DefineValues(Rc<Formals>),
+    // the expression of a promise is being evaluated
+    Force(Rc<Promise>),
+    // the converter of a make-parameter is running
+    MakeParameter(Rc<Value>),
+    // the params and values of a parameterize are being evaluated, `done` so far
+    ParameterizeInits { form: Rc<Form>, inits: Rc<Vec<Rc<Form>>>, done: Vec<Rc<Value>>, env: Rc<Env> },
+    // the converter of param `converted.len()` is running
+    Convert { form: Rc<Form>, params: Rc<Vec<Rc<Parameter>>>, raw: Rc<Vec<Rc<Value>>>, converted: Vec<Rc<Value>>, env: Rc<Env> },
+    // the body of a parameterize is running
+    Parameterize { bindings: Rc<Vec<(Rc<Parameter>, Rc<Value>)>>, env: Rc<Env> }
+}
+
+type Then = Rc<dyn Fn(&mut Machine, Rc<Value>, Rc<Env>) -> StepResult>;
+
+// a non-local exit, which runs the after and before thunks between here and where it's going
+pub enum Transfer {
+    Resume(Rc<Continuation>, Rc<Value>),
+    // to a guard, with the raised object
+    Catch { guard: Rc<Frame>, obj: Rc<Value> },
+    // to a prompt, with the continuation up to it and the values for its handler
+    Abort { prompt: Rc<Frame>, k: Rc<Value>, args: Vec<Rc<Value>> },
+    // to a reset, with the continuation up to it for the body of a shift
+    Shift { prompt: Rc<Frame>, k: Rc<Value>, body: Then },
+    Exit(i32)
+}
+
+impl fmt::Debug for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transfer::Resume(_, val) => write!(f, "Resume({})", val),
+            Transfer::Catch { obj, .. } => write!(f, "Catch({})", obj),
+            Transfer::Abort { .. } => write!(f, "Abort"),
+            Transfer::Shift { .. } => write!(f, "Shift"),
+            Transfer::Exit(status) => write!(f, "Exit({})", status)
+        }
+    }
+}
+
+impl Transfer {
+    // the frames it ends up on
+    fn target(&self) -> Frames {
+        match self {
+            Transfer::Resume(k, _) => k.frames.clone(),
+            Transfer::Catch { guard: frame, .. } | Transfer::Abort { prompt: frame, .. } | Transfer::Shift { prompt: frame, .. } =>
+                frame.next.clone(),
+            Transfer::Exit(_) => None
+        }
+    }
+}
+
+pub struct Continuation {
+    frames: Frames,
+    env: Rc<Env>,
+    // set for escape continuations, which only work until their call/ec returns
+    live: Option<Rc<Cell<bool>>>,
+    // for delimited continuations, the prompt they reach down to
+    prompt: Option<Rc<Frame>>
+}
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.prompt.is_some() {
+            write!(f, "Continuation(delimited)")
+        } else {
+            write!(f, "Continuation")
+        }
+    }
+}
+
+thread_local! {
+    // while a builtin runs, the frames of the run which called it
+    static CALLER_FRAMES: RefCell<Frames> = const { RefCell::new(None) };
+    // the tag reset and shift use
+    static RESET_TAG: Rc<Value> = Rc::new(Value::Symbol(String::from("%reset")));
+}
+
+fn depth(frames: &Frames) -> usize {
+    frames.as_ref().map_or(0, |frame| frame.depth)
+}
+
+fn same(a: &Frames, b: &Frames) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false
+    }
+}
+
+fn nth_next(frames: &Frames, n: usize) -> Frames {
+    let mut runner = frames.clone();
+    for _ in 0..n {
+        runner = runner.and_then(|frame| frame.next.clone());
+    }
+    runner
+}
+
+// the frames two stacks have in common
+fn common_ancestor(a: &Frames, b: &Frames) -> Frames {
+    let mut a = nth_next(a, depth(a).saturating_sub(depth(b)));
+    let mut b = nth_next(b, depth(b).saturating_sub(depth(&a)));
+    while !same(&a, &b) {
+        a = a.and_then(|frame| frame.next.clone());
+        b = b.and_then(|frame| frame.next.clone());
+    }
+    a
+}
+
+// the innermost handler or guard frame installed
+fn current_handler(frames: &Frames) -> Option<Rc<Frame>> {
+    let mut runner = frames.clone();
+    while let Some(frame) = runner {
+        runner = match &frame.kind {
+            FrameKind::Handler(_) | FrameKind::Guard { .. } => return Some(frame),
+            // while a handler runs, only the handlers outside it are installed
+            FrameKind::RaiseReturn { skip, .. } => nth_next(&frame.next, *skip),
+            _ => frame.next.clone()
+        };
+    }
+    None
+}
+
+enum NoPrompt {
+    Missing,
+    // the frames of a builtin's caller can't be captured as part of a delimited continuation
+    OutsideBuiltin
+}
+
+// the innermost prompt with `tag` in this run
+fn find_prompt(frames: &Frames, tag: &Rc<Value>) -> Result<Rc<Frame>, NoPrompt> {
+    let mut runner = frames.clone();
+    let mut crossed = false;
+    while let Some(frame) = runner {
+        match &frame.kind {
+            FrameKind::Prompt { tag: prompt_tag, .. } if is_equal(prompt_tag, tag) =>
+                return if crossed { Err(NoPrompt::OutsideBuiltin) } else { Ok(frame) },
+            FrameKind::Halt { .. } => crossed = true,
+            _ => {}
+        }
+        runner = frame.next.clone();
+    }
+    Err(NoPrompt::Missing)
+}
+
+// the value `param` has in the extent of `frames`
+fn parameter_value(frames: &Frames, param: &Rc<Parameter>) -> Rc<Value> {
+    let mut runner = frames.clone();
+    while let Some(frame) = runner {
+        if let FrameKind::Parameterize { bindings, .. } = &frame.kind {
+            if let Some((_, val)) = bindings.iter().find(|(bound, _)| Rc::ptr_eq(bound, param)) {
+                return val.clone();
+            }
+        }
+        runner = frame.next.clone();
+    }
+    param.value().clone()
+}
+
+// the value of `param` for a builtin, which sees the parameterizes of the run that called it
+pub fn current_value(param: &Rc<Parameter>) -> Rc<Value> {
+    CALLER_FRAMES.with(|frames| parameter_value(&frames.borrow(), param))
+}
+
+// the operands of an s-expression, or the elements of a literal
+fn children(expr: &Expression) -> &[Rc<Expression>] {
+    match expr {
+        SExpr(_, rands) => rands,
+        ListExpr(items) | VectorExpr(items) | MapExpr(items) | SetExpr(items) => items,
+        _ => &[]
+    }
+}
+
+// a new copy of a constant, for those which are new objects each time they're evaluated
+fn fresh(val: &Value) -> Rc<Value> {
+    match val {
+        Value::Bytevector(bytes) => Rc::new(Value::Bytevector(Rc::new(RefCell::new(bytes.borrow().clone())))),
+        _ => Rc::new(val.clone())
+    }
+}
+
+// the value of a list, vector, map or set literal once its elements are evaluated
+fn literal(expr: &Expression, items: Vec<Rc<Value>>) -> Rc<Value> {
+    match expr {
+        VectorExpr(_) => Rc::new(Value::Vector(Rc::new(RefCell::new(items)))),
+        MapExpr(_) => persistent::hash_map(items),
+        SetExpr(_) => persistent::hash_set(items),
+        _ => Value::list(items)
+    }
+}
+
+// the clauses of `(guard (var clause ...) body ...)`
+fn guard_clauses(form: &Form) -> &[Rc<Form>] {
+    match form.parts.get(1) {
+        Some(spec) if !spec.parts.is_empty() => &spec.parts[1..],
+        _ => &[]
+    }
+}
+
+fn guard_var(form: &Form) -> Option<&String> {
+    match form.parts.get(1).and_then(|spec| spec.parts.first()).map(|var| &*var.expr) {
+        Some(LookupExpr(var)) => Some(var),
+        _ => None
+    }
+}
+
+// compiled code being run
+#[derive(Clone)]
+struct Activation {
+    code: Rc<Code>,
+    pc: usize,
+    locals: Vec<Option<Rc<Value>>>,
+    stack: Vec<Rc<Value>>,
+    // where to carry on from as each block being run returns
+    returns: Vec<usize>,
+    env: Rc<Env>,
+    // a procedure body, whose env goes no further than the procedure
+    procedure: bool
+}
+
+impl Activation {
+    // runs `code` from `entry`, in vectors left by an activation which has finished if there are any
+    fn new(code: Rc<Code>, entry: usize, env: Rc<Env>, spare: Option<Spare>) -> Self {
+        let (mut locals, stack, returns) = spare.unwrap_or_default();
+        locals.resize(code.slots.len(), None);
+        Activation {
+            code,
+            pc: entry,
+            locals,
+            stack,
+            returns,
+            env,
+            procedure: false
+        }
+    }
+    // whether there's nothing left to do but return, so a call can be a tail call
+    fn returning(&self) -> bool {
+        self.returns.is_empty() && matches!(self.code.ops[self.pc], Op::Return)
+    }
+    // the env a syntax builtin at `site` runs in: the code's own, with the variables it uses
+    fn syntax_env(&self, site: &Site) -> Rc<Env> {
+        let bindings: HashMap<String, Rc<Value>> = site.uses.iter()
+            .filter_map(|slot| self.locals[*slot].as_ref().map(|val| (self.code.slots[*slot].clone(), val.clone())))
+            .collect();
+        if bindings.is_empty() {
+            self.env.clone()
+        } else {
+            Rc::new(self.env.subenv(bindings))
+        }
+    }
+}
+
+// the emptied vectors of an activation
+type Spare = (Vec<Option<Rc<Value>>>, Vec<Rc<Value>>, Vec<usize>);
+
+enum State {
+    Run(Activation),
+    Apply(Rc<Value>, Vec<Rc<Value>>, Rc<Env>),
+    Return(Rc<Value>, Rc<Env>)
+}
+
+impl State {
+    fn env(&self) -> Rc<Env> {
+        match self {
+            State::Run(act) => act.env.clone(),
+            State::Apply(_, _, env) | State::Return(_, env) => env.clone()
+        }
+    }
+}
+
+pub struct Machine {
+    state: State,
+    frames: Frames,
+    base: Rc<Env>,
+    // what's left of the activations which have finished, so a call needn't allocate
+    spare: Vec<Spare>
+}
+
+pub fn eval(env: Rc<Env>, expr: Rc<Expression>) -> EvalResult {
+    let code = Rc::new(compiler::compile(&expr, &env));
+    let entry = code.entry;
+    Machine::run(State::Run(Activation::new(code, entry, env, None)))
+}
+
+impl Machine {
+    fn run(state: State) -> EvalResult {
+        let base = state.env();
+        let caller = CALLER_FRAMES.with(|frames| frames.borrow().clone());
+        let halt = Frame { kind: FrameKind::Halt { base: base.clone() }, depth: depth(&caller) + 1, next: caller };
+        let mut machine = Machine {
+            state,
+            frames: Some(Rc::new(halt)),
+            base,
+            spare: Vec::new()
+        };
+        machine.execute()
+    }
+    fn execute(&mut self) -> EvalResult {
+        loop {
+            let env = self.state.env();
+            let step = match std::mem::replace(&mut self.state, State::Return(Rc::new(Value::Nil), env.clone())) {
+                State::Run(act) => self.run_code(act),
+                State::Apply(func, args, env) => self.apply(func, args, env),
+                State::Return(val, env) => match self.pop() {
+                    // after resuming an earlier top-level continuation, what it defined goes on
+                    // top of what's been defined since
+                    FrameKind::Halt { base } if !Rc::ptr_eq(&base, &self.base) =>
+                        return Ok((Rc::new(env.rebase(&base, &self.base)), val)),
+                    FrameKind::Halt { .. } => return Ok((env, val)),
+                    frame => self.resume(frame, val, env)
+                }
+            };
+            if let Err(err) = step {
+                self.signal(err, env)?;
+            }
+        }
+    }
+    fn push(&mut self, kind: FrameKind) {
+        let next = self.frames.take();
+        self.frames = Some(Rc::new(Frame { kind, depth: depth(&next) + 1, next }));
+    }
+    fn pop(&mut self) -> FrameKind {
+        let frame = self.frames.take().expect("every run ends in a halt frame");
+        match Rc::try_unwrap(frame) {
+            Ok(mut frame) => {
+                self.frames = frame.next.take();
+                std::mem::replace(&mut frame.kind, FrameKind::RestoreEnv(self.base.clone()))
+            },
+            Err(shared) => {
+                self.frames = shared.next.clone();
+                shared.kind.clone()
+            }
+        }
+    }
+    // returns `val` to the frame on top, as special forms which don't evaluate anything do
+    pub fn ret(&mut self, val: Rc<Value>, env: Rc<Env>) -> StepResult {
+        self.state = State::Return(val, env);
+        Ok(())
+    }
+    // calls `func`, then hands its value to `then` to carry on with. `then` runs again each time
+    // a continuation captured inside the call is resumed, so it mustn't change what it captures
+    pub fn call_then(&mut self, func: Rc<Value>, args: Vec<Rc<Value>>, env: Rc<Env>,
+                     then: impl Fn(&mut Machine, Rc<Value>, Rc<Env>) -> StepResult + 'static) -> StepResult {
+        self.push(FrameKind::Then(Rc::new(then)));
+        self.apply(func, args, env)
+    }
+    // like call_then, with each param parameterized to its value while `func` runs. The values
+    // are taken as they are, without going through the params' converters
+    pub fn call_parameterized(&mut self, bindings: Vec<(Rc<Parameter>, Rc<Value>)>, func: Rc<Value>, args: Vec<Rc<Value>>, env: Rc<Env>,
+                              then: impl Fn(&mut Machine, Rc<Value>, Rc<Env>) -> StepResult + 'static) -> StepResult {
+        self.push(FrameKind::Then(Rc::new(then)));
+        self.push(FrameKind::Parameterize { bindings: Rc::new(bindings), env: env.clone() });
+        self.apply(func, args, env)
+    }
+    pub fn tail_apply(&mut self, func: Rc<Value>, args: Vec<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        self.state = State::Apply(func, args, env);
+        Ok(())
+    }
+    // a tail call returns straight to a caller already waiting to restore its env
+    fn restore_env(&mut self, env: Rc<Env>) {
+        if !matches!(self.frames.as_deref(), Some(Frame { kind: FrameKind::RestoreEnv(_) | FrameKind::Code { defines: false, .. }, .. })) {
+            self.push(FrameKind::RestoreEnv(env));
+        }
+    }
+    // evaluates a part of a syntax builtin's form
+    fn eval(&mut self, form: &Form, env: Rc<Env>) -> StepResult {
+        let entry = form.entry.expect("only the parts of a form are evaluated");
+        self.state = State::Run(Activation::new(form.code.clone(), entry, env, self.spare.pop()));
+        Ok(())
+    }
+    // evaluates the parts of `form` from `index`, the last in tail position
+    fn sequence(&mut self, form: Rc<Form>, index: usize, env: Rc<Env>) -> StepResult {
+        let next = match form.parts.get(index) {
+            Some(next) => next.clone(),
+            None => return self.ret(Rc::new(Value::Nil), env)
+        };
+        if index + 1 < form.parts.len() {
+            self.push(FrameKind::Sequence { form, index: index + 1 });
+        }
+        self.eval(&next, env)
+    }
+    // runs a builtin, with this run's frames underneath any run it starts
+    fn with_caller_frames(&self, call: impl FnOnce() -> EvalResult) -> EvalResult {
+        let outer = CALLER_FRAMES.with(|frames| frames.replace(self.frames.clone()));
+        let res = call();
+        CALLER_FRAMES.with(|frames| frames.replace(outer));
+        res
+    }
+    fn call_builtin(&mut self, call: impl FnOnce() -> EvalResult) -> StepResult {
+        let (env, val) = self.with_caller_frames(call)?;
+        self.ret(val, env)
+    }
+    // the body of `lambda`, which is `func`, called with `args`
+    fn call(&mut self, func: &Rc<Value>, lambda: &LambdaFunction, args: &[Rc<Value>]) -> Result<Activation, RuntimeError> {
+        let proto = lambda.proto();
+        if lambda.checks_arity() {
+            let name = lambda.name().map_or("procedure", |name| name.as_str());
+            expect_arity(name, args, proto.params.len(), proto.params.len())?;
+        }
+        let mut act = Activation::new(proto.code.clone(), proto.code.entry, lambda.env().clone(), self.spare.pop());
+        act.procedure = true;
+        for ((_, slot), val) in proto.captures.iter().zip(lambda.captures()) {
+            act.locals[*slot] = val.clone();
+        }
+        for (slot, arg) in proto.params.iter().zip(args) {
+            act.locals[*slot] = Some(arg.clone());
+        }
+        if let Some((_, slot)) = &proto.own {
+            act.locals[*slot] = Some(func.clone());
+        }
+        Ok(act)
+    }
+    // keeps the vectors of an activation which has finished, giving back its env
+    fn finish(&mut self, act: Activation) -> Rc<Env> {
+        let Activation { mut locals, mut stack, mut returns, env, .. } = act;
+        locals.clear();
+        stack.clear();
+        returns.clear();
+        self.spare.push((locals, stack, returns));
+        env
+    }
+    // runs `act` until it returns or hands over to something which needs a frame
+    fn run_code(&mut self, mut act: Activation) -> StepResult {
+        loop {
+            let op = act.code.ops[act.pc];
+            act.pc += 1;
+            match op {
+                Op::Const(i) => {
+                    let val = act.code.consts[i].clone();
+                    act.stack.push(val);
+                },
+                Op::Fresh(i) => {
+                    let val = fresh(&act.code.consts[i]);
+                    act.stack.push(val);
+                },
+                Op::Local(slot) => {
+                    let val = match &act.locals[slot] {
+                        Some(val) => val.clone(),
+                        None => act.env.lookup(&act.code.slots[slot])
+                    };
+                    act.stack.push(val);
+                },
+                Op::Global(i) => {
+                    let val = act.code.global(i, &act.env);
+                    act.stack.push(val);
+                },
+                Op::DefineLocal(slot) => {
+                    act.locals[slot] = act.stack.pop();
+                    act.stack.push(Rc::new(Value::Nil));
+                },
+                Op::DefineGlobal(i) => {
+                    let val = act.stack.pop().expect("a definition's value is on the stack");
+                    act.env = Rc::new(act.env.add_name(act.code.names[i].clone(), val));
+                    act.stack.push(Rc::new(Value::Nil));
+                },
+                Op::Closure(i) => {
+                    let proto = act.code.protos[i].clone();
+                    let captures = proto.captures.iter().map(|(outer, _)| act.locals[*outer].clone()).collect();
+                    let lambda = LambdaFunction::new(proto, captures, act.env.clone());
+                    act.stack.push(Rc::new(Value::Lambda(Rc::new(lambda))));
+                },
+                Op::Pop => {
+                    act.stack.pop();
+                },
+                Op::Gosub(pc) => {
+                    act.returns.push(act.pc);
+                    act.pc = pc;
+                },
+                Op::Return => match act.returns.pop() {
+                    Some(pc) => act.pc = pc,
+                    None => {
+                        let val = act.stack.pop().expect("code returns a value");
+                        let env = self.finish(act);
+                        if !matches!(self.frames.as_deref(), Some(Frame { kind: FrameKind::Code { .. }, .. })) {
+                            return self.ret(val, env);
+                        }
+                        match self.pop() {
+                            FrameKind::Code { act: mut caller, defines } => {
+                                if defines {
+                                    caller.env = env;
+                                }
+                                act = caller;
+                                act.stack.push(val);
+                            },
+                            _ => unreachable!("the frame on top is code")
+                        }
+                    }
+                },
+                Op::Operator(i) => {
+                    if let Some(Value::Lambda(_) | Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(_))) = act.stack.last().map(|func| &**func) {
+                        continue;
+                    }
+                    let code = act.code.clone();
+                    let site = &code.sites[i];
+                    let func = act.stack.last().expect("the operator is on the stack").clone();
+                    if is_true(&func) || is_false(&func) {
+                        act.stack.pop();
+                        act.pc = match site.branches {
+                            Some((yes, _)) if is_true(&func) => yes,
+                            Some((_, no)) => no,
+                            None => {
+                                let name = if is_true(&func) { "true" } else { "false" };
+                                return Err(RuntimeError::ArityError(format!("{} expects 2 branches", name)));
+                            }
+                        };
+                        if site.inline {
+                            act.returns.push(site.after);
+                        }
+                        continue;
+                    }
+                    match &*func {
+                        Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(internal)) => {
+                            act.stack.pop();
+                            let rands = children(&site.expr).to_vec();
+                            let env = act.syntax_env(site);
+                            let (env, val) = self.with_caller_frames(|| internal(env, rands))?;
+                            act.env = env;
+                            act.stack.push(val);
+                            act.pc = site.after;
+                        },
+                        Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(internal)) => {
+                            let form = match &site.form {
+                                Some(form) => form.clone(),
+                                None => return Err(RuntimeError::BadRator(format!("{} wasn't syntax when {} was compiled", func, site.expr)))
+                            };
+                            act.stack.pop();
+                            let env = act.syntax_env(site);
+                            act.pc = site.after;
+                            if !act.returning() {
+                                self.push(FrameKind::Code { act, defines: true });
+                            }
+                            return internal(self, env, form);
+                        },
+                        Value::Lambda(_) | Value::RuntimeFunction(_) | Value::Continuation(_) | Value::Parameter(_) => {},
+                        _ => match &*site.expr {
+                            SExpr(rator, _) => return Err(RuntimeError::BadRator(format!("{} ({})", func, rator))),
+                            _ => unreachable!("only s-expressions have operators")
+                        }
+                    }
+                },
+                Op::Call(argc) => {
+                    let base = act.stack.len() - argc;
+                    let func = act.stack[base - 1].clone();
+                    if let Value::Lambda(lambda) = &*func {
+                        let callee = self.call(&func, lambda, &act.stack[base..])?;
+                        act.stack.truncate(base - 1);
+                        if !act.returning() {
+                            self.push(FrameKind::Code { act, defines: false });
+                        } else if act.procedure {
+                            self.finish(act);
+                        } else {
+                            let env = self.finish(act);
+                            self.restore_env(env);
+                        }
+                        act = callee;
+                        continue;
+                    }
+                    let args = act.stack.split_off(base);
+                    act.stack.pop();
+                    match &*func {
+                        Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(internal)) => {
+                            let env = act.env.clone();
+                            let (env, val) = self.with_caller_frames(|| internal(env, args))?;
+                            act.env = env;
+                            act.stack.push(val);
+                        },
+                        _ => {
+                            let env = act.env.clone();
+                            if !act.returning() {
+                                self.push(FrameKind::Code { act, defines: true });
+                            }
+                            return self.apply(func, args, env);
+                        }
+                    }
+                },
+                Op::Literal(i) => {
+                    let expr = &act.code.sites[i].expr;
+                    let items = act.stack.split_off(act.stack.len() - children(expr).len());
+                    let val = literal(expr, items);
+                    act.stack.push(val);
+                },
+                Op::BadRator(i) => match &*act.code.sites[i].expr {
+                    SExpr(rator, _) => return Err(RuntimeError::BadRator(format!("cannot evaluate rator for s-expr {}", rator))),
+                    _ => unreachable!("only s-expressions have operators")
+                }
+            }
+        }
+    }
+    fn apply(&mut self, func: Rc<Value>, args: Vec<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        match &*func {
+            Value::Lambda(lambda) => {
+                let act = self.call(&func, lambda, &args)?;
+                self.restore_env(env);
+                self.state = State::Run(act);
+                Ok(())
+            },
+            Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(internal)) => self.call_builtin(|| internal(env, args)),
+            Value::RuntimeFunction(RuntimeFunctionWrapper::Control(internal)) => internal(self, env, args),
+            Value::Parameter(param) if args.is_empty() => {
+                let val = parameter_value(&self.frames, param);
+                self.ret(val, env)
+            },
+            Value::Parameter(_) => Err(RuntimeError::ArityError(format!("a parameter expects no arguments, got {}", args.len()))),
+            Value::Continuation(k) => {
+                let val = values::values(args);
+                match &k.prompt {
+                    None => self.transfer(Rc::new(Transfer::Resume(k.clone(), val))),
+                    Some(prompt) => {
+                        // a delimited continuation is called like a procedure, its frames going on
+                        // top of the caller's
+                        let mut segment = Vec::new();
+                        let mut runner = k.frames.clone();
+                        while let Some(frame) = runner {
+                            segment.push(frame.kind.clone());
+                            if Rc::ptr_eq(&frame, prompt) {
+                                break;
+                            }
+                            runner = frame.next.clone();
+                        }
+                        self.restore_env(env);
+                        let index = segment.len();
+                        self.reinstate(Rc::new(segment), index, val, k.env.clone())
+                    }
+                }
+            },
+            _ => Err(RuntimeError::BadRator(format!("cannot apply {} to evaluated arguments", func)))
+        }
+    }
+    // carries on from `frame` with the value of what it was waiting on
+    fn resume(&mut self, frame: FrameKind, val: Rc<Value>, env: Rc<Env>) -> StepResult {
+        match frame {
+            FrameKind::Halt { .. } => unreachable!(),
+            FrameKind::RestoreEnv(caller_env) => self.ret(val, caller_env),
+            FrameKind::Code { mut act, defines } => {
+                if defines {
+                    act.env = env;
+                }
+                act.stack.push(val);
+                self.run_code(act)
+            },
+            FrameKind::Sequence { form, index } => self.sequence(form, index, env),
+            FrameKind::Handler(_) => self.ret(val, env),
+            FrameKind::RaiseReturn { obj, continuable, skip, env: raise_env } => {
+                if continuable {
+                    return self.ret(val, raise_env);
+                }
+                // the handler returned, so the error is raised again to the handlers outside it
+                let outside = nth_next(&self.frames, skip);
+                self.raise_from(outside, non_continuable(obj), false, raise_env)
+            },
+            FrameKind::Guard { env: guard_env, .. } => self.ret(val, guard_env),
+            FrameKind::GuardClause { form, index, env: clause_env, obj } => {
+                if is_false(&val) {
+                    return self.guard_clause(form, index + 1, clause_env, obj);
+                }
+                let clause = guard_clauses(&form)[index].clone();
+                match &clause.parts[1..] {
+                    [] => self.ret(val, clause_env),
+                    [arrow, receiver] if matches!(&*arrow.expr, LookupExpr(name) if name == "=>") => {
+                        let receiver = receiver.clone();
+                        self.push(FrameKind::GuardReceiver(val));
+                        self.eval(&receiver, clause_env)
+                    },
+                    _ => self.sequence(clause, 1, clause_env)
+                }
+            },
+            FrameKind::GuardReceiver(tested) => self.tail_apply(val, vec![tested], env),
+            FrameKind::WindBefore { before, thunk, after, env: wind_env } => {
+                self.push(FrameKind::Wind { before, after, env: wind_env.clone() });
+                self.tail_apply(thunk, Vec::new(), wind_env)
+            },
+            FrameKind::Wind { after, env: wind_env, .. } => {
+                self.push(FrameKind::WindAfter { value: val, env: wind_env.clone() });
+                self.tail_apply(after, Vec::new(), wind_env)
+            },
+            FrameKind::WindAfter { value, env: wind_env } => self.ret(value, wind_env),
+            FrameKind::Prompt { env: prompt_env, .. } => self.ret(val, prompt_env),
+            FrameKind::Transfer(transfer) => self.transfer(transfer),
+            FrameKind::Rewind { wind, transfer } => {
+                self.frames = Some(wind);
+                self.transfer(transfer)
+            },
+            FrameKind::Reinstate { segment, index, value, env: resume_env } => {
+                self.push(segment[index].clone());
+                self.reinstate(segment, index, value, resume_env)
+            },
+            FrameKind::EscapeEnd(live) => {
+                live.set(false);
+                self.ret(val, env)
+            },
+            FrameKind::Then(then) => then(self, val, env),
+            FrameKind::Consumer { consumer, env: caller_env } => self.tail_apply(consumer, values::spread(val), caller_env),
+            FrameKind::Receive { formals, form, env: receive_env } => {
+                let scope = Rc::new(receive_env.subenv(formals.bind(val)?));
+                self.sequence(form, 3, scope)
+            },
+            FrameKind::LetValues { bindings, form, index, sequential, scope, env: let_env } => {
+                let scope = Rc::new(scope.subenv(bindings[index].0.bind(val)?));
+                self.let_values_from(bindings, form, index + 1, sequential, scope, let_env)
+            },
+            FrameKind::DefineValues(formals) => {
+                let env = formals.bind(val)?.into_iter()
+                    .fold(env, |env, (name, val)| Rc::new(env.add_name(name, val)));
+                self.ret(values::values(Vec::new()), env)
+            },
+            FrameKind::Force(promise) => match promise.state() {
+                // forcing the promise from its own expression settled it first
+                PromiseState::Done(val) => self.ret(val, env),
+                PromiseState::Delayed { lazy: true, .. } if matches!(*val, Value::Promise(_)) => {
+                    if let Value::Promise(next) = &*val {
+                        promise.adopt(next);
+                    }
+                    self.force_promise(promise, env)
+                },
+                PromiseState::Delayed { .. } | PromiseState::Call { .. } => {
+                    promise.settle(val.clone());
+                    self.ret(val, env)
+                }
+            },
+            FrameKind::MakeParameter(converter) => {
+                let param = Parameter::new(val, Some(converter));
+                self.ret(Rc::new(Value::Parameter(Rc::new(param))), env)
+            },
+            FrameKind::ParameterizeInits { form, inits, mut done, env: body_env } => {
+                done.push(val);
+                self.parameterize_from(form, inits, done, body_env)
+            },
+            FrameKind::Convert { form, params, raw, mut converted, env: body_env } => {
+                converted.push(val);
+                self.convert(form, params, raw, converted, body_env)
+            },
+            FrameKind::Parameterize { env: body_env, .. } => self.ret(val, body_env)
+        }
+    }
+    // what to do with an error: carry on with a transfer that had to leave a builtin's run to get
+    // where it's going, or raise the error to the current handlers
+    fn signal(&mut self, err: RuntimeError, env: Rc<Env>) -> StepResult {
+        match err {
+            RuntimeError::Transfer(transfer) => self.transfer(transfer),
+            RuntimeError::Exit(status) => self.transfer(Rc::new(Transfer::Exit(status))),
+            // raise has already been through every handler
+            RuntimeError::Raised(_) => Err(err),
+            err if current_handler(&self.frames).is_none() => Err(err),
+            err => self.raise(error_value(err), false, env)
+        }
+    }
+    // calls the innermost handler with `obj`, or goes to the innermost guard
+    pub fn raise(&mut self, obj: Rc<Value>, continuable: bool, env: Rc<Env>) -> StepResult {
+        let frames = self.frames.clone();
+        self.raise_from(frames, obj, continuable, env)
+    }
+    fn raise_from(&mut self, frames: Frames, obj: Rc<Value>, continuable: bool, env: Rc<Env>) -> StepResult {
+        let frame = match current_handler(&frames) {
+            Some(frame) => frame,
+            None => return Err(RuntimeError::Raised(obj))
+        };
+        match &frame.kind {
+            FrameKind::Handler(handler) => {
+                let handler = handler.clone();
+                let skip = depth(&self.frames) + 1 - frame.depth;
+                self.push(FrameKind::RaiseReturn { obj: obj.clone(), continuable, skip, env: env.clone() });
+                self.tail_apply(handler, vec![obj], env)
+            },
+            _ => self.transfer(Rc::new(Transfer::Catch { guard: frame, obj }))
+        }
+    }
+    // runs `thunk` with `handler` installed
+    pub fn with_exception_handler(&mut self, handler: Rc<Value>, thunk: Rc<Value>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::Handler(handler));
+        self.tail_apply(thunk, Vec::new(), env)
+    }
+    // (guard (var clause ...) body ...), where `form` is the whole form
+    pub fn guard(&mut self, form: Rc<Form>, env: Rc<Env>) -> StepResult {
+        if guard_var(&form).is_none() {
+            return Err(RuntimeError::Syntax(SyntaxError::MalformedGuard(form.expr.to_string())));
+        }
+        self.push(FrameKind::Guard { form: form.clone(), env: env.clone() });
+        self.sequence(form, 2, env)
+    }
+    // the clauses run with the guard's handlers, and `var` bound to what was raised
+    fn catch(&mut self, form: Rc<Form>, env: Rc<Env>, obj: Rc<Value>) -> StepResult {
+        let var = guard_var(&form).expect("guards are checked on entry").to_string();
+        self.push(FrameKind::RestoreEnv(env.clone()));
+        let clause_env = Rc::new(env.add_name(var, obj.clone()));
+        self.guard_clause(form, 0, clause_env, obj)
+    }
+    fn guard_clause(&mut self, form: Rc<Form>, index: usize, env: Rc<Env>, obj: Rc<Value>) -> StepResult {
+        let clause = match guard_clauses(&form).get(index) {
+            Some(clause) => clause.clone(),
+            // nothing matched, so it goes on to the handlers outside the guard
+            None => return self.raise(obj, false, env)
+        };
+        let test = match &*clause.expr {
+            SExpr(_, _) => clause.parts[0].clone(),
+            _ => return Err(RuntimeError::Syntax(SyntaxError::MalformedGuard(clause.expr.to_string())))
+        };
+        if matches!(&*test.expr, LookupExpr(name) if name == "else") {
+            return self.sequence(clause, 1, env);
+        }
+        self.push(FrameKind::GuardClause { form, index, env: env.clone(), obj });
+        self.eval(&test, env)
+    }
+    pub fn call_cc(&mut self, func: Rc<Value>, env: Rc<Env>) -> StepResult {
+        let k = Continuation { frames: self.frames.clone(), env: env.clone(), live: None, prompt: None };
+        self.tail_apply(func, vec![Rc::new(Value::Continuation(Rc::new(k)))], env)
+    }
+    pub fn call_ec(&mut self, func: Rc<Value>, env: Rc<Env>) -> StepResult {
+        let live = Rc::new(Cell::new(true));
+        self.push(FrameKind::EscapeEnd(live.clone()));
+        let k = Continuation { frames: self.frames.clone(), env: env.clone(), live: Some(live), prompt: None };
+        self.tail_apply(func, vec![Rc::new(Value::Continuation(Rc::new(k)))], env)
+    }
+    pub fn dynamic_wind(&mut self, before: Rc<Value>, thunk: Rc<Value>, after: Rc<Value>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::WindBefore { before: before.clone(), thunk, after, env: env.clone() });
+        self.tail_apply(before, Vec::new(), env)
+    }
+    // runs `thunk` under a prompt; `handler` is None for a reset
+    pub fn call_with_prompt(&mut self, tag: Rc<Value>, thunk: Rc<Value>, handler: Option<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::Prompt { tag, handler, env: env.clone() });
+        self.tail_apply(thunk, Vec::new(), env)
+    }
+    // leaves for the innermost prompt with `tag`, passing its handler the continuation up to the
+    // prompt and `args`
+    pub fn abort_to_prompt(&mut self, tag: Rc<Value>, args: Vec<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        let prompt = match find_prompt(&self.frames, &tag) {
+            Ok(prompt) => prompt,
+            Err(NoPrompt::Missing) => return Err(RuntimeError::ControlError(format!("no prompt tagged {}", tag))),
+            Err(NoPrompt::OutsideBuiltin) => return Err(RuntimeError::ControlError(format!("the prompt tagged {} is outside a builtin", tag)))
+        };
+        let k = Continuation { frames: self.frames.clone(), env, live: None, prompt: Some(prompt.clone()) };
+        let k = Rc::new(Value::Continuation(Rc::new(k)));
+        self.transfer(Rc::new(Transfer::Abort { prompt, k, args }))
+    }
+    // (reset body ...)
+    pub fn reset(&mut self, form: Rc<Form>, env: Rc<Env>) -> StepResult {
+        let tag = RESET_TAG.with(|tag| tag.clone());
+        self.push(FrameKind::Prompt { tag, handler: None, env: env.clone() });
+        self.sequence(form, 1, env)
+    }
+    // (shift k body ...) leaves for the innermost reset, running the body there with k bound to
+    // the continuation up to it
+    pub fn shift(&mut self, form: Rc<Form>, env: Rc<Env>) -> StepResult {
+        let var = match form.parts.get(1).map(|var| &*var.expr) {
+            Some(LookupExpr(var)) if form.parts.len() >= 3 => var.to_string(),
+            _ => return Err(RuntimeError::Syntax(SyntaxError::MalformedShift(form.expr.to_string())))
+        };
+        let tag = RESET_TAG.with(|tag| tag.clone());
+        let prompt = match find_prompt(&self.frames, &tag) {
+            Ok(prompt) => prompt,
+            Err(NoPrompt::Missing) => return Err(RuntimeError::ControlError(String::from("shift without a reset"))),
+            Err(NoPrompt::OutsideBuiltin) => return Err(RuntimeError::ControlError(String::from("the reset is outside a builtin")))
+        };
+        let k = Continuation { frames: self.frames.clone(), env: env.clone(), live: None, prompt: Some(prompt.clone()) };
+        let k = Rc::new(Value::Continuation(Rc::new(k)));
+        let body: Then = Rc::new(move |machine, k, _| {
+            let scope = Rc::new(env.add_name(var.clone(), k));
+            machine.sequence(form.clone(), 2, scope)
+        });
+        self.transfer(Rc::new(Transfer::Shift { prompt, k, body }))
+    }
+    // calls `consumer` with the values `producer` returns
+    pub fn call_with_values(&mut self, producer: Rc<Value>, consumer: Rc<Value>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::Consumer { consumer, env: env.clone() });
+        self.tail_apply(producer, Vec::new(), env)
+    }
+    // (receive formals producer body ...), where `form` is the whole form
+    pub fn receive(&mut self, formals: Rc<Formals>, producer: Rc<Form>, form: Rc<Form>, env: Rc<Env>) -> StepResult {
+        self.restore_env(env.clone());
+        self.push(FrameKind::Receive { formals, form, env: env.clone() });
+        self.eval(&producer, env)
+    }
+    // (let-values bindings body ...), or let*-values when `sequential`
+    pub fn let_values(&mut self, bindings: Rc<Vec<(Formals, Rc<Form>)>>, form: Rc<Form>, sequential: bool, env: Rc<Env>) -> StepResult {
+        self.restore_env(env.clone());
+        self.let_values_from(bindings, form, 0, sequential, env.clone(), env)
+    }
+    fn let_values_from(&mut self, bindings: Rc<Vec<(Formals, Rc<Form>)>>, form: Rc<Form>, index: usize, sequential: bool, scope: Rc<Env>, env: Rc<Env>) -> StepResult {
+        let init = match bindings.get(index) {
+            Some((_, init)) => init.clone(),
+            None => return self.sequence(form, 2, scope)
+        };
+        let init_env = if sequential { scope.clone() } else { env.clone() };
+        self.push(FrameKind::LetValues { bindings, form, index, sequential, scope, env });
+        self.eval(&init, init_env)
+    }
+    // (define-values formals producer) defines each name like a top-level `let`
+    pub fn define_values(&mut self, formals: Rc<Formals>, producer: Rc<Form>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::DefineValues(formals));
+        self.eval(&producer, env)
+    }
+    // the value of a promise, evaluating its expression the first time. A delay-force hands over
+    // to the promise its expression gives, in the same frame, so chains of them don't build up
+    pub fn force(&mut self, val: Rc<Value>, env: Rc<Env>) -> StepResult {
+        match &*val {
+            Value::Promise(promise) => self.force_promise(promise.clone(), env),
+            _ => self.ret(val, env)
+        }
+    }
+    fn force_promise(&mut self, promise: Rc<Promise>, env: Rc<Env>) -> StepResult {
+        match promise.state() {
+            PromiseState::Done(val) => self.ret(val, env),
+            PromiseState::Delayed { form, env: promise_env, .. } => {
+                self.restore_env(env);
+                self.push(FrameKind::Force(promise));
+                self.eval(&form, promise_env)
+            },
+            PromiseState::Call { func, args } => {
+                self.push(FrameKind::Force(promise));
+                self.apply(func, args, env)
+            }
+        }
+    }
+    // forces `val`, then hands its value to `then` as call_then does
+    pub fn force_then(&mut self, val: Rc<Value>, env: Rc<Env>,
+                      then: impl Fn(&mut Machine, Rc<Value>, Rc<Env>) -> StepResult + 'static) -> StepResult {
+        self.push(FrameKind::Then(Rc::new(then)));
+        self.force(val, env)
+    }
+    // makes a parameter whose value is `value` passed through `converter`
+    pub fn make_parameter(&mut self, value: Rc<Value>, converter: Rc<Value>, env: Rc<Env>) -> StepResult {
+        self.push(FrameKind::MakeParameter(converter.clone()));
+        self.tail_apply(converter, vec![value], env)
+    }
+    // (parameterize ((param value) ...) body ...), where `inits` are each param and value in turn
+    pub fn parameterize(&mut self, inits: Rc<Vec<Rc<Form>>>, form: Rc<Form>, env: Rc<Env>) -> StepResult {
+        self.parameterize_from(form, inits, Vec::new(), env)
+    }
+    // evaluates the inits from the first not yet done, then runs the converters
+    fn parameterize_from(&mut self, form: Rc<Form>, inits: Rc<Vec<Rc<Form>>>, done: Vec<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        if let Some(init) = inits.get(done.len()) {
+            let init = init.clone();
+            self.push(FrameKind::ParameterizeInits { form, inits, done, env: env.clone() });
+            return self.eval(&init, env);
+        }
+        let mut params = Vec::new();
+        let mut raw = Vec::new();
+        for pair in done.chunks(2) {
+            match &*pair[0] {
+                Value::Parameter(param) => params.push(param.clone()),
+                _ => return Err(RuntimeError::TypeError(format!("parameterize expects a parameter, got {}", pair[0])))
+            }
+            raw.push(pair[1].clone());
+        }
+        self.convert(form, Rc::new(params), Rc::new(raw), Vec::new(), env)
+    }
+    // runs the converters from the first param not yet converted, then the body
+    fn convert(&mut self, form: Rc<Form>, params: Rc<Vec<Rc<Parameter>>>, raw: Rc<Vec<Rc<Value>>>, mut converted: Vec<Rc<Value>>, env: Rc<Env>) -> StepResult {
+        while converted.len() < params.len() {
+            let index = converted.len();
+            if let Some(converter) = params[index].converter() {
+                let (converter, val) = (converter.clone(), raw[index].clone());
+                self.push(FrameKind::Convert { form, params, raw, converted, env: env.clone() });
+                return self.tail_apply(converter, vec![val], env);
+            }
+            converted.push(raw[index].clone());
+        }
+        let bindings = params.iter().cloned().zip(converted).collect();
+        self.push(FrameKind::Parameterize { bindings: Rc::new(bindings), env: env.clone() });
+        self.sequence(form, 2, env)
+    }
+    // runs every after thunk on the way out, then leaves with `Exit`
+    pub fn exit(&mut self, status: i32) -> StepResult {
+        self.transfer(Rc::new(Transfer::Exit(status)))
+    }
+    // takes one step towards where `transfer` is going: running the after thunk of the innermost
+    // wind being left, or the before thunk of the outermost one being entered, and coming back
+    // here when it's done, or getting there if there are none left
+    fn transfer(&mut self, transfer: Rc<Transfer>) -> StepResult {
+        if let Transfer::Resume(k, _) = &*transfer {
+            if k.live.as_ref().is_some_and(|live| !live.get()) {
+                return Err(RuntimeError::ControlError(String::from("escape continuation called after its call/ec returned")));
+            }
+        }
+        let target = transfer.target();
+        let mut common = common_ancestor(&self.frames, &target);
+        let mut runner = self.frames.clone();
+        while !same(&runner, &common) {
+            let frame = runner.expect("the common frames are further down");
+            match &frame.kind {
+                FrameKind::Wind { after, env, .. } => {
+                    let (after, env) = (after.clone(), env.clone());
+                    self.frames = frame.next.clone();
+                    self.push(FrameKind::Transfer(transfer));
+                    return self.tail_apply(after, Vec::new(), env);
+                },
+                // nothing is waiting on the bottom of a top-level run, so a continuation from an
+                // earlier one can take its place
+                FrameKind::Halt { .. } if frame.next.is_none() && matches!(*transfer, Transfer::Resume(_, _)) => {
+                    common = None;
+                    break;
+                },
+                // the rest of the way is in the run which called this one's builtin
+                FrameKind::Halt { .. } => return Err(match &*transfer {
+                    Transfer::Exit(status) => RuntimeError::Exit(*status),
+                    _ => RuntimeError::Transfer(transfer.clone())
+                }),
+                _ => {}
+            }
+            runner = frame.next.clone();
+        }
+        let mut entering = None;
+        let mut runner = target.clone();
+        while !same(&runner, &common) {
+            let frame = runner.expect("the common frames are further down");
+            match &frame.kind {
+                FrameKind::Wind { .. } => entering = Some(frame.clone()),
+                FrameKind::Halt { .. } if frame.next.is_some() =>
+                    return Err(RuntimeError::ControlError(String::from("continuation captured inside a builtin called after the builtin returned"))),
+                _ => {}
+            }
+            runner = frame.next.clone();
+        }
+        if let Some(wind) = entering {
+            if let FrameKind::Wind { before, env, .. } = &wind.kind {
+                let (before, env) = (before.clone(), env.clone());
+                self.frames = wind.next.clone();
+                self.push(FrameKind::Rewind { wind, transfer });
+                return self.tail_apply(before, Vec::new(), env);
+            }
+        }
+        self.frames = target;
+        match &*transfer {
+            Transfer::Resume(k, val) => self.ret(val.clone(), k.env.clone()),
+            Transfer::Catch { guard, obj } => match &guard.kind {
+                FrameKind::Guard { form, env } => self.catch(form.clone(), env.clone(), obj.clone()),
+                _ => unreachable!()
+            },
+            Transfer::Abort { prompt, k, args } => match &prompt.kind {
+                FrameKind::Prompt { tag, handler, env } => {
+                    self.push(FrameKind::RestoreEnv(env.clone()));
+                    match handler {
+                        Some(handler) => {
+                            let mut handler_args = vec![k.clone()];
+                            handler_args.extend(args.iter().cloned());
+                            self.tail_apply(handler.clone(), handler_args, env.clone())
+                        },
+                        // aborting to a reset runs the receiver under a reset of its own, as
+                        // shift does
+                        None => {
+                            self.push(FrameKind::Prompt { tag: tag.clone(), handler: None, env: env.clone() });
+                            let receiver = args.first().cloned().unwrap_or_else(|| Rc::new(Value::Nil));
+                            self.tail_apply(receiver, vec![k.clone()], env.clone())
+                        }
+                    }
+                },
+                _ => unreachable!()
+            },
+            // shift's body runs under a reset of its own
+            Transfer::Shift { prompt, k, body } => match &prompt.kind {
+                FrameKind::Prompt { tag, env, .. } => {
+                    self.push(FrameKind::RestoreEnv(env.clone()));
+                    self.push(FrameKind::Prompt { tag: tag.clone(), handler: None, env: env.clone() });
+                    body(self, k.clone(), env.clone())
+                },
+                _ => unreachable!()
+            },
+            Transfer::Exit(status) => Err(RuntimeError::Exit(*status))
+        }
+    }
+    // puts the frames of a delimited continuation back, from the bottom, running the before
+    // thunk of each wind as it's entered, then returns `value` to them
+    fn reinstate(&mut self, segment: Rc<Vec<FrameKind>>, mut index: usize, value: Rc<Value>, env: Rc<Env>) -> StepResult {
+        while index > 0 {
+            index -= 1;
+            if let FrameKind::Wind { before, env: wind_env, .. } = &segment[index] {
+                let (before, wind_env) = (before.clone(), wind_env.clone());
+                self.push(FrameKind::Reinstate { segment, index, value, env });
+                return self.tail_apply(before, Vec::new(), wind_env);
+            }
+            self.push(segment[index].clone());
+        }
+        self.ret(value, env)
+    }
+}
@@ -1,59 +1,149 @@
 extern crate im;
 use im::hashmap::HashMap;
 
-use super::super::parser::expressions::{Expression, Expression::*};
+use super::super::parser::expressions::Expression;
+use super::super::parser::main::{Parser, SyntaxError};
+use super::hashtables::HashTable;
+use super::persistent::{PersistentMap, Key};
+use super::records::{Record, RecordType};
+use super::ports::PortRef;
+use super::exceptions::Condition;
+use super::promises::Promise;
+use super::parameters::Parameter;
+use super::machine::{self, Machine, Continuation, Transfer};
+use super::compiler::{Proto, Form, compile_lambda};
 
+use either::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::boxed::Box;
 
-#[derive(Debug, Clone)]
-struct LambdaFunction {
+#[derive(Debug)]
+pub enum RuntimeError {
+    Syntax(SyntaxError),
+    BadRator(String),
+    TypeError(String),
+    ArityError(String),
+    RangeError(String),
+    ModuleError(String),
+    IOError(String),
+    // a datum `read` couldn't make sense of
+    ReadError(String),
+    // an object raised and not handled
+    Raised(Rc<Value>),
+    // raised by `exit`, unwinding to the top level so ports can be flushed on the way out
+    Exit(i32),
+    // a continuation being resumed or a guard or prompt being gone to, on its way out of a
+    // builtin to the run it belongs to
+    Transfer(Rc<Transfer>),
+    ControlError(String)
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Syntax(err) => write!(f, "syntax error: {}", err),
+            RuntimeError::BadRator(msg) => write!(f, "not a procedure: {}", msg),
+            RuntimeError::TypeError(msg) => write!(f, "type error: {}", msg),
+            RuntimeError::ArityError(msg) => write!(f, "arity error: {}", msg),
+            RuntimeError::RangeError(msg) => write!(f, "out of range: {}", msg),
+            RuntimeError::ModuleError(msg) => write!(f, "module error: {}", msg),
+            RuntimeError::IOError(msg) => write!(f, "i/o error: {}", msg),
+            RuntimeError::ReadError(msg) => write!(f, "read error: {}", msg),
+            RuntimeError::Raised(val) => match &**val {
+                Value::Condition(condition) => write!(f, "{}", condition),
+                _ => write!(f, "uncaught exception: {}", val)
+            },
+            RuntimeError::Exit(status) => write!(f, "exit with status {}", status),
+            RuntimeError::Transfer(_) => write!(f, "continuation called outside its extent"),
+            RuntimeError::ControlError(msg) => write!(f, "control error: {}", msg)
+        }
+    }
+}
+
+// what evaluating an expression leaves behind: the environment to carry on in and its value
+pub type EvalResult = Result<(Rc<Env>, Rc<Value>), RuntimeError>;
+// what builtins working on the machine return, having set it up to carry on
+pub type StepResult = Result<(), RuntimeError>;
+
+thread_local! {
+    // directories of the files currently being loaded, innermost last
+    static LOAD_DIRS: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+// relative paths are taken from the file being loaded, or the working directory at the REPL
+pub fn resolve_path(path: &str) -> PathBuf {
+    LOAD_DIRS.with(|dirs| match dirs.borrow().last() {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path)
+    })
+}
+
+#[derive(Clone)]
+pub struct LambdaFunction {
+    proto: Rc<Proto>,
+    // the values of the variables it uses from the procedures around it, as they were when it
+    // was made
+    captures: Vec<Option<Rc<Value>>>,
     env: Rc<Env>,
-    arg_names: Vec<String>,
-    body: Rc<Expression>,
-    own_name: Option<String>
+    // lambdas ignore extra arguments and leave missing ones unbound, but builtin procedures
+    // written as lambdas, like record accessors, reject the wrong number of arguments
+    checks_arity: bool
+}
+
+// the captured env is left out, since it can be huge
+impl fmt::Debug for LambdaFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LambdaFunction")
+            .field("arg_names", &self.proto.arg_names)
+            .field("own_name", &self.name())
+            .finish()
+    }
 }
 
 impl LambdaFunction {
-    fn new_anonymous(env: Rc<Env>, arg_names: Vec<String>, body: Rc<Expression>) -> Self {
-        return LambdaFunction {
-            env: env,
-            arg_names: arg_names,
-            body: body,
-            own_name: None
+    pub fn new(proto: Rc<Proto>, captures: Vec<Option<Rc<Value>>>, env: Rc<Env>) -> Self {
+        LambdaFunction {
+            proto,
+            captures,
+            env,
+            checks_arity: false
         }
     }
-    fn new_named(env: Rc<Env>, name: String, arg_names: Vec<String>, body: Rc<Expression>) -> Self {
+    pub fn new_checked(env: Rc<Env>, name: String, arg_names: Vec<String>, body: Rc<Expression>) -> Self {
         LambdaFunction {
-            env: env,
-            arg_names: arg_names,
-            body: body,
-            own_name: Some(name)
+            checks_arity: true,
+            ..LambdaFunction::new(compile_lambda(&arg_names, &body, Some(&name), &env), Vec::new(), env)
         }
     }
-    fn eval(self: Rc<Self>, arguments: Vec<Rc<Value>>) -> Rc<Value> {
-        let mut new_vars = HashMap::new();
-        self.arg_names.iter()
-            .zip(arguments.into_iter())
-            .for_each(|(name, value)|
-                match new_vars.insert(name.clone(), value) {
-                    _ => ()
-                });
-        match &self.own_name {
-            Some(name) => {
-                new_vars.insert(name.to_string(), Rc::new(Value::Lambda(self.clone())));
-            },
-            None => {}
-        };
-        let subenv = Rc::new(self.env.subenv(new_vars));
-        return subenv.eval(&self.body).1
+    pub fn name(&self) -> Option<&String> {
+        self.proto.own.as_ref().map(|(name, _)| name)
+    }
+    pub fn proto(&self) -> &Rc<Proto> {
+        &self.proto
+    }
+    pub fn captures(&self) -> &[Option<Rc<Value>>] {
+        &self.captures
+    }
+    pub fn env(&self) -> &Rc<Env> {
+        &self.env
+    }
+    pub fn checks_arity(&self) -> bool {
+        self.checks_arity
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum RuntimeFunctionWrapper {
-    Immediate(fn (Rc<Env>, Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>)),
-    Symbolic(fn (Rc<Env>, Vec<Box<Expression>>) -> (Rc<Env>, Rc<Value>))
+    Immediate(fn (Rc<Env>, Vec<Rc<Value>>) -> EvalResult),
+    Symbolic(fn (Rc<Env>, Vec<Rc<Expression>>) -> EvalResult),
+    // control operators, which work on the machine's continuation rather than returning
+    Control(fn (&mut Machine, Rc<Env>, Vec<Rc<Value>>) -> StepResult),
+    // special forms given the form of the whole expression, to evaluate as much of it as they like
+    // on the machine
+    Syntax(fn (&mut Machine, Rc<Env>, Rc<Form>) -> StepResult)
 }
 
 #[derive(Debug, Clone)]
@@ -63,7 +153,48 @@ pub enum Value {
     RuntimeFunction(RuntimeFunctionWrapper),
     Int(i32),
     Float(f32),
-    Str(String)
+    Str(String),
+    Char(char),
+    Symbol(String),
+    Eof,
+    Pair(Rc<Value>, Rc<Value>),
+    Vector(Rc<RefCell<Vec<Rc<Value>>>>),
+    Bytevector(Rc<RefCell<Vec<u8>>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    Map(PersistentMap),
+    Set(im::HashSet<Key>),
+    Record(Rc<Record>),
+    RecordType(Rc<RecordType>),
+    Port(PortRef),
+    Condition(Rc<Condition>),
+    Continuation(Rc<Continuation>),
+    // more or fewer than one value, returned by `values`
+    Values(Vec<Rc<Value>>),
+    Promise(Rc<Promise>),
+    Parameter(Rc<Parameter>)
+}
+
+impl Value {
+    // builds a proper list, ending in `Nil`
+    pub fn list(items: Vec<Rc<Value>>) -> Rc<Value> {
+        items.into_iter().rev()
+            .fold(Rc::new(Value::Nil), |tail, item| Rc::new(Value::Pair(item, tail)))
+    }
+    // the elements of a proper list, or `None` if it doesn't end in `Nil`
+    pub fn list_items(list: &Rc<Value>) -> Option<Vec<Rc<Value>>> {
+        let mut items = Vec::new();
+        let mut runner = list.clone();
+        loop {
+            runner = match &*runner {
+                Value::Nil => return Some(items),
+                Value::Pair(car, cdr) => {
+                    items.push(car.clone());
+                    cdr.clone()
+                },
+                _ => return None
+            };
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -73,108 +204,87 @@ pub struct Env {
 
 impl Env {
     pub fn from_table(table: HashMap<String, Rc<Value>>) -> Self {
-        println!("New env from table {:?}", table.keys().collect::<Vec<_>>());
         Env {
-            table: table
+            table
         }
     }
     pub fn new() -> Self {
-        println!("New empty env");
         Env {
             table: HashMap::new()
         }
     }
-    fn subenv(&self, new_vars: HashMap<String, Rc<Value>>) -> Self {
-        println!("New subenv {:?}", new_vars.keys().collect::<Vec<_>>());
+    // the new names go into a copy of this env's table rather than the other way round, which
+    // would walk the whole of it on every procedure call
+    pub fn subenv(&self, new_vars: HashMap<String, Rc<Value>>) -> Self {
+        let mut table = self.table.clone();
+        table.extend(new_vars);
         Env {
-            table: new_vars.union(self.table.clone())
+            table
         }
     }
-    fn add_name(self: &Self, name: String, value: Rc<Value>) -> Self {
+    pub fn add_name(&self, name: String, value: Rc<Value>) -> Self {
         Env {
             table: self.table.update(name, value)
         }
     }
-    pub fn lookup(&self, name: &String) -> Rc<Value> {
+    // the definitions made in this env since `from`, on top of `onto`
+    pub fn rebase(&self, from: &Env, onto: &Env) -> Env {
+        let mut table = onto.table.clone();
+        for (name, val) in self.table.iter() {
+            if !from.table.get(name).is_some_and(|old| Rc::ptr_eq(old, val)) {
+                table.insert(name.clone(), val.clone());
+            }
+        }
+        Env {
+            table
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<Rc<Value>> {
+        self.table.get(name).cloned()
+    }
+    pub fn lookup(&self, name: &str) -> Rc<Value> {
         self.table.get(name)
             .map_or_else(
                 || Rc::new(Value::Nil),
                 |val| val.clone())
     }
-    pub fn eval(self: Rc<Self>, expr: &Expression) -> (Rc<Self>, Rc<Value>) {
-        return match expr {
-            ListExpr(contents) => self.eval_list(contents.to_vec()),
-            SExpr(rator, rands) => self.eval_sexpr(rator.clone(), rands.to_vec()),
-            LetExpr(name, rhs) => self.eval_let(name.to_string(), rhs),
-            LambdaExpr(arg_list, body) => self.eval_lambda(arg_list.to_vec(), body.clone()),
-            LookupExpr(name) => {
-                let val = self.lookup(name);
-                (self, val)
-            },
-            IntegerLiteral(v) => (self, Rc::new(Value::Int(*v))),
-            FloatLiteral(v) => (self, Rc::new(Value::Float(*v))),
-            StringLiteral(v) => (self, Rc::new(Value::Str(v.to_string())))
-        }
-    }
-    fn eval_list(self: Rc<Self>, contents: Vec<Box<Expression>>) -> (Rc<Self>, Rc<Value>) {
-        if contents.len() == 0 {
-            return (self, Rc::new(Value::Nil));
-        }
-        let cons = self.lookup(&String::from("cons"));
-        return (self, cons);
-    }
-    fn eval_sexpr(self: Rc<Self>, rator: Box<Expression>, rands: Vec<Box<Expression>>) -> (Rc<Self>, Rc<Value>) {
-        let (mut env, func) = match &*rator {
-            expr @ SExpr(_, _) |
-            expr @ LookupExpr(_) |
-            expr @ LambdaExpr(_, _) |
-            expr @ LetExpr(_, _) => self.clone().eval(expr),
-            _ => panic!("Cannot evaluate rator for s-expr")
+    // parse and evaluate every expression in `source`, threading definitions through.
+    // `origin` is the file the source came from, if any, which `include`s are relative to
+    pub fn eval_source(self: Rc<Self>, source: &str, origin: Option<&Path>) -> EvalResult {
+        let mut env = self;
+        let mut res = Rc::new(Value::Nil);
+        let mut p = match origin {
+            Some(path) => Parser::for_file(source, path, 0),
+            None => Parser::new(source, 0)
         };
-        return match &*func {
-            Value::Lambda(lambda) => {
-                let arguments = rands.into_iter().map(|a| {
-                    let res = env.clone().eval(&a);
-                    match res {
-                        (new_env, val) => {
-                            env = new_env;
-                            return val;
-                        }
-                    }
-                }).collect();
-                (self, lambda.clone().eval(arguments))
-            },
-            Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(internal)) => internal(self, rands),
-            Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(internal)) => {
-                let arguments = rands.into_iter().map(|a| {
-                    let res = env.clone().eval(&a);
-                    match res {
-                        (new_env, val) => {
-                            env = new_env;
-                            return val;
-                        }
-                    }
-                }).collect();
-                internal(self, arguments)
+        while !p.is_finished() {
+            match p.parse(false).map_err(RuntimeError::Syntax)? {
+                Either::Left(expr) => {
+                    let (new_env, val) = env.eval(&Rc::new(expr))?;
+                    env = new_env;
+                    res = val;
+                },
+                Either::Right(_) => {}
             }
-            _ => panic!("Bad rator for s-expr: {:?} ({:?})", func, rator)
         }
+        Ok((env, res))
     }
-    fn eval_let(self: Rc<Self>, name: String, rhs: &Expression) -> (Rc<Env>, Rc<Value>) {
-        match rhs {
-            LambdaExpr(arg_list, body) => {
-                let lambda = LambdaFunction::new_named(self.clone(), name.clone(), arg_list.to_vec(), body.clone());
-                let value = Rc::new(Value::Lambda(Rc::new(lambda)));
-                return (Rc::new(self.add_name(name, value)), Rc::new(Value::Nil));
-            }
-            _  => {
-                let (env, value) = self.clone().eval(rhs);
-                return (Rc::new(env.add_name(name, value)), Rc::new(Value::Nil));
-            }
+    pub fn eval_file(self: Rc<Self>, path: &Path) -> EvalResult {
+        let mut source = fs::read_to_string(path)
+            .map_err(|why| RuntimeError::IOError(format!("couldn't read {}: {}", path.display(), why)))?;
+        // `#!` lines let scripts be made executable, but aren't valid lisp
+        if source.starts_with("#!") {
+            source = source.find('\n').map_or_else(String::new, |nl| source[nl..].to_string());
         }
+        let dir = path.parent().map_or_else(PathBuf::new, |dir| dir.to_path_buf());
+        LOAD_DIRS.with(|dirs| dirs.borrow_mut().push(dir));
+        let res = self.eval_source(&source, Some(path));
+        LOAD_DIRS.with(|dirs| dirs.borrow_mut().pop());
+        res
     }
-    fn eval_lambda(self: Rc<Self>, arg_list: Vec<String>, body: Rc<Expression>) -> (Rc<Env>, Rc<Value>) {
-        let lambda = Rc::new(Value::Lambda(Rc::new(LambdaFunction::new_anonymous(self.clone(), arg_list, body))));
-        return (self, lambda);
+    // evaluation runs on the machine, so it doesn't use up the Rust stack however deeply the
+    // program recurses
+    pub fn eval(self: Rc<Self>, expr: &Rc<Expression>) -> EvalResult {
+        machine::eval(self, expr.clone())
     }
 }
@@ -1,2 +1,24 @@
 pub mod main;
 pub mod stdlib;
+pub mod modules;
+pub mod strings;
+pub mod chars;
+pub mod vectors;
+pub mod bytevectors;
+pub mod equality;
+pub mod hashtables;
+pub mod persistent;
+pub mod records;
+pub mod printer;
+pub mod reader;
+pub mod ports;
+pub mod files;
+pub mod process;
+pub mod exceptions;
+pub mod machine;
+pub mod compiler;
+pub mod control;
+pub mod values;
+pub mod promises;
+pub mod parameters;
+pub mod io;
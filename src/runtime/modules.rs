@@ -0,0 +1,218 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeError, EvalResult};
+use super::super::parser::expressions::{Expression, Expression::*};
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// the names and values a library makes available to its importers
+type Exports = HashMap<String, Rc<Value>>;
+
+thread_local! {
+    // directories searched, in order, for `our/utils.sld` or `our/utils.scm`
+    static SEARCH_PATH: RefCell<Vec<PathBuf>> = RefCell::new(vec![PathBuf::from(".")]);
+    // every library defined so far, keyed by its printed name, e.g. "(our utils)"
+    static LIBRARIES: RefCell<HashMap<String, Exports>> = RefCell::new(HashMap::new());
+    // libraries whose definitions are currently being evaluated, innermost last
+    static LOADING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    // the builtins and prelude which every library body starts from
+    static ROOT: RefCell<Option<Rc<Env>>> = const { RefCell::new(None) };
+}
+
+pub fn set_search_path(path: Vec<PathBuf>) {
+    SEARCH_PATH.with(|sp| *sp.borrow_mut() = path);
+}
+
+pub fn set_root(env: Rc<Env>) {
+    ROOT.with(|root| *root.borrow_mut() = Some(env));
+}
+
+fn root() -> Rc<Env> {
+    ROOT.with(|root| root.borrow().clone())
+        .unwrap_or_else(|| Rc::new(Env::new()))
+}
+
+fn malformed(what: &str, expr: &Expression) -> RuntimeError {
+    RuntimeError::ModuleError(format!("malformed {}: {}", what, expr))
+}
+
+fn identifier(expr: &Expression, what: &str) -> Result<String, RuntimeError> {
+    match expr {
+        LookupExpr(name) => Ok(name.to_string()),
+        _ => Err(malformed(what, expr))
+    }
+}
+
+// library names are lists of identifiers and integers, e.g. `(srfi 1)`
+fn library_name(expr: &Expression) -> Result<String, RuntimeError> {
+    let parts = match expr {
+        SExpr(head, rest) => std::iter::once(head).chain(rest.iter()),
+        _ => return Err(malformed("library name", expr))
+    };
+    let parts: Result<Vec<String>, RuntimeError> = parts.map(|part| match &**part {
+        LookupExpr(name) => Ok(name.to_string()),
+        IntegerLiteral(n) if *n >= 0 => Ok(n.to_string()),
+        _ => Err(malformed("library name", expr))
+    }).collect();
+    Ok(format!("({})", parts?.join(" ")))
+}
+
+fn find_library_file(name: &str) -> Option<PathBuf> {
+    let relative: PathBuf = name.trim_matches(|c| c == '(' || c == ')').split(' ').collect();
+    SEARCH_PATH.with(|sp| {
+        sp.borrow().iter()
+            .flat_map(|dir| ["sld", "scm"].iter().map(|ext| dir.join(&relative).with_extension(ext)).collect::<Vec<_>>())
+            .find(|path| path.is_file())
+    })
+}
+
+fn cycle_through(name: &str) -> Option<String> {
+    LOADING.with(|loading| {
+        let loading = loading.borrow();
+        loading.iter().position(|lib| lib == name).map(|start| {
+            let mut cycle: Vec<&str> = loading[start..].iter().map(|lib| lib.as_str()).collect();
+            cycle.push(name);
+            cycle.join(" -> ")
+        })
+    })
+}
+
+fn resolve_library(name: &String) -> Result<Exports, RuntimeError> {
+    if let Some(cycle) = cycle_through(name) {
+        return Err(RuntimeError::ModuleError(format!("cyclic import: {}", cycle)));
+    }
+    if let Some(exports) = LIBRARIES.with(|libs| libs.borrow().get(name).cloned()) {
+        return Ok(exports);
+    }
+    let path = find_library_file(name)
+        .ok_or_else(|| RuntimeError::ModuleError(format!("library {} not found in search path", name)))?;
+    root().eval_file(&path)?;
+    LIBRARIES.with(|libs| libs.borrow().get(name).cloned())
+        .ok_or_else(|| RuntimeError::ModuleError(format!("{} does not define library {}", path.display(), name)))
+}
+
+fn resolve_import_set(set: &Expression) -> Result<Exports, RuntimeError> {
+    if let SExpr(head, args) = set {
+        if let (LookupExpr(modifier), Some(inner)) = (&**head, args.first()) {
+            let rest = &args[1..];
+            match modifier.as_str() {
+                "only" => {
+                    let exports = resolve_import_set(inner)?;
+                    let mut restricted = HashMap::new();
+                    for id in rest {
+                        let id = identifier(id, "only import set")?;
+                        match exports.get(&id) {
+                            Some(val) => restricted.insert(id, val.clone()),
+                            None => return Err(RuntimeError::ModuleError(format!("cannot import missing name {}", id)))
+                        };
+                    }
+                    return Ok(restricted);
+                },
+                "except" => {
+                    let mut exports = resolve_import_set(inner)?;
+                    for id in rest {
+                        let id = identifier(id, "except import set")?;
+                        if exports.remove(&id).is_none() {
+                            return Err(RuntimeError::ModuleError(format!("cannot exclude missing name {}", id)));
+                        }
+                    }
+                    return Ok(exports);
+                },
+                "prefix" => {
+                    let prefix = match rest {
+                        [prefix] => identifier(prefix, "prefix import set")?,
+                        _ => return Err(malformed("prefix import set", set))
+                    };
+                    let exports = resolve_import_set(inner)?;
+                    return Ok(exports.into_iter().map(|(id, val)| (format!("{}{}", prefix, id), val)).collect());
+                },
+                "rename" => {
+                    let mut exports = resolve_import_set(inner)?;
+                    for pair in rest {
+                        let (from, to) = rename_pair(pair)?;
+                        match exports.remove(&from) {
+                            Some(val) => exports.insert(to, val),
+                            None => return Err(RuntimeError::ModuleError(format!("cannot rename missing name {}", from)))
+                        };
+                    }
+                    return Ok(exports);
+                },
+                _ => {}
+            }
+        }
+    }
+    resolve_library(&library_name(set)?)
+}
+
+// the `(from to)` pairs of a rename import set
+fn rename_pair(pair: &Expression) -> Result<(String, String), RuntimeError> {
+    match pair {
+        SExpr(from, to) if to.len() == 1 => Ok((identifier(from, "rename")?, identifier(&to[0], "rename")?)),
+        _ => Err(malformed("rename", pair))
+    }
+}
+
+fn import_all(env: Rc<Env>, sets: &[Rc<Expression>]) -> Result<Rc<Env>, RuntimeError> {
+    let mut imported = HashMap::new();
+    for set in sets {
+        imported = resolve_import_set(set)?.union(imported);
+    }
+    Ok(Rc::new(env.subenv(imported)))
+}
+
+pub fn fn_import(env: Rc<Env>, args: Vec<Rc<Expression>>) -> EvalResult {
+    Ok((import_all(env, &args)?, Rc::new(Value::Nil)))
+}
+
+fn eval_library(name: &String, declarations: &[Rc<Expression>]) -> Result<Exports, RuntimeError> {
+    let mut env = root();
+    let mut export_specs = Vec::new();
+    for declaration in declarations {
+        let (keyword, args) = match &**declaration {
+            SExpr(head, args) => (identifier(head, "library declaration")?, args),
+            // `begin` bodies, and the contents of `include`d files
+            BeginExpr(_) => {
+                env = env.eval(declaration)?.0;
+                continue;
+            },
+            _ => return Err(malformed("library declaration", declaration))
+        };
+        match keyword.as_str() {
+            "export" => export_specs.extend(args.iter()),
+            "import" => env = import_all(env, args)?,
+            _ => return Err(malformed("library declaration", declaration))
+        }
+    }
+    let mut exports = HashMap::new();
+    for spec in export_specs {
+        let (internal, external) = match &**spec {
+            LookupExpr(id) => (id.to_string(), id.to_string()),
+            SExpr(head, pair) if pair.len() == 2 && identifier(head, "export")? == "rename" =>
+                (identifier(&pair[0], "export")?, identifier(&pair[1], "export")?),
+            _ => return Err(malformed("export spec", spec))
+        };
+        match env.get(&internal) {
+            Some(val) => exports.insert(external, val),
+            None => return Err(RuntimeError::ModuleError(format!("library {} exports undefined name {}", name, internal)))
+        };
+    }
+    Ok(exports)
+}
+
+pub fn fn_define_library(env: Rc<Env>, args: Vec<Rc<Expression>>) -> EvalResult {
+    let name = match args.first() {
+        Some(name) => library_name(name)?,
+        None => return Err(RuntimeError::ArityError(String::from("define-library expects a name")))
+    };
+    if let Some(cycle) = cycle_through(&name) {
+        return Err(RuntimeError::ModuleError(format!("cyclic import: {}", cycle)));
+    }
+    LOADING.with(|loading| loading.borrow_mut().push(name.clone()));
+    let exports = eval_library(&name, &args[1..]);
+    LOADING.with(|loading| loading.borrow_mut().pop());
+    let exports = exports?;
+    LIBRARIES.with(|libs| libs.borrow_mut().insert(name, exports));
+    Ok((env, Rc::new(Value::Nil)))
+}
@@ -0,0 +1,76 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::machine::Machine;
+use super::stdlib::{boolean, expect_arity};
+use super::compiler::Form;
+use super::values::items;
+use super::super::parser::main::SyntaxError;
+
+use std::rc::Rc;
+
+// a parameter object, whose value is the one given by the innermost parameterize around the
+// call, or the one it was made with
+#[derive(Debug)]
+pub struct Parameter {
+    value: Rc<Value>,
+    // applied to each value the parameter is given, as it's made and by parameterize
+    converter: Option<Rc<Value>>
+}
+
+impl Parameter {
+    pub fn new(value: Rc<Value>, converter: Option<Rc<Value>>) -> Self {
+        Parameter {
+            value,
+            converter
+        }
+    }
+    pub fn value(&self) -> &Rc<Value> {
+        &self.value
+    }
+    pub fn converter(&self) -> Option<&Rc<Value>> {
+        self.converter.as_ref()
+    }
+}
+
+// (make-parameter value [converter])
+fn fn_make_parameter(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> Result<(), RuntimeError> {
+    expect_arity("make-parameter", &args, 1, 2)?;
+    match args.get(1) {
+        Some(converter) => machine.make_parameter(args[0].clone(), converter.clone(), env),
+        None => machine.ret(Rc::new(Value::Parameter(Rc::new(Parameter::new(args[0].clone(), None)))), env)
+    }
+}
+
+fn fn_is_parameter(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("parameter?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Parameter(_)));
+    Ok((env, res))
+}
+
+// (parameterize ((param value) ...) body ...) evaluates every param and value, then runs the body
+// with each param giving its converted value until the body is left, however it's left
+fn fn_parameterize(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> Result<(), RuntimeError> {
+    let malformed = || RuntimeError::Syntax(SyntaxError::MalformedParameterize(form.expr.to_string()));
+    let bindings = match &form.parts[..] {
+        [_, bindings, _, ..] if items(&bindings.expr).is_some() => &bindings.parts,
+        _ => return Err(malformed())
+    };
+    let mut inits = Vec::new();
+    for binding in bindings.iter() {
+        match &binding.parts[..] {
+            [param, value] => {
+                inits.push(param.clone());
+                inits.push(value.clone());
+            },
+            _ => return Err(malformed())
+        }
+    }
+    machine.parameterize(Rc::new(inits), form.clone(), env)
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("make-parameter"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_make_parameter))));
+    table.insert(String::from("parameter?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_parameter))));
+    table.insert(String::from("parameterize"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_parameterize))));
+}
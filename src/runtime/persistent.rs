@@ -0,0 +1,357 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_arity};
+use super::equality::{is_equal, hash_value};
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// a value used as a map key or set member, compared with `equal?`
+#[derive(Debug, Clone)]
+pub struct Key(pub Rc<Value>);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        is_equal(&self.0, &other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, true, state);
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_values(&self.0, &other.0)
+    }
+}
+
+// values of different types are ordered by type, so sorted maps can mix key types
+fn type_rank(val: &Value) -> u8 {
+    match val {
+        Value::Nil => 0,
+        Value::Int(_) => 1,
+        Value::Float(_) => 2,
+        Value::Char(_) => 3,
+        Value::Str(_) => 4,
+        Value::Symbol(_) => 5,
+        Value::Pair(_, _) => 6,
+        Value::Vector(_) => 7,
+        Value::Bytevector(_) => 8,
+        Value::Record(_) => 9,
+        Value::Map(_) => 10,
+        Value::Set(_) => 11,
+        _ => 12
+    }
+}
+
+// what a value which is only ever equal to itself is ordered by: the object `eq?` compares
+fn identity(val: &Rc<Value>) -> usize {
+    match &**val {
+        Value::Nil => 0,
+        Value::Lambda(lambda) => Rc::as_ptr(lambda) as usize,
+        Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(f)) => *f as usize,
+        Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(f)) => *f as usize,
+        Value::HashTable(table) => Rc::as_ptr(table) as usize,
+        Value::RecordType(rtd) => Rc::as_ptr(rtd) as usize,
+        _ => Rc::as_ptr(val) as usize
+    }
+}
+
+// an order which agrees with `equal?`, so values it finds equal are the same key: containers are
+// compared by their contents, and only values compared by identity fall back on their addresses
+fn compare_values(a: &Rc<Value>, b: &Rc<Value>) -> Ordering {
+    compare_seen(a, b, &mut HashSet::new())
+}
+
+// `seen` holds the mutable containers already being compared, as in `equal?`, so cyclic vectors
+// and records are ordered without looping forever
+fn compare_seen(a: &Rc<Value>, b: &Rc<Value>, seen: &mut HashSet<(usize, usize)>) -> Ordering {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    // walks down the cdrs in a loop, so long lists don't use up the stack
+    loop {
+        if Rc::ptr_eq(&a, &b) {
+            return Ordering::Equal;
+        }
+        let (next_a, next_b) = match (&*a, &*b) {
+            (Value::Pair(x_car, x_cdr), Value::Pair(y_car, y_cdr)) => {
+                let ord = compare_seen(x_car, y_car, seen);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                (x_cdr.clone(), y_cdr.clone())
+            },
+            (Value::Int(x), Value::Int(y)) => return x.cmp(y),
+            (Value::Float(x), Value::Float(y)) => return x.total_cmp(y),
+            (Value::Char(x), Value::Char(y)) => return x.cmp(y),
+            (Value::Str(x), Value::Str(y)) => return x.cmp(y),
+            (Value::Symbol(x), Value::Symbol(y)) => return x.cmp(y),
+            (Value::Bytevector(x), Value::Bytevector(y)) => return x.borrow().cmp(&*y.borrow()),
+            (Value::Vector(x), Value::Vector(y)) => {
+                if !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    return Ordering::Equal;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                return compare_all(x.iter().zip(y.iter()), seen).then_with(|| x.len().cmp(&y.len()));
+            },
+            (Value::Record(x), Value::Record(y)) => {
+                let by_type = (Rc::as_ptr(x.record_type()) as usize).cmp(&(Rc::as_ptr(y.record_type()) as usize));
+                if by_type != Ordering::Equal || !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    return by_type;
+                }
+                let (x, y) = (x.fields(), y.fields());
+                return compare_all(x.iter().zip(y.iter()).map(|((_, a), (_, b))| (a, b)), seen);
+            },
+            (Value::Map(x), Value::Map(y)) => {
+                let (x, y) = (sorted_entries(x), sorted_entries(y));
+                let pairs = x.iter().zip(y.iter()).flat_map(|((xk, xv), (yk, yv))| vec![(&xk.0, &yk.0), (xv, yv)]);
+                return compare_all(pairs, seen).then_with(|| x.len().cmp(&y.len()));
+            },
+            (Value::Set(x), Value::Set(y)) => {
+                let (x, y) = (sorted_members(x), sorted_members(y));
+                return compare_all(x.iter().zip(y.iter()), seen).then_with(|| x.len().cmp(&y.len()));
+            },
+            (x, y) if type_rank(x) != type_rank(y) => return type_rank(x).cmp(&type_rank(y)),
+            _ => return identity(&a).cmp(&identity(&b))
+        };
+        a = next_a;
+        b = next_b;
+    }
+}
+
+// the first difference between corresponding values
+fn compare_all<'a>(pairs: impl Iterator<Item = (&'a Rc<Value>, &'a Rc<Value>)>, seen: &mut HashSet<(usize, usize)>) -> Ordering {
+    for (a, b) in pairs {
+        let ord = compare_seen(a, b, seen);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+// hashed maps and sets come out in no particular order, so are sorted before being compared
+fn sorted_entries(map: &PersistentMap) -> Vec<(Key, Rc<Value>)> {
+    let mut entries = map.entries();
+    if let PersistentMap::Hashed(_) = map {
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+    entries
+}
+
+fn sorted_members(set: &im::HashSet<Key>) -> Vec<Rc<Value>> {
+    let mut members: Vec<Key> = set.iter().cloned().collect();
+    members.sort();
+    members.into_iter().map(|key| key.0).collect()
+}
+
+// maps are hashed, like `{k v}` literals, or sorted by key
+#[derive(Debug, Clone)]
+pub enum PersistentMap {
+    Hashed(im::HashMap<Key, Rc<Value>>),
+    Sorted(im::OrdMap<Key, Rc<Value>>)
+}
+
+impl PersistentMap {
+    fn get(&self, key: &Key) -> Option<Rc<Value>> {
+        match self {
+            PersistentMap::Hashed(map) => map.get(key).cloned(),
+            PersistentMap::Sorted(map) => map.get(key).cloned()
+        }
+    }
+    fn update(&self, key: Key, value: Rc<Value>) -> Self {
+        match self {
+            PersistentMap::Hashed(map) => PersistentMap::Hashed(map.update(key, value)),
+            PersistentMap::Sorted(map) => PersistentMap::Sorted(map.update(key, value))
+        }
+    }
+    fn without(&self, key: &Key) -> Self {
+        match self {
+            PersistentMap::Hashed(map) => PersistentMap::Hashed(map.without(key)),
+            PersistentMap::Sorted(map) => PersistentMap::Sorted(map.without(key))
+        }
+    }
+    fn len(&self) -> usize {
+        match self {
+            PersistentMap::Hashed(map) => map.len(),
+            PersistentMap::Sorted(map) => map.len()
+        }
+    }
+    pub fn entries(&self) -> Vec<(Key, Rc<Value>)> {
+        match self {
+            PersistentMap::Hashed(map) => map.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            PersistentMap::Sorted(map) => map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }
+    }
+    // the same keys bound to `equal?` values, whichever way each map is ordered
+    pub fn same_entries(&self, other: &PersistentMap) -> bool {
+        self.len() == other.len() && self.entries().into_iter()
+            .all(|(k, v)| other.get(&k).is_some_and(|w| is_equal(&v, &w)))
+    }
+    fn keys(&self) -> Vec<Rc<Value>> {
+        match self {
+            PersistentMap::Hashed(map) => map.keys().map(|k| k.0.clone()).collect(),
+            PersistentMap::Sorted(map) => map.keys().map(|k| k.0.clone()).collect()
+        }
+    }
+}
+
+// builds a hashed map from alternating keys and values
+pub fn hash_map(items: Vec<Rc<Value>>) -> Rc<Value> {
+    let map = items.chunks(2)
+        .map(|kv| (Key(kv[0].clone()), kv[1].clone()))
+        .collect();
+    Rc::new(Value::Map(PersistentMap::Hashed(map)))
+}
+
+pub fn hash_set(items: Vec<Rc<Value>>) -> Rc<Value> {
+    Rc::new(Value::Set(items.into_iter().map(Key).collect()))
+}
+
+fn expect_map(name: &str, val: &Value) -> Result<PersistentMap, RuntimeError> {
+    match val {
+        Value::Map(map) => Ok(map.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a map, got {}", name, val)))
+    }
+}
+
+fn expect_set(name: &str, val: &Value) -> Result<im::HashSet<Key>, RuntimeError> {
+    match val {
+        Value::Set(set) => Ok(set.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a set, got {}", name, val)))
+    }
+}
+
+fn expect_pairs(name: &str, args: &[Rc<Value>]) -> Result<(), RuntimeError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(RuntimeError::ArityError(format!("{} expects alternating keys and values", name)));
+    }
+    Ok(())
+}
+
+fn fn_hash_map(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_pairs("hash-map", &args)?;
+    Ok((env, hash_map(args)))
+}
+
+fn fn_sorted_map(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_pairs("sorted-map", &args)?;
+    let map = args.chunks(2)
+        .map(|kv| (Key(kv[0].clone()), kv[1].clone()))
+        .collect();
+    Ok((env, Rc::new(Value::Map(PersistentMap::Sorted(map)))))
+}
+
+// (map-assoc map k v ...) returns a new map sharing structure with the old one
+fn fn_map_assoc(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("map-assoc", &args, 1, usize::MAX)?;
+    expect_pairs("map-assoc", &args[1..])?;
+    let mut map = expect_map("map-assoc", &args[0])?;
+    for kv in args[1..].chunks(2) {
+        map = map.update(Key(kv[0].clone()), kv[1].clone());
+    }
+    Ok((env, Rc::new(Value::Map(map))))
+}
+
+fn fn_map_dissoc(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("map-dissoc", &args, 1, usize::MAX)?;
+    let mut map = expect_map("map-dissoc", &args[0])?;
+    for key in args[1..].iter() {
+        map = map.without(&Key(key.clone()));
+    }
+    Ok((env, Rc::new(Value::Map(map))))
+}
+
+// (map-get map k [default]) is nil for missing keys unless a default is given
+fn fn_map_get(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("map-get", &args, 2, 3)?;
+    let map = expect_map("map-get", &args[0])?;
+    let res = map.get(&Key(args[1].clone()))
+        .or_else(|| args.get(2).cloned())
+        .unwrap_or_else(|| Rc::new(Value::Nil));
+    Ok((env, res))
+}
+
+fn fn_map_contains(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("map-contains?", &args, 2, 2)?;
+    let map = expect_map("map-contains?", &args[0])?;
+    let res = boolean(&env, map.get(&Key(args[1].clone())).is_some());
+    Ok((env, res))
+}
+
+fn fn_map_keys(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("map-keys", &args, 1, 1)?;
+    let map = expect_map("map-keys", &args[0])?;
+    Ok((env, Value::list(map.keys())))
+}
+
+fn fn_hash_set(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, hash_set(args)))
+}
+
+fn fn_set_add(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("set-add", &args, 1, usize::MAX)?;
+    let mut set = expect_set("set-add", &args[0])?;
+    for item in args[1..].iter() {
+        set = set.update(Key(item.clone()));
+    }
+    Ok((env, Rc::new(Value::Set(set))))
+}
+
+fn fn_set_contains(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("set-contains?", &args, 2, 2)?;
+    let set = expect_set("set-contains?", &args[0])?;
+    let res = boolean(&env, set.contains(&Key(args[1].clone())));
+    Ok((env, res))
+}
+
+fn fn_set_union(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let sets: Result<Vec<im::HashSet<Key>>, RuntimeError> = args.iter().map(|arg| expect_set("set-union", arg)).collect();
+    let union = im::HashSet::unions(sets?);
+    Ok((env, Rc::new(Value::Set(union))))
+}
+
+fn fn_set_intersection(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("set-intersection", &args, 1, usize::MAX)?;
+    let mut sets = args.iter().map(|arg| expect_set("set-intersection", arg));
+    let mut intersection = sets.next().unwrap()?;
+    for set in sets {
+        intersection = intersection.intersection(set?);
+    }
+    Ok((env, Rc::new(Value::Set(intersection))))
+}
+
+fn fn_set_to_list(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("set->list", &args, 1, 1)?;
+    let set = expect_set("set->list", &args[0])?;
+    Ok((env, Value::list(set.into_iter().map(|k| k.0).collect())))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("hash-map"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_map))));
+    table.insert(String::from("sorted-map"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_sorted_map))));
+    table.insert(String::from("map-assoc"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_map_assoc))));
+    table.insert(String::from("map-dissoc"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_map_dissoc))));
+    table.insert(String::from("map-get"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_map_get))));
+    table.insert(String::from("map-contains?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_map_contains))));
+    table.insert(String::from("map-keys"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_map_keys))));
+    table.insert(String::from("hash-set"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_hash_set))));
+    table.insert(String::from("set-add"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_set_add))));
+    table.insert(String::from("set-contains?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_set_contains))));
+    table.insert(String::from("set-union"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_set_union))));
+    table.insert(String::from("set-intersection"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_set_intersection))));
+    table.insert(String::from("set->list"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_set_to_list))));
+}
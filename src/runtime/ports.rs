@@ -0,0 +1,581 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::{self, Machine};
+use super::stdlib::{boolean, expect_arity, expect_str, expect_int};
+use super::reader::{read_datum, Datum};
+use super::parameters::Parameter;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::rc::{Rc, Weak};
+
+pub type PortRef = Rc<RefCell<Port>>;
+
+// text read from a source a line at a time, so characters can be peeked at and
+// data spanning several lines can be read
+pub struct Input {
+    source: Box<dyn BufRead>,
+    buffer: String,
+    eof: bool
+}
+
+impl Input {
+    fn new(source: Box<dyn BufRead>) -> Self {
+        Input {
+            source,
+            buffer: String::new(),
+            eof: false
+        }
+    }
+    // reads another line onto the buffer, returning false once the source is exhausted
+    fn fill(&mut self) -> Result<bool, RuntimeError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let read = self.source.read_line(&mut self.buffer)
+            .map_err(|why| RuntimeError::IOError(format!("couldn't read input: {}", why)))?;
+        self.eof = read == 0;
+        Ok(!self.eof)
+    }
+    pub fn peek_char(&mut self) -> Result<Option<char>, RuntimeError> {
+        while self.buffer.is_empty() {
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.buffer.chars().next())
+    }
+    pub fn read_char(&mut self) -> Result<Option<char>, RuntimeError> {
+        let c = self.peek_char()?;
+        if let Some(c) = c {
+            self.buffer.drain(..c.len_utf8());
+        }
+        Ok(c)
+    }
+    // a line without its line ending
+    pub fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+        while !self.buffer.contains('\n') {
+            if !self.fill()? {
+                break;
+            }
+        }
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+        let end = self.buffer.find('\n').map_or(self.buffer.len(), |nl| nl + 1);
+        let line: String = self.buffer.drain(..end).collect();
+        Ok(Some(line.trim_end_matches('\n').trim_end_matches('\r').to_string()))
+    }
+    // up to `k` characters, fewer only at the end of the input
+    pub fn read_string(&mut self, k: usize) -> Result<Option<String>, RuntimeError> {
+        let mut res = String::new();
+        while res.chars().count() < k {
+            match self.read_char()? {
+                Some(c) => res.push(c),
+                None => break
+            }
+        }
+        Ok(if res.is_empty() && k > 0 { None } else { Some(res) })
+    }
+    // the next datum, or `None` at the end of the input
+    pub fn read(&mut self) -> Result<Option<Rc<Value>>, RuntimeError> {
+        loop {
+            match read_datum(&self.buffer)? {
+                Datum::Complete(val, end) => {
+                    self.buffer.drain(..end);
+                    return Ok(Some(val));
+                },
+                Datum::Empty if self.eof => {
+                    self.buffer.clear();
+                    return Ok(None);
+                },
+                Datum::Incomplete if self.eof => return Err(RuntimeError::ReadError(String::from("input ends partway through a datum"))),
+                Datum::Empty | Datum::Incomplete => {
+                    self.fill()?;
+                }
+            }
+        }
+    }
+}
+
+pub enum Sink {
+    Stdout,
+    Stderr,
+    File(BufWriter<File>),
+    Text(String),
+    Bytes(Vec<u8>)
+}
+
+pub enum Port {
+    TextInput(Input),
+    BinaryInput(Box<dyn BufRead>),
+    // file sinks can be textual or binary, the rest are one or the other
+    Output(Sink, bool),
+    Closed { input: bool, binary: bool }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Port({})", self.describe())
+    }
+}
+
+fn io_error(why: io::Error) -> RuntimeError {
+    RuntimeError::IOError(why.to_string())
+}
+
+impl Port {
+    pub fn is_input(&self) -> bool {
+        match self {
+            Port::TextInput(_) | Port::BinaryInput(_) => true,
+            Port::Output(_, _) => false,
+            Port::Closed { input, .. } => *input
+        }
+    }
+    pub fn is_binary(&self) -> bool {
+        match self {
+            Port::TextInput(_) => false,
+            Port::BinaryInput(_) => true,
+            Port::Output(_, binary) => *binary,
+            Port::Closed { binary, .. } => *binary
+        }
+    }
+    pub fn is_open(&self) -> bool {
+        !matches!(self, Port::Closed { .. })
+    }
+    // e.g. "textual input port", as printed in `#<textual input port>`
+    pub fn describe(&self) -> String {
+        format!("{}{} {} port",
+            if self.is_open() { "" } else { "closed " },
+            if self.is_binary() { "binary" } else { "textual" },
+            if self.is_input() { "input" } else { "output" })
+    }
+    pub fn close(&mut self) -> Result<(), RuntimeError> {
+        if let Port::Output(Sink::File(file), _) = self {
+            file.flush().map_err(io_error)?;
+        }
+        *self = Port::Closed { input: self.is_input(), binary: self.is_binary() };
+        Ok(())
+    }
+    fn closed_error(name: &str) -> RuntimeError {
+        RuntimeError::IOError(format!("{}: port is closed", name))
+    }
+    pub fn text_input(&mut self, name: &str) -> Result<&mut Input, RuntimeError> {
+        match self {
+            Port::TextInput(input) => Ok(input),
+            Port::Closed { .. } => Err(Port::closed_error(name)),
+            _ => Err(RuntimeError::TypeError(format!("{} expects a textual input port", name)))
+        }
+    }
+    pub fn binary_input(&mut self, name: &str) -> Result<&mut Box<dyn BufRead>, RuntimeError> {
+        match self {
+            Port::BinaryInput(input) => Ok(input),
+            Port::Closed { .. } => Err(Port::closed_error(name)),
+            _ => Err(RuntimeError::TypeError(format!("{} expects a binary input port", name)))
+        }
+    }
+    fn sink(&mut self, name: &str, binary: bool) -> Result<&mut Sink, RuntimeError> {
+        match self {
+            Port::Output(sink, b) if *b == binary => Ok(sink),
+            Port::Closed { .. } => Err(Port::closed_error(name)),
+            _ => Err(RuntimeError::TypeError(format!("{} expects a {} output port", name, if binary { "binary" } else { "textual" })))
+        }
+    }
+    pub fn write_text(&mut self, name: &str, text: &str) -> Result<(), RuntimeError> {
+        match self.sink(name, false)? {
+            Sink::Text(s) => s.push_str(text),
+            sink => sink.write_all(text.as_bytes())?
+        }
+        Ok(())
+    }
+    pub fn write_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<(), RuntimeError> {
+        self.sink(name, true)?.write_all(bytes)
+    }
+    pub fn flush(&mut self) -> Result<(), RuntimeError> {
+        match self {
+            Port::Output(Sink::File(file), _) => file.flush().map_err(io_error),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Sink {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), RuntimeError> {
+        let res = match self {
+            Sink::Stdout => io::stdout().write_all(bytes).and_then(|_| io::stdout().flush()),
+            Sink::Stderr => io::stderr().write_all(bytes),
+            Sink::File(file) => file.write_all(bytes),
+            Sink::Text(s) => {
+                s.push_str(&String::from_utf8_lossy(bytes));
+                Ok(())
+            },
+            Sink::Bytes(buffer) => {
+                buffer.extend_from_slice(bytes);
+                Ok(())
+            }
+        };
+        res.map_err(io_error)
+    }
+}
+
+fn new_port(port: Port) -> PortRef {
+    Rc::new(RefCell::new(port))
+}
+
+fn port_value(port: PortRef) -> Rc<Value> {
+    Rc::new(Value::Port(port))
+}
+
+// the current ports are parameters, so parameterize can change them, and only take ports
+fn current_port(port: PortRef) -> Rc<Parameter> {
+    let converter = Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_as_port)));
+    Rc::new(Parameter::new(port_value(port), Some(converter)))
+}
+
+thread_local! {
+    static CURRENT_INPUT: Rc<Parameter> = current_port(new_port(Port::TextInput(Input::new(Box::new(BufReader::new(io::stdin()))))));
+    static CURRENT_OUTPUT: Rc<Parameter> = current_port(new_port(Port::Output(Sink::Stdout, false)));
+    static CURRENT_ERROR: Rc<Parameter> = current_port(new_port(Port::Output(Sink::Stderr, false)));
+    // every file output port opened, so buffered output isn't lost when the process exits
+    static FILE_OUTPUTS: RefCell<Vec<Weak<RefCell<Port>>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn file_output(file: File, binary: bool) -> PortRef {
+    let port = new_port(Port::Output(Sink::File(BufWriter::new(file)), binary));
+    FILE_OUTPUTS.with(|outputs| {
+        let mut outputs = outputs.borrow_mut();
+        outputs.retain(|port| port.strong_count() > 0);
+        outputs.push(Rc::downgrade(&port));
+    });
+    port
+}
+
+// flushes every file output port still open, ignoring any which fail
+pub fn flush_all() {
+    FILE_OUTPUTS.with(|outputs| {
+        for port in outputs.borrow().iter().filter_map(|port| port.upgrade()) {
+            if let Ok(mut port) = port.try_borrow_mut() {
+                let _ = port.flush();
+            }
+        }
+    });
+}
+
+fn parameter_port(param: &Rc<Parameter>) -> PortRef {
+    match &*machine::current_value(param) {
+        Value::Port(port) => port.clone(),
+        _ => unreachable!("the current port parameters convert their values to ports")
+    }
+}
+
+pub fn current_input() -> PortRef {
+    CURRENT_INPUT.with(parameter_port)
+}
+
+pub fn current_output() -> PortRef {
+    CURRENT_OUTPUT.with(parameter_port)
+}
+
+pub fn expect_port(name: &str, val: &Value) -> Result<PortRef, RuntimeError> {
+    match val {
+        Value::Port(port) => Ok(port.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a port, got {}", name, val)))
+    }
+}
+
+// the optional port argument at `k`, or the current port when it's left out
+pub fn port_arg(name: &str, args: &[Rc<Value>], k: usize, current: fn () -> PortRef) -> Result<PortRef, RuntimeError> {
+    match args.get(k) {
+        Some(port) => expect_port(name, port),
+        None => Ok(current())
+    }
+}
+
+fn open_file(name: &str, path: &str) -> Result<File, RuntimeError> {
+    File::open(path).map_err(|why| RuntimeError::IOError(format!("{}: couldn't open {}: {}", name, path, why)))
+}
+
+fn create_file(name: &str, path: &str) -> Result<File, RuntimeError> {
+    File::create(path).map_err(|why| RuntimeError::IOError(format!("{}: couldn't create {}: {}", name, path, why)))
+}
+
+pub fn open_input_file(name: &str, path: &str) -> Result<PortRef, RuntimeError> {
+    let file = open_file(name, path)?;
+    Ok(new_port(Port::TextInput(Input::new(Box::new(BufReader::new(file))))))
+}
+
+pub fn open_output_file(name: &str, path: &str) -> Result<PortRef, RuntimeError> {
+    Ok(file_output(create_file(name, path)?, false))
+}
+
+// the binding which parameterizes the current output or input port to `port`
+pub fn output_binding(port: PortRef) -> (Rc<Parameter>, Rc<Value>) {
+    (CURRENT_OUTPUT.with(|param| param.clone()), port_value(port))
+}
+
+pub fn input_binding(port: PortRef) -> (Rc<Parameter>, Rc<Value>) {
+    (CURRENT_INPUT.with(|param| param.clone()), port_value(port))
+}
+
+fn fn_open_input_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-input-string", &args, 1, 1)?;
+    let s = expect_str("open-input-string", &args[0])?.clone();
+    let port = new_port(Port::TextInput(Input::new(Box::new(Cursor::new(s.into_bytes())))));
+    Ok((env, port_value(port)))
+}
+
+fn fn_open_output_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-output-string", &args, 0, 0)?;
+    Ok((env, port_value(new_port(Port::Output(Sink::Text(String::new()), false)))))
+}
+
+fn fn_get_output_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("get-output-string", &args, 1, 1)?;
+    let port = expect_port("get-output-string", &args[0])?;
+    let res = match &*port.borrow() {
+        Port::Output(Sink::Text(s), _) => s.clone(),
+        _ => return Err(RuntimeError::TypeError(String::from("get-output-string expects a port from open-output-string")))
+    };
+    Ok((env, Rc::new(Value::Str(res))))
+}
+
+fn fn_open_input_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-input-bytevector", &args, 1, 1)?;
+    let bytes = match &*args[0] {
+        Value::Bytevector(bytes) => bytes.borrow().clone(),
+        other => return Err(RuntimeError::TypeError(format!("open-input-bytevector expects a bytevector, got {}", other)))
+    };
+    Ok((env, port_value(new_port(Port::BinaryInput(Box::new(Cursor::new(bytes)))))))
+}
+
+fn fn_open_output_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-output-bytevector", &args, 0, 0)?;
+    Ok((env, port_value(new_port(Port::Output(Sink::Bytes(Vec::new()), true)))))
+}
+
+fn fn_get_output_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("get-output-bytevector", &args, 1, 1)?;
+    let port = expect_port("get-output-bytevector", &args[0])?;
+    let res = match &*port.borrow() {
+        Port::Output(Sink::Bytes(bytes), _) => bytes.clone(),
+        _ => return Err(RuntimeError::TypeError(String::from("get-output-bytevector expects a port from open-output-bytevector")))
+    };
+    Ok((env, Rc::new(Value::Bytevector(Rc::new(RefCell::new(res))))))
+}
+
+fn fn_open_input_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-input-file", &args, 1, 1)?;
+    let port = open_input_file("open-input-file", expect_str("open-input-file", &args[0])?)?;
+    Ok((env, port_value(port)))
+}
+
+fn fn_open_binary_input_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-binary-input-file", &args, 1, 1)?;
+    let file = open_file("open-binary-input-file", expect_str("open-binary-input-file", &args[0])?)?;
+    Ok((env, port_value(new_port(Port::BinaryInput(Box::new(BufReader::new(file)))))))
+}
+
+fn fn_open_output_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-output-file", &args, 1, 1)?;
+    let port = open_output_file("open-output-file", expect_str("open-output-file", &args[0])?)?;
+    Ok((env, port_value(port)))
+}
+
+fn fn_open_binary_output_file(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("open-binary-output-file", &args, 1, 1)?;
+    let file = create_file("open-binary-output-file", expect_str("open-binary-output-file", &args[0])?)?;
+    Ok((env, port_value(file_output(file, true))))
+}
+
+fn fn_close_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("close-port", &args, 1, 1)?;
+    expect_port("close-port", &args[0])?.borrow_mut().close()?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_close_input_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("close-input-port", &args, 1, 1)?;
+    let port = expect_port("close-input-port", &args[0])?;
+    if !port.borrow().is_input() {
+        return Err(RuntimeError::TypeError(String::from("close-input-port expects an input port")));
+    }
+    port.borrow_mut().close()?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_close_output_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("close-output-port", &args, 1, 1)?;
+    let port = expect_port("close-output-port", &args[0])?;
+    if port.borrow().is_input() {
+        return Err(RuntimeError::TypeError(String::from("close-output-port expects an output port")));
+    }
+    port.borrow_mut().close()?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+// the port predicates are false for anything that isn't a port
+fn port_predicate(env: Rc<Env>, args: Vec<Rc<Value>>, name: &str, pred: fn (&Port) -> bool) -> EvalResult {
+    expect_arity(name, &args, 1, 1)?;
+    let holds = match &*args[0] {
+        Value::Port(port) => pred(&port.borrow()),
+        _ => false
+    };
+    let res = boolean(&env, holds);
+    Ok((env, res))
+}
+
+fn fn_is_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "port?", |_| true)
+}
+
+fn fn_is_input_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "input-port?", |port| port.is_input())
+}
+
+fn fn_is_output_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "output-port?", |port| !port.is_input())
+}
+
+fn fn_is_textual_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "textual-port?", |port| !port.is_binary())
+}
+
+fn fn_is_binary_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "binary-port?", |port| port.is_binary())
+}
+
+fn fn_is_input_port_open(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "input-port-open?", |port| port.is_input() && port.is_open())
+}
+
+fn fn_is_output_port_open(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    port_predicate(env, args, "output-port-open?", |port| !port.is_input() && port.is_open())
+}
+
+// the converter of the current port parameters
+fn fn_as_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("current port", &args, 1, 1)?;
+    expect_port("current port", &args[0])?;
+    Ok((env, args[0].clone()))
+}
+
+// (with-output-to-string thunk) is everything the thunk writes to the current output port
+fn fn_with_output_to_string(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("with-output-to-string", &args, 1, 1)?;
+    let port = new_port(Port::Output(Sink::Text(String::new()), false));
+    machine.call_parameterized(vec![output_binding(port.clone())], args[0].clone(), Vec::new(), env, move |machine, _, env| {
+        let res = match &*port.borrow() {
+            Port::Output(Sink::Text(s), _) => s.clone(),
+            _ => String::new()
+        };
+        machine.ret(Rc::new(Value::Str(res)), env)
+    })
+}
+
+fn fn_flush_output_port(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("flush-output-port", &args, 0, 1)?;
+    port_arg("flush-output-port", &args, 0, current_output)?.borrow_mut().flush()?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn eof_or(val: Option<Rc<Value>>) -> Rc<Value> {
+    val.unwrap_or_else(|| Rc::new(Value::Eof))
+}
+
+fn fn_read_u8(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read-u8", &args, 0, 1)?;
+    let port = port_arg("read-u8", &args, 0, current_input)?;
+    let mut port = port.borrow_mut();
+    let input = port.binary_input("read-u8")?;
+    let byte = input.fill_buf().map_err(io_error)?.first().cloned();
+    if byte.is_some() {
+        input.consume(1);
+    }
+    Ok((env, eof_or(byte.map(|b| Rc::new(Value::Int(b as i32))))))
+}
+
+fn fn_peek_u8(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("peek-u8", &args, 0, 1)?;
+    let port = port_arg("peek-u8", &args, 0, current_input)?;
+    let mut port = port.borrow_mut();
+    let byte = port.binary_input("peek-u8")?.fill_buf().map_err(io_error)?.first().cloned();
+    Ok((env, eof_or(byte.map(|b| Rc::new(Value::Int(b as i32))))))
+}
+
+fn fn_read_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("read-bytevector", &args, 1, 2)?;
+    let k = expect_int("read-bytevector", &args[0])?.max(0) as usize;
+    let port = port_arg("read-bytevector", &args, 1, current_input)?;
+    let mut port = port.borrow_mut();
+    let input = port.binary_input("read-bytevector")?;
+    let mut bytes = Vec::new();
+    while bytes.len() < k {
+        let available = input.fill_buf().map_err(io_error)?;
+        if available.is_empty() {
+            break;
+        }
+        let n = available.len().min(k - bytes.len());
+        bytes.extend_from_slice(&available[..n]);
+        input.consume(n);
+    }
+    if bytes.is_empty() && k > 0 {
+        return Ok((env, Rc::new(Value::Eof)));
+    }
+    Ok((env, Rc::new(Value::Bytevector(Rc::new(RefCell::new(bytes))))))
+}
+
+fn fn_write_u8(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("write-u8", &args, 1, 2)?;
+    let byte = expect_int("write-u8", &args[0])?;
+    if !(0..256).contains(&byte) {
+        return Err(RuntimeError::RangeError(format!("write-u8: {} is not a byte", byte)));
+    }
+    port_arg("write-u8", &args, 1, current_output)?.borrow_mut().write_bytes("write-u8", &[byte as u8])?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_write_bytevector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("write-bytevector", &args, 1, 2)?;
+    let bytes = match &*args[0] {
+        Value::Bytevector(bytes) => bytes.borrow().clone(),
+        other => return Err(RuntimeError::TypeError(format!("write-bytevector expects a bytevector, got {}", other)))
+    };
+    port_arg("write-bytevector", &args, 1, current_output)?.borrow_mut().write_bytes("write-bytevector", &bytes)?;
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("open-input-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_input_string))));
+    table.insert(String::from("open-output-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_output_string))));
+    table.insert(String::from("get-output-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_get_output_string))));
+    table.insert(String::from("open-input-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_input_bytevector))));
+    table.insert(String::from("open-output-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_output_bytevector))));
+    table.insert(String::from("get-output-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_get_output_bytevector))));
+    table.insert(String::from("open-input-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_input_file))));
+    table.insert(String::from("open-binary-input-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_binary_input_file))));
+    table.insert(String::from("open-output-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_output_file))));
+    table.insert(String::from("open-binary-output-file"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_open_binary_output_file))));
+    table.insert(String::from("close-port"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_close_port))));
+    table.insert(String::from("close-input-port"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_close_input_port))));
+    table.insert(String::from("close-output-port"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_close_output_port))));
+    table.insert(String::from("port?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_port))));
+    table.insert(String::from("input-port?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_input_port))));
+    table.insert(String::from("output-port?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_output_port))));
+    table.insert(String::from("textual-port?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_textual_port))));
+    table.insert(String::from("binary-port?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_binary_port))));
+    table.insert(String::from("input-port-open?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_input_port_open))));
+    table.insert(String::from("output-port-open?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_output_port_open))));
+    table.insert(String::from("current-input-port"), CURRENT_INPUT.with(|param| Rc::new(Value::Parameter(param.clone()))));
+    table.insert(String::from("current-output-port"), CURRENT_OUTPUT.with(|param| Rc::new(Value::Parameter(param.clone()))));
+    table.insert(String::from("current-error-port"), CURRENT_ERROR.with(|param| Rc::new(Value::Parameter(param.clone()))));
+    table.insert(String::from("with-output-to-string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_with_output_to_string))));
+    table.insert(String::from("flush-output-port"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_flush_output_port))));
+    table.insert(String::from("read-u8"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read_u8))));
+    table.insert(String::from("peek-u8"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_peek_u8))));
+    table.insert(String::from("read-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_read_bytevector))));
+    table.insert(String::from("write-u8"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_write_u8))));
+    table.insert(String::from("write-bytevector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_write_bytevector))));
+}
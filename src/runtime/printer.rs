@@ -0,0 +1,334 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Value, RuntimeFunctionWrapper};
+use super::super::parser::expressions::Expression;
+use super::stdlib::{is_false, is_true};
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+thread_local! {
+    // the names builtins were registered under, keyed by function address
+    static BUILTIN_NAMES: RefCell<std::collections::HashMap<usize, String>> = RefCell::new(std::collections::HashMap::new());
+}
+
+fn builtin_address(wrapper: &RuntimeFunctionWrapper) -> usize {
+    match wrapper {
+        RuntimeFunctionWrapper::Immediate(f) => *f as usize,
+        RuntimeFunctionWrapper::Symbolic(f) => *f as usize,
+        RuntimeFunctionWrapper::Control(f) => *f as usize,
+        RuntimeFunctionWrapper::Syntax(f) => *f as usize
+    }
+}
+
+// names bound to a builtin which is already registered under another name, and so never
+// the one it prints as
+const ALIASES: &[&str] = &["call/cc", "call/ec"];
+
+// remembers the canonical name of every builtin in `table`, so they print as `#<builtin +>`
+pub fn register_builtin_names(table: &HashMap<String, Rc<Value>>) {
+    BUILTIN_NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        for (name, value) in table.iter() {
+            if let Value::RuntimeFunction(wrapper) = &**value {
+                if ALIASES.contains(&name.as_str()) {
+                    continue;
+                }
+                let previous = names.insert(builtin_address(wrapper), name.to_string());
+                debug_assert!(previous.is_none(), "builtin {} is also bound as {:?}, which isn't listed as an alias", name, previous);
+            }
+        }
+    });
+}
+
+fn builtin_name(wrapper: &RuntimeFunctionWrapper) -> Option<String> {
+    BUILTIN_NAMES.with(|names| names.borrow().get(&builtin_address(wrapper)).cloned())
+}
+
+// `write` prints values the way the reader would read them back, `display` prints strings
+// and characters as their bare contents
+pub fn write_value(val: &Value) -> String {
+    Printer::new(val, false).print(val)
+}
+
+pub fn display_value(val: &Value) -> String {
+    Printer::new(val, true).print(val)
+}
+
+// `{}` formats a value as `write` would, `{:#}` as `display` would
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            f.write_str(&display_value(self))
+        } else {
+            f.write_str(&write_value(self))
+        }
+    }
+}
+
+// `{}` formats an expression as the source that would parse back to it, for error messages
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::ListExpr(items) if items.is_empty() => f.write_str("()"),
+            Expression::ListExpr(items) => write!(f, "'({})", join(items)),
+            Expression::VectorExpr(items) => write!(f, "#({})", join(items)),
+            Expression::BytevectorLiteral(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                write!(f, "#u8({})", bytes.join(" "))
+            },
+            Expression::MapExpr(items) => write!(f, "{{{}}}", join(items)),
+            Expression::SetExpr(items) => write!(f, "#{{{}}}", join(items)),
+            Expression::SExpr(rator, rands) if rands.is_empty() => write!(f, "({})", rator),
+            Expression::SExpr(rator, rands) => write!(f, "({} {})", rator, join(rands)),
+            Expression::LetExpr(name, value) => write!(f, "(let {} {})", name, value),
+            Expression::LambdaExpr(arg_names, body) => write!(f, "(lambda ({}) {})", arg_names.join(" "), body),
+            Expression::BeginExpr(body) if body.is_empty() => f.write_str("(begin)"),
+            Expression::BeginExpr(body) => write!(f, "(begin {})", join(body)),
+            Expression::LookupExpr(name) => f.write_str(name),
+            Expression::IntegerLiteral(i) => write!(f, "{}", i),
+            Expression::FloatLiteral(x) => f.write_str(&float_string(*x)),
+            Expression::StringLiteral(s) => f.write_str(&string_literal(s)),
+            Expression::CharLiteral(c) => f.write_str(&char_name(*c)),
+            Expression::BoolLiteral(b) => f.write_str(if *b { "#t" } else { "#f" })
+        }
+    }
+}
+
+fn join(exprs: &[Rc<Expression>]) -> String {
+    let exprs: Vec<String> = exprs.iter().map(|expr| expr.to_string()).collect();
+    exprs.join(" ")
+}
+
+// a special form as written, for saying which one was malformed
+pub fn form(keyword: &str, args: &[Rc<Expression>]) -> String {
+    if args.is_empty() {
+        format!("({})", keyword)
+    } else {
+        format!("({} {})", keyword, join(args))
+    }
+}
+
+// vectors and records are the only mutable containers, so every cycle runs through one of them
+fn container_id(val: &Value) -> Option<usize> {
+    match val {
+        Value::Vector(slots) => Some(Rc::as_ptr(slots) as *const u8 as usize),
+        Value::Record(record) => Some(Rc::as_ptr(record) as *const u8 as usize),
+        _ => None
+    }
+}
+
+fn children(val: &Value) -> Vec<Rc<Value>> {
+    match val {
+        Value::Pair(car, cdr) => vec![car.clone(), cdr.clone()],
+        Value::Vector(slots) => slots.borrow().clone(),
+        Value::Record(record) => record.fields().into_iter().map(|(_, value)| value).collect(),
+        Value::Map(map) => map.entries().into_iter().flat_map(|(k, v)| vec![k.0, v]).collect(),
+        Value::Set(set) => set.iter().map(|k| k.0.clone()).collect(),
+        _ => Vec::new()
+    }
+}
+
+struct Printer {
+    display: bool,
+    // containers which contain themselves, and the datum label each has been given so far
+    cyclic: HashSet<usize>,
+    labels: std::collections::HashMap<usize, usize>,
+    out: String
+}
+
+impl Printer {
+    fn new(val: &Value, display: bool) -> Self {
+        let mut cyclic = HashSet::new();
+        find_cycles(val, &mut HashSet::new(), &mut HashSet::new(), &mut cyclic);
+        Printer {
+            display,
+            cyclic,
+            labels: std::collections::HashMap::new(),
+            out: String::new()
+        }
+    }
+    fn print(mut self, val: &Value) -> String {
+        self.value(val);
+        self.out
+    }
+    fn value(&mut self, val: &Value) {
+        if let Some(id) = container_id(val).filter(|id| self.cyclic.contains(id)) {
+            if let Some(label) = self.labels.get(&id) {
+                self.out.push_str(&format!("#{}#", label));
+                return;
+            }
+            let label = self.labels.len();
+            self.labels.insert(id, label);
+            self.out.push_str(&format!("#{}=", label));
+        }
+        match val {
+            Value::Nil => self.out.push_str("()"),
+            Value::Int(i) => self.out.push_str(&i.to_string()),
+            Value::Float(f) => self.out.push_str(&float_string(*f)),
+            Value::Char(c) if self.display => self.out.push(*c),
+            Value::Char(c) => self.out.push_str(&char_name(*c)),
+            Value::Str(s) if self.display => self.out.push_str(s),
+            Value::Str(s) => self.out.push_str(&string_literal(s)),
+            Value::Symbol(name) => self.out.push_str(name),
+            Value::Eof => self.out.push_str("#<eof>"),
+            Value::Pair(_, _) => self.list(val),
+            Value::Vector(slots) => {
+                let slots = slots.borrow().clone();
+                self.sequence("#(", &slots, ")");
+            },
+            Value::Bytevector(bytes) => {
+                let bytes: Vec<String> = bytes.borrow().iter().map(|b| b.to_string()).collect();
+                self.out.push_str(&format!("#u8({})", bytes.join(" ")));
+            },
+            Value::Map(_) => self.sequence("{", &children(val), "}"),
+            Value::Set(_) => self.sequence("#{", &children(val), "}"),
+            Value::Record(record) => {
+                self.out.push_str(&format!("#<{}", record.record_type().short_name()));
+                for (name, value) in record.fields() {
+                    self.out.push_str(&format!(" {}: ", name));
+                    self.value(&value);
+                }
+                self.out.push('>');
+            },
+            Value::RecordType(rtd) => self.out.push_str(&format!("#<record-type {}>", rtd.short_name())),
+            Value::HashTable(_) => self.out.push_str("#<hash-table>"),
+            Value::Port(port) => self.out.push_str(&format!("#<{}>", port.borrow().describe())),
+            Value::Condition(condition) => {
+                self.out.push_str(&format!("#<{} {}", condition.kind(), string_literal(condition.message())));
+                for irritant in condition.irritants() {
+                    self.out.push(' ');
+                    self.value(irritant);
+                }
+                self.out.push('>');
+            },
+            Value::Continuation(_) => self.out.push_str("#<continuation>"),
+            Value::Promise(_) => self.out.push_str("#<promise>"),
+            Value::Parameter(_) => self.out.push_str("#<parameter>"),
+            Value::Values(vals) => self.sequence("", vals, ""),
+            Value::Lambda(lambda) => match lambda.name() {
+                Some(name) => self.out.push_str(&format!("#<procedure {}>", name)),
+                None => self.out.push_str("#<procedure>")
+            },
+            _ if is_true(val) => self.out.push_str("#t"),
+            _ if is_false(val) => self.out.push_str("#f"),
+            Value::RuntimeFunction(wrapper) => match builtin_name(wrapper) {
+                Some(name) => self.out.push_str(&format!("#<builtin {}>", name)),
+                None => self.out.push_str("#<builtin>")
+            }
+        }
+    }
+    fn sequence(&mut self, open: &str, items: &[Rc<Value>], close: &str) {
+        self.out.push_str(open);
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+            }
+            self.value(item);
+        }
+        self.out.push_str(close);
+    }
+    // lists are walked down their cdrs in a loop, so long ones don't use up the stack
+    fn list(&mut self, val: &Value) {
+        self.out.push('(');
+        let mut runner = val;
+        while let Value::Pair(car, cdr) = runner {
+            self.value(car);
+            match &**cdr {
+                Value::Nil => break,
+                Value::Pair(_, _) => self.out.push(' '),
+                tail => {
+                    self.out.push_str(" . ");
+                    self.value(tail);
+                    break;
+                }
+            }
+            runner = cdr;
+        }
+        self.out.push(')');
+    }
+}
+
+// marks the containers reached again while still inside themselves; `done` holds those
+// already fully explored, so shared structure is only walked once
+fn find_cycles(val: &Value, on_stack: &mut HashSet<usize>, done: &mut HashSet<usize>, cyclic: &mut HashSet<usize>) {
+    let mut runner = Rc::new(val.clone());
+    loop {
+        let id = container_id(&runner);
+        if let Some(id) = id {
+            if on_stack.contains(&id) {
+                cyclic.insert(id);
+                return;
+            }
+            if !done.insert(id) {
+                return;
+            }
+        }
+        let next = match &*runner {
+            Value::Pair(car, cdr) => {
+                find_cycles(car, on_stack, done, cyclic);
+                cdr.clone()
+            },
+            other => {
+                on_stack.extend(id);
+                for child in children(other) {
+                    find_cycles(&child, on_stack, done, cyclic);
+                }
+                if let Some(id) = id {
+                    on_stack.remove(&id);
+                }
+                return;
+            }
+        };
+        runner = next;
+    }
+}
+
+fn float_string(f: f32) -> String {
+    if f.is_nan() {
+        String::from("+nan.0")
+    } else if f.is_infinite() {
+        String::from(if f > 0.0 { "+inf.0" } else { "-inf.0" })
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+// the `#\` syntax the lexer reads, named for whitespace and control characters
+fn char_name(c: char) -> String {
+    let name = match c {
+        ' ' => "space",
+        '\n' => "newline",
+        '\t' => "tab",
+        '\r' => "return",
+        '\x07' => "alarm",
+        '\x08' => "backspace",
+        '\x7f' => "delete",
+        '\x1b' => "escape",
+        '\0' => "null",
+        c if c.is_control() => return format!("#\\x{:x}", c as u32),
+        c => return format!("#\\{}", c)
+    };
+    format!("#\\{}", name)
+}
+
+fn string_literal(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            '\x07' => res.push_str("\\a"),
+            '\x08' => res.push_str("\\b"),
+            c if c.is_control() => res.push_str(&format!("\\x{:x};", c as u32)),
+            c => res.push(c)
+        }
+    }
+    res.push('"');
+    res
+}
@@ -0,0 +1,125 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_arity, expect_str, is_true, is_false};
+use super::machine::Machine;
+use super::ports::flush_all;
+
+use std::convert::TryFrom;
+use std::env;
+use std::process::{self, Command};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    // current-jiffy counts from the first time it's asked for
+    static JIFFY_EPOCH: Instant = Instant::now();
+}
+
+const JIFFIES_PER_SECOND: i32 = 1000;
+
+// `#t` or no status is success and `#f` failure, as in R7RS
+fn exit_status(name: &str, args: &[Rc<Value>]) -> Result<i32, RuntimeError> {
+    match args.first() {
+        None => Ok(0),
+        Some(status) => match &**status {
+            Value::Int(status) => Ok(*status),
+            _ if is_true(status) => Ok(0),
+            _ if is_false(status) => Ok(1),
+            _ => Err(RuntimeError::TypeError(format!("{}: bad exit status: {}", name, status)))
+        }
+    }
+}
+
+// runs the after thunks of every dynamic-wind being exited from, then unwinds to the top level
+// rather than exiting here, so everything else gets to clean up too
+fn fn_exit(machine: &mut Machine, _env: Rc<Env>, args: Vec<Rc<Value>>) -> Result<(), RuntimeError> {
+    expect_arity("exit", &args, 0, 1)?;
+    machine.exit(exit_status("exit", &args)?)
+}
+
+// exits straight away, leaving buffered file output unwritten
+fn fn_emergency_exit(_env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("emergency-exit", &args, 0, 1)?;
+    process::exit(exit_status("emergency-exit", &args)?)
+}
+
+// flushes file ports and exits, for the top level once an `exit` has unwound to it
+pub fn exit(status: i32) -> ! {
+    flush_all();
+    process::exit(status)
+}
+
+fn fn_get_environment_variable(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("get-environment-variable", &args, 1, 1)?;
+    let name = expect_str("get-environment-variable", &args[0])?;
+    let res = match env::var_os(name) {
+        Some(value) => Rc::new(Value::Str(value.to_string_lossy().into_owned())),
+        None => boolean(&env, false)
+    };
+    Ok((env, res))
+}
+
+// an association list of `(name . value)` pairs
+fn fn_get_environment_variables(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("get-environment-variables", &args, 0, 0)?;
+    let vars = env::vars_os()
+        .map(|(name, value)| Rc::new(Value::Pair(
+            Rc::new(Value::Str(name.to_string_lossy().into_owned())),
+            Rc::new(Value::Str(value.to_string_lossy().into_owned())))))
+        .collect();
+    Ok((env, Value::list(vars)))
+}
+
+// whole seconds since the Unix epoch
+fn fn_current_time(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("current-time", &args, 0, 0)?;
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let seconds = i32::try_from(seconds)
+        .map_err(|_| RuntimeError::RangeError(format!("current-time: {} is too late for an integer", seconds)))?;
+    Ok((env, Rc::new(Value::Int(seconds))))
+}
+
+fn fn_current_jiffy(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("current-jiffy", &args, 0, 0)?;
+    let jiffies = JIFFY_EPOCH.with(|epoch| epoch.elapsed().as_millis());
+    let jiffies = i32::try_from(jiffies)
+        .map_err(|_| RuntimeError::RangeError(String::from("current-jiffy: too long since the first jiffy")))?;
+    Ok((env, Rc::new(Value::Int(jiffies))))
+}
+
+fn fn_jiffies_per_second(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("jiffies-per-second", &args, 0, 0)?;
+    Ok((env, Rc::new(Value::Int(JIFFIES_PER_SECOND))))
+}
+
+// (run-process program arg ...) waits for the program and is a list of its exit code
+// (`#f` if it was killed by a signal), stdout and stderr
+fn fn_run_process(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("run-process", &args, 1, usize::MAX)?;
+    let program = expect_str("run-process", &args[0])?;
+    let arguments: Result<Vec<&String>, RuntimeError> = args[1..].iter().map(|arg| expect_str("run-process", arg)).collect();
+    let output = Command::new(program).args(arguments?).output()
+        .map_err(|why| RuntimeError::IOError(format!("run-process: couldn't run {}: {}", program, why)))?;
+    let status = match output.status.code() {
+        Some(code) => Rc::new(Value::Int(code)),
+        None => boolean(&env, false)
+    };
+    let res = Value::list(vec![
+        status,
+        Rc::new(Value::Str(String::from_utf8_lossy(&output.stdout).into_owned())),
+        Rc::new(Value::Str(String::from_utf8_lossy(&output.stderr).into_owned()))
+    ]);
+    Ok((env, res))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("exit"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_exit))));
+    table.insert(String::from("emergency-exit"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_emergency_exit))));
+    table.insert(String::from("get-environment-variable"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_get_environment_variable))));
+    table.insert(String::from("get-environment-variables"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_get_environment_variables))));
+    table.insert(String::from("current-time"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_current_time))));
+    table.insert(String::from("current-jiffy"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_current_jiffy))));
+    table.insert(String::from("jiffies-per-second"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_jiffies_per_second))));
+    table.insert(String::from("run-process"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_run_process))));
+}
@@ -0,0 +1,191 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::Machine;
+use super::stdlib::{boolean, expect_arity, expect_int, is_false};
+use super::compiler::Form;
+use super::super::parser::main::SyntaxError;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum PromiseState {
+    Done(Rc<Value>),
+    // `lazy` is set for delay-force, whose expression gives another promise to force in its place
+    Delayed { form: Rc<Form>, env: Rc<Env>, lazy: bool },
+    // what calling `func` with `args` returns, for promises made by builtins
+    Call { func: Rc<Value>, args: Vec<Rc<Value>> }
+}
+
+// promises chained by delay-force end up sharing one state, so forcing any of them settles all
+#[derive(Debug)]
+pub struct Promise {
+    state: RefCell<Rc<RefCell<PromiseState>>>
+}
+
+impl Promise {
+    pub fn new(state: PromiseState) -> Self {
+        Promise {
+            state: RefCell::new(Rc::new(RefCell::new(state)))
+        }
+    }
+    pub fn state(&self) -> PromiseState {
+        self.state.borrow().borrow().clone()
+    }
+    pub fn settle(&self, val: Rc<Value>) {
+        *self.state.borrow().borrow_mut() = PromiseState::Done(val);
+    }
+    // takes over `other`'s state, and has `other` share this one's from now on
+    pub fn adopt(&self, other: &Promise) {
+        let state = other.state();
+        *self.state.borrow().borrow_mut() = state;
+        let shared = self.state.borrow().clone();
+        *other.state.borrow_mut() = shared;
+    }
+}
+
+fn delayed(env: &Rc<Env>, form: Rc<Form>, lazy: bool) -> Rc<Value> {
+    Rc::new(Value::Promise(Rc::new(Promise::new(PromiseState::Delayed { form, env: env.clone(), lazy }))))
+}
+
+// the one expression of `(delay expr)` and the like
+fn operand(name: &str, form: &Form) -> Result<Rc<Form>, RuntimeError> {
+    match &form.parts[..] {
+        [_, operand] => Ok(operand.clone()),
+        _ => Err(RuntimeError::Syntax(SyntaxError::MalformedPromise(format!("{} expects 1 expression, got {}", name, form.expr))))
+    }
+}
+
+// (delay expr) leaves expr to be evaluated the first time the promise is forced
+fn fn_delay(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    let promise = delayed(&env, operand("delay", &form)?, false);
+    machine.ret(promise, env)
+}
+
+// (delay-force expr), where expr gives a promise; forcing a chain of them runs in constant space
+fn fn_delay_force(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    let promise = delayed(&env, operand("delay-force", &form)?, true);
+    machine.ret(promise, env)
+}
+
+fn fn_make_promise(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("make-promise", &args, 1, 1)?;
+    let res = match &*args[0] {
+        Value::Promise(_) => args[0].clone(),
+        _ => Rc::new(Value::Promise(Rc::new(Promise::new(PromiseState::Done(args[0].clone())))))
+    };
+    Ok((env, res))
+}
+
+fn fn_is_promise(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("promise?", &args, 1, 1)?;
+    let res = boolean(&env, matches!(*args[0], Value::Promise(_)));
+    Ok((env, res))
+}
+
+// (force promise); anything else is its own value
+fn fn_force(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("force", &args, 1, 1)?;
+    machine.force(args[0].clone(), env)
+}
+
+// (stream-cons head tail) delays both, so a stream is a pair of promises, and nil is the empty stream
+fn fn_stream_cons(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> StepResult {
+    let (head, tail) = match &form.parts[..] {
+        [_, head, tail] => (head.clone(), tail.clone()),
+        _ => return Err(RuntimeError::Syntax(SyntaxError::MalformedPromise(format!("stream-cons expects 2 expressions, got {}", form.expr))))
+    };
+    let stream = Rc::new(Value::Pair(delayed(&env, head, false), delayed(&env, tail, false)));
+    machine.ret(stream, env)
+}
+
+fn expect_stream<'a>(name: &str, val: &'a Value) -> Result<(&'a Rc<Value>, &'a Rc<Value>), RuntimeError> {
+    match val {
+        Value::Pair(head, tail) => Ok((head, tail)),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a non-empty stream, got {}", name, val)))
+    }
+}
+
+fn fn_stream_car(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("stream-car", &args, 1, 1)?;
+    let (head, _) = expect_stream("stream-car", &args[0])?;
+    machine.force(head.clone(), env)
+}
+
+fn fn_stream_cdr(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("stream-cdr", &args, 1, 1)?;
+    let (_, tail) = expect_stream("stream-cdr", &args[0])?;
+    machine.force(tail.clone(), env)
+}
+
+// (stream-take n s) as a list of the first n elements of s, or all of them if there are fewer
+fn fn_stream_take(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("stream-take", &args, 2, 2)?;
+    let n = expect_int("stream-take", &args[0])?.max(0) as usize;
+    take_from(machine, n, args[1].clone(), im::Vector::new(), env)
+}
+
+// carries on taking from `stream`, with `taken` the elements before it
+fn take_from(machine: &mut Machine, n: usize, stream: Rc<Value>, taken: im::Vector<Rc<Value>>, env: Rc<Env>) -> StepResult {
+    if taken.len() == n {
+        return machine.ret(Value::list(taken.into_iter().collect()), env);
+    }
+    machine.force_then(stream, env, move |machine, stream, env| {
+        let (head, tail) = match &*stream {
+            Value::Nil => return machine.ret(Value::list(taken.iter().cloned().collect()), env),
+            val => expect_stream("stream-take", val)?
+        };
+        let (taken, tail) = (taken.clone(), tail.clone());
+        machine.force_then(head.clone(), env, move |machine, head, env| {
+            let mut taken = taken.clone();
+            taken.push_back(head);
+            take_from(machine, n, tail.clone(), taken, env)
+        })
+    })
+}
+
+// (stream-filter p s) finds the first element of s which satisfies p, and leaves the rest of the
+// search until the result's tail is forced
+fn fn_stream_filter(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("stream-filter", &args, 2, 2)?;
+    filter_from(machine, args[0].clone(), args[1].clone(), env)
+}
+
+fn filter_from(machine: &mut Machine, pred: Rc<Value>, stream: Rc<Value>, env: Rc<Env>) -> StepResult {
+    machine.force_then(stream, env, move |machine, stream, env| {
+        let (head, tail) = match &*stream {
+            Value::Nil => return machine.ret(stream.clone(), env),
+            val => expect_stream("stream-filter", val)?
+        };
+        let (pred, tail) = (pred.clone(), tail.clone());
+        machine.force_then(head.clone(), env, move |machine, head, env| filter_test(machine, pred.clone(), head, tail.clone(), env))
+    })
+}
+
+// keeps `head` if it satisfies `pred`, or carries on with `tail` if not
+fn filter_test(machine: &mut Machine, pred: Rc<Value>, head: Rc<Value>, tail: Rc<Value>, env: Rc<Env>) -> StepResult {
+    machine.call_then(pred.clone(), vec![head.clone()], env, move |machine, matched, env| {
+        if is_false(&matched) {
+            return filter_from(machine, pred.clone(), tail.clone(), env);
+        }
+        let filter = Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_stream_filter)));
+        let rest = Promise::new(PromiseState::Call { func: filter, args: vec![pred.clone(), tail.clone()] });
+        let head = Promise::new(PromiseState::Done(head.clone()));
+        let res = Rc::new(Value::Pair(Rc::new(Value::Promise(Rc::new(head))), Rc::new(Value::Promise(Rc::new(rest)))));
+        machine.ret(res, env)
+    })
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("delay"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_delay))));
+    table.insert(String::from("delay-force"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_delay_force))));
+    table.insert(String::from("make-promise"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_make_promise))));
+    table.insert(String::from("promise?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_is_promise))));
+    table.insert(String::from("force"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_force))));
+    table.insert(String::from("stream-cons"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_stream_cons))));
+    table.insert(String::from("stream-car"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_stream_car))));
+    table.insert(String::from("stream-cdr"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_stream_cdr))));
+    table.insert(String::from("stream-take"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_stream_take))));
+    table.insert(String::from("stream-filter"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_stream_filter))));
+}
@@ -0,0 +1,122 @@
+use super::super::lexer::main::Lexer;
+use super::super::lexer::tokens::{Token, Keyword};
+use super::main::{Value, RuntimeError};
+use super::persistent;
+use super::stdlib::bool_value;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// what `read` finds at the start of some text
+pub enum Datum {
+    // a datum, and the byte offset just past it
+    Complete(Rc<Value>, usize),
+    // the text ends partway through a datum, so more input is needed
+    Incomplete,
+    // nothing but whitespace
+    Empty
+}
+
+enum ReadError {
+    Incomplete,
+    Bad(String)
+}
+
+// reads data rather than code: identifiers become symbols and lists aren't evaluated
+pub fn read_datum(text: &str) -> Result<Datum, RuntimeError> {
+    let mut lexer = Lexer::new(text);
+    let res = match lexer.lex() {
+        Some(Token::Eof) | None => return Ok(Datum::Empty),
+        Some(tok) => datum(&mut lexer, tok)
+    };
+    match res {
+        Ok(val) => Ok(Datum::Complete(val, lexer.position())),
+        Err(ReadError::Incomplete) => Ok(Datum::Incomplete),
+        Err(ReadError::Bad(why)) => Err(RuntimeError::ReadError(why))
+    }
+}
+
+fn next_token(lexer: &mut Lexer) -> Result<Token, ReadError> {
+    match lexer.lex() {
+        Some(Token::Eof) | None => Err(ReadError::Incomplete),
+        Some(tok) => Ok(tok)
+    }
+}
+
+fn keyword_name(keyword: Keyword) -> &'static str {
+    match keyword {
+        Keyword::Let => "let",
+        Keyword::Lambda => "lambda",
+        Keyword::Begin => "begin",
+        Keyword::Include => "include"
+    }
+}
+
+fn datum(lexer: &mut Lexer, tok: Token) -> Result<Rc<Value>, ReadError> {
+    let val = match tok {
+        Token::IntLiteral(lexeme) => Value::Int(lexeme.parse().map_err(|_| ReadError::Bad(format!("bad integer {}", lexeme)))?),
+        Token::FloatLiteral(lexeme) => Value::Float(lexeme.parse().map_err(|_| ReadError::Bad(format!("bad number {}", lexeme)))?),
+        Token::StringLiteral(s) => Value::Str(s),
+        Token::CharLiteral(c) => Value::Char(c),
+        Token::BoolLiteral(b) => return Ok(bool_value(b)),
+        Token::Identifier(name) => Value::Symbol(name),
+        Token::Keyword(keyword) => Value::Symbol(String::from(keyword_name(keyword))),
+        Token::Quote => {
+            let quoted = next_token(lexer).and_then(|tok| datum(lexer, tok))?;
+            return Ok(Value::list(vec![Rc::new(Value::Symbol(String::from("quote"))), quoted]));
+        },
+        Token::OpenPar => return list(lexer),
+        Token::OpenVector => Value::Vector(Rc::new(RefCell::new(elements(lexer, Token::ClosePar)?))),
+        Token::OpenBytevector => {
+            let bytes: Result<Vec<u8>, ReadError> = elements(lexer, Token::ClosePar)?.iter()
+                .map(|item| match **item {
+                    Value::Int(i) if (0..256).contains(&i) => Ok(i as u8),
+                    _ => Err(ReadError::Bad(format!("{} is not a byte", item)))
+                })
+                .collect();
+            Value::Bytevector(Rc::new(RefCell::new(bytes?)))
+        },
+        Token::OpenBrace => {
+            let items = elements(lexer, Token::CloseBrace)?;
+            if items.len() % 2 != 0 {
+                return Err(ReadError::Bad(String::from("map literal with an odd number of elements")));
+            }
+            return Ok(persistent::hash_map(items));
+        },
+        Token::OpenSet => return Ok(persistent::hash_set(elements(lexer, Token::CloseBrace)?)),
+        Token::UnterminatedString => return Err(ReadError::Incomplete),
+        Token::BadCharacter(name) if name.is_empty() => return Err(ReadError::Incomplete),
+        other => return Err(ReadError::Bad(format!("unexpected {:?}", other)))
+    };
+    Ok(Rc::new(val))
+}
+
+// the data up to `close`
+fn elements(lexer: &mut Lexer, close: Token) -> Result<Vec<Rc<Value>>, ReadError> {
+    let mut items = Vec::new();
+    loop {
+        let tok = next_token(lexer)?;
+        if tok == close {
+            return Ok(items);
+        }
+        items.push(datum(lexer, tok)?);
+    }
+}
+
+// the rest of a list after its `(`, which may end in `. tail`
+fn list(lexer: &mut Lexer) -> Result<Rc<Value>, ReadError> {
+    let mut items = Vec::new();
+    loop {
+        match next_token(lexer)? {
+            Token::ClosePar => return Ok(Value::list(items)),
+            Token::Unknown(dot) if dot == "." && !items.is_empty() => {
+                let tail = next_token(lexer).and_then(|tok| datum(lexer, tok))?;
+                if next_token(lexer)? != Token::ClosePar {
+                    return Err(ReadError::Bad(String::from("more than one datum after a dot")));
+                }
+                return Ok(items.into_iter().rev().fold(tail, |tail, item| Rc::new(Value::Pair(item, tail))));
+            },
+            tok => items.push(datum(lexer, tok)?)
+        }
+    }
+}
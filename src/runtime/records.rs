@@ -0,0 +1,189 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, LambdaFunction, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::stdlib::{boolean, expect_str, expect_int};
+use super::printer;
+use super::super::parser::expressions::{Expression, Expression::*};
+use super::super::parser::main::SyntaxError;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// the record-type descriptor bound to the type name, e.g. `<point>`
+#[derive(Debug)]
+pub struct RecordType {
+    name: String,
+    fields: Vec<String>,
+    // the field each constructor argument initialises, in argument order
+    init: Vec<usize>
+}
+
+impl RecordType {
+    // `<point>` prints as `point`
+    pub fn short_name(&self) -> &str {
+        self.name.trim_start_matches('<').trim_end_matches('>')
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    rtd: Rc<RecordType>,
+    fields: RefCell<Vec<Rc<Value>>>
+}
+
+impl Record {
+    pub fn record_type(&self) -> &Rc<RecordType> {
+        &self.rtd
+    }
+    pub fn fields(&self) -> Vec<(String, Rc<Value>)> {
+        self.rtd.fields.iter().cloned().zip(self.fields.borrow().iter().cloned()).collect()
+    }
+}
+
+fn malformed(expr: &Expression) -> RuntimeError {
+    RuntimeError::Syntax(SyntaxError::MalformedRecordType(expr.to_string()))
+}
+
+fn identifier(expr: &Expression) -> Result<String, RuntimeError> {
+    match expr {
+        LookupExpr(name) => Ok(name.to_string()),
+        _ => Err(malformed(expr))
+    }
+}
+
+// `(make-point x y)` or `(x point-x set-point-x!)` as a list of names
+fn identifiers(expr: &Expression) -> Result<Vec<String>, RuntimeError> {
+    match expr {
+        SExpr(head, rest) => std::iter::once(head).chain(rest.iter()).map(|part| identifier(part)).collect(),
+        _ => Err(malformed(expr))
+    }
+}
+
+fn expect_record(name: &str, rtd: &Rc<RecordType>, val: &Value) -> Result<Rc<Record>, RuntimeError> {
+    match val {
+        Value::Record(record) if Rc::ptr_eq(&record.rtd, rtd) => Ok(record.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a {}, got {}", name, rtd.short_name(), val)))
+    }
+}
+
+fn expect_record_type(val: &Value) -> Result<Rc<RecordType>, RuntimeError> {
+    match val {
+        Value::RecordType(rtd) => Ok(rtd.clone()),
+        _ => Err(RuntimeError::TypeError(format!("expected a record type, got {}", val)))
+    }
+}
+
+// the procedures made by define-record-type are closures over these, bound to `%` names
+// which user code can't spell, so each one can find its record type
+
+fn fn_record_construct(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let rtd = expect_record_type(&args[0])?;
+    let mut fields = rtd.fields.iter().map(|_| Rc::new(Value::Nil)).collect::<Vec<_>>();
+    for (k, value) in rtd.init.iter().zip(args[1..].iter()) {
+        fields[*k] = value.clone();
+    }
+    let record = Record { rtd, fields: RefCell::new(fields) };
+    Ok((env, Rc::new(Value::Record(Rc::new(record)))))
+}
+
+fn fn_record_is(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let rtd = expect_record_type(&args[0])?;
+    let res = boolean(&env, matches!(&*args[1], Value::Record(record) if Rc::ptr_eq(&record.rtd, &rtd)));
+    Ok((env, res))
+}
+
+// (%record-ref rtd obj k name)
+fn fn_record_ref(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let rtd = expect_record_type(&args[0])?;
+    let name = expect_str("record accessor", &args[3])?;
+    let record = expect_record(name, &rtd, &args[1])?;
+    let k = expect_int(name, &args[2])? as usize;
+    let value = record.fields.borrow()[k].clone();
+    Ok((env, value))
+}
+
+// (%record-set! rtd obj k value name)
+fn fn_record_set(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let rtd = expect_record_type(&args[0])?;
+    let name = expect_str("record modifier", &args[4])?;
+    let record = expect_record(name, &rtd, &args[1])?;
+    let k = expect_int(name, &args[2])? as usize;
+    record.fields.borrow_mut()[k] = args[3].clone();
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn call(name: &str, args: Vec<Expression>) -> Expression {
+    SExpr(Rc::new(LookupExpr(String::from(name))), args.into_iter().map(Rc::new).collect())
+}
+
+fn lookup(name: &str) -> Expression {
+    LookupExpr(String::from(name))
+}
+
+fn procedure(env: &Rc<Env>, name: &str, arg_names: Vec<&str>, body: Expression) -> Rc<Value> {
+    let arg_names = arg_names.into_iter().map(String::from).collect();
+    Rc::new(Value::Lambda(Rc::new(LambdaFunction::new_checked(env.clone(), String::from(name), arg_names, Rc::new(body)))))
+}
+
+// (define-record-type <point> (make-point x y) point? (x point-x set-point-x!) (y point-y))
+pub fn fn_define_record_type(env: Rc<Env>, args: Vec<Rc<Expression>>) -> EvalResult {
+    if args.len() < 3 {
+        return Err(RuntimeError::Syntax(SyntaxError::MalformedRecordType(printer::form("define-record-type", &args))));
+    }
+    let type_name = identifier(&args[0])?;
+    let predicate = identifier(&args[2])?;
+    let specs: Result<Vec<Vec<String>>, RuntimeError> = args[3..].iter()
+        .map(|spec| match identifiers(spec) {
+            Ok(names) if names.len() <= 3 => Ok(names),
+            _ => Err(malformed(spec))
+        })
+        .collect();
+    let specs = specs?;
+    let fields: Vec<String> = specs.iter().map(|spec| spec[0].clone()).collect();
+    // a bare constructor name takes every field in order
+    let (constructor, init_names) = match &*args[1] {
+        LookupExpr(name) => (name.to_string(), fields.clone()),
+        spec => {
+            let mut names = identifiers(spec)?;
+            let constructor = names.remove(0);
+            (constructor, names)
+        }
+    };
+    let init: Result<Vec<usize>, RuntimeError> = init_names.iter()
+        .map(|name| fields.iter().position(|field| field == name).ok_or_else(|| malformed(&args[1])))
+        .collect();
+    let rtd = Rc::new(RecordType { name: type_name.clone(), fields, init: init? });
+
+    let mut internals = HashMap::new();
+    internals.insert(String::from("%rtd"), Rc::new(Value::RecordType(rtd.clone())));
+    internals.insert(String::from("%record-construct"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_record_construct))));
+    internals.insert(String::from("%record-is"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_record_is))));
+    internals.insert(String::from("%record-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_record_ref))));
+    internals.insert(String::from("%record-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_record_set))));
+    let closure_env = Rc::new(env.subenv(internals));
+
+    let mut bindings = HashMap::new();
+    bindings.insert(type_name, Rc::new(Value::RecordType(rtd.clone())));
+    // the constructor's arguments are renamed too, so a field can't be shadowed by the constructor's own name
+    let init_args: Vec<String> = init_names.iter().map(|name| format!("%{}", name)).collect();
+    let mut construct_args = vec![lookup("%rtd")];
+    construct_args.extend(init_args.iter().map(|name| lookup(name)));
+    let init_args = init_args.iter().map(|name| name.as_str()).collect();
+    bindings.insert(constructor.clone(), procedure(&closure_env, &constructor, init_args, call("%record-construct", construct_args)));
+    bindings.insert(predicate.clone(), procedure(&closure_env, &predicate, vec!["obj"], call("%record-is", vec![lookup("%rtd"), lookup("obj")])));
+    for (k, spec) in specs.iter().enumerate() {
+        if let Some(accessor) = spec.get(1) {
+            let body = call("%record-ref", vec![lookup("%rtd"), lookup("obj"), IntegerLiteral(k as i32), StringLiteral(accessor.clone())]);
+            bindings.insert(accessor.clone(), procedure(&closure_env, accessor, vec!["obj"], body));
+        }
+        if let Some(modifier) = spec.get(2) {
+            let body = call("%record-set!", vec![lookup("%rtd"), lookup("obj"), IntegerLiteral(k as i32), lookup("value"), StringLiteral(modifier.clone())]);
+            bindings.insert(modifier.clone(), procedure(&closure_env, modifier, vec!["obj", "value"], body));
+        }
+    }
+    Ok((Rc::new(env.subenv(bindings)), Rc::new(Value::Nil)))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("define-record-type"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(fn_define_record_type))));
+}
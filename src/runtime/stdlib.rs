@@ -1,160 +1,280 @@
 extern crate im;
 use im::hashmap::HashMap;
-use super::main::{Env, Value, RuntimeFunctionWrapper};
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, resolve_path};
+use super::modules;
+use super::strings;
+use super::chars;
+use super::vectors;
+use super::bytevectors;
+use super::equality::{self, is_eqv};
+use super::hashtables;
+use super::control;
+use super::values;
+use super::promises;
+use super::parameters;
+use super::persistent;
+use super::records;
+use super::ports;
+use super::files;
+use super::process;
+use super::exceptions;
+use super::printer;
+use super::io;
 use super::super::parser::expressions::Expression;
-use super::super::parser::main::Parser;
 
 use either::*;
 use std::rc::Rc;
+use std::cell::RefCell;
 
-use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::path::PathBuf;
 
-use std::process::exit;
+// core.scm is compiled into the binary so the interpreter works from any directory
+const CORE_PRELUDE: &str = include_str!("../../core.scm");
 
-type IFold = fn (i32, i32) -> i32;
+// which lisp source to evaluate on top of the runtime builtins
+pub enum Prelude {
+    Embedded,
+    File(PathBuf),
+    Empty
+}
+
+// integer folds can fail, as division by zero does
+type IFold = fn (i32, i32) -> Result<i32, RuntimeError>;
 type FFold = fn (f32, f32) -> f32;
 
-fn try_fold_ints(mut args: Vec<Rc<Value>>, i_base: i32, i_fold: IFold, f_base: f32, f_fold: FFold) -> Either<i32, f32> {
+fn try_fold_ints(mut args: Vec<Rc<Value>>, i_base: i32, i_fold: IFold, f_base: f32, f_fold: FFold) -> Result<Either<i32, f32>, RuntimeError> {
     match args.pop() {
         Some(v) => match *v {
-            Value::Int(num) => match try_fold_ints(args, i_base, i_fold, f_base, f_fold) {
-                Left(i) => Left(i_fold(num, i)),
-                Right(f) => Right(f_fold(num as f32, f))
+            Value::Int(num) => match try_fold_ints(args, i_base, i_fold, f_base, f_fold)? {
+                Left(i) => Ok(Left(i_fold(num, i)?)),
+                Right(f) => Ok(Right(f_fold(num as f32, f)))
             },
-            Value::Float(num) => Right(f_fold(num, fold_floats(args, f_base, f_fold))),
-            _ => panic!("Bad values in built-in")
+            Value::Float(num) => Ok(Right(f_fold(num, fold_floats(args, f_base, f_fold)?))),
+            _ => Err(RuntimeError::TypeError(format!("expected a number, got {}", v)))
         },
-        None => Left(i_base)
+        None => Ok(Left(i_base))
     }
 }
 
-fn fold_floats(mut args: Vec<Rc<Value>>, f_base: f32, f_fold: FFold) -> f32 {
+fn fold_floats(mut args: Vec<Rc<Value>>, f_base: f32, f_fold: FFold) -> Result<f32, RuntimeError> {
     match args.pop() {
         Some(v) => match *v {
-            Value::Int(num) => f_fold(num as f32, fold_floats(args, f_base, f_fold)),
-            Value::Float(num) => f_fold(num, fold_floats(args, f_base, f_fold)),
-            _ => panic!("Bad values in add call")
+            Value::Int(num) => Ok(f_fold(num as f32, fold_floats(args, f_base, f_fold)?)),
+            Value::Float(num) => Ok(f_fold(num, fold_floats(args, f_base, f_fold)?)),
+            _ => Err(RuntimeError::TypeError(format!("expected a number, got {}", v)))
         },
-        None => f_base
+        None => Ok(f_base)
     }
 }
 
-fn add_i(a: i32, b: i32) -> i32 {
-    a + b
+fn add_i(a: i32, b: i32) -> Result<i32, RuntimeError> {
+    Ok(a + b)
 }
 fn add_f(a: f32, b: f32) -> f32 {
     a + b
 }
 
-fn sub_i(a: i32, b: i32) -> i32 {
-    a - b
+fn sub_i(a: i32, b: i32) -> Result<i32, RuntimeError> {
+    Ok(a - b)
 }
 fn sub_f(a: f32, b: f32) -> f32 {
     a - b
 }
 
-fn mul_i(a: i32, b: i32) -> i32 {
-    a * b
+fn mul_i(a: i32, b: i32) -> Result<i32, RuntimeError> {
+    Ok(a * b)
 }
 fn mul_f(a: f32, b: f32) -> f32 {
     a * b
 }
 
-fn div_i(a: i32, b: i32) -> i32 {
-    a / b
+// `a` is the dividend, so `(/ 0 1)` divides 1 by 0
+fn div_i(a: i32, b: i32) -> Result<i32, RuntimeError> {
+    if b == 0 {
+        return Err(RuntimeError::RangeError(format!("/: division of {} by zero", a)));
+    }
+    Ok(a.wrapping_div(b))
 }
 fn div_f(a: f32, b: f32) -> f32 {
     a / b
 }
 
-fn wrapped_add(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    (env, Rc::new(match try_fold_ints(args, 0, add_i, 0.0, add_f) {
+fn wrapped_add(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, Rc::new(match try_fold_ints(args, 0, add_i, 0.0, add_f)? {
         Left(i) => Value::Int(i),
         Right(f) => Value::Float(f)
-    }))
+    })))
 }
 
-fn wrapped_sub(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    (env, Rc::new(match try_fold_ints(args, 0, sub_i, 0.0, sub_f) {
+fn wrapped_sub(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, Rc::new(match try_fold_ints(args, 0, sub_i, 0.0, sub_f)? {
         Left(i) => Value::Int(i),
         Right(f) => Value::Float(f)
-    }))
+    })))
 }
 
-fn wrapped_mul(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    (env, Rc::new(match try_fold_ints(args, 1, mul_i, 1.0, mul_f) {
+fn wrapped_mul(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, Rc::new(match try_fold_ints(args, 1, mul_i, 1.0, mul_f)? {
         Left(i) => Value::Int(i),
         Right(f) => Value::Float(f)
-    }))
+    })))
 }
 
-fn wrapped_div(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    (env, Rc::new(match try_fold_ints(args, 1, div_i, 1.0, div_f) {
+fn wrapped_div(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, Rc::new(match try_fold_ints(args, 1, div_i, 1.0, div_f)? {
         Left(i) => Value::Int(i),
         Right(f) => Value::Float(f)
-    }))
+    })))
 }
 
-fn wrapped_exit(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    exit(0);
-    (env, Rc::new(Value::Nil))
+thread_local! {
+    // arguments handed to the interpreter, starting with the script name
+    static COMMAND_LINE: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
 }
 
+pub fn set_command_line(args: Vec<String>) {
+    COMMAND_LINE.with(|cl| *cl.borrow_mut() = args);
+}
 
-fn fn_true(env: Rc<Env>, mut args: Vec<Box<Expression>>) -> (Rc<Env>, Rc<Value>) {
-    let (env, res) = env.eval(&args[0]);
-    (env, res)
+fn fn_command_line(env: Rc<Env>, _args: Vec<Rc<Value>>) -> EvalResult {
+    let args = COMMAND_LINE.with(|cl| cl.borrow().clone());
+    let list = Value::list(args.into_iter().map(|arg| Rc::new(Value::Str(arg))).collect());
+    Ok((env, list))
 }
 
-fn fn_false(env: Rc<Env>, mut args: Vec<Box<Expression>>) -> (Rc<Env>, Rc<Value>) {
-    let (env, res) = env.eval(&args[1]);
-    (env, res)
+// the church booleans bound to `true` and `false`, for builtin predicates
+pub fn boolean(env: &Env, b: bool) -> Rc<Value> {
+    if b {
+        env.lookup("true")
+    } else {
+        env.lookup("false")
+    }
 }
 
-fn fn_eq(env: Rc<Env>, mut args: Vec<Rc<Value>>) -> (Rc<Env>, Rc<Value>) {
-    if args.len() != 2 {
-        panic!("Malformed eq predicate");
+// the same values `true` and `false` are bound to, for when there's no env to look them up in
+pub fn bool_value(b: bool) -> Rc<Value> {
+    if b {
+        Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(fn_true)))
+    } else {
+        Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(fn_false)))
     }
-    let x = args.pop().unwrap();
-    let y = args.pop().unwrap();
-    let res = match &*x {
-        Value::Nil => match &*y {
-            Value::Nil => env.lookup(&String::from("true")),
-            _ => env.lookup(&String::from("false"))
-        },
-        Value::Int(a) => match &*y {
-            Value::Int(b) => if a == b {
-                env.lookup(&String::from("true"))
-            } else {
-                env.lookup(&String::from("false"))
-            },
-            _ => env.lookup(&String::from("false"))
-        },
-        Value::Float(a) => match &*y {
-            Value::Float(b) => if a == b {
-                env.lookup(&String::from("true"))
-            } else {
-                env.lookup(&String::from("false"))
-            },
-            _ => env.lookup(&String::from("false"))
-        },
-        Value::Str(a) => match &*y {
-            Value::Str(b) => if a == b {
-                env.lookup(&String::from("true"))
-            } else {
-                env.lookup(&String::from("false"))
-            },
-            _ => env.lookup(&String::from("false"))
+}
+
+pub fn is_true(val: &Value) -> bool {
+    match val {
+        Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(f)) =>
+            std::ptr::fn_addr_eq(*f, fn_true as fn (Rc<Env>, Vec<Rc<Expression>>) -> EvalResult),
+        _ => false
+    }
+}
+
+// everything but `false` counts as true, as in scheme
+pub fn is_false(val: &Value) -> bool {
+    match val {
+        Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(f)) =>
+            std::ptr::fn_addr_eq(*f, fn_false as fn (Rc<Env>, Vec<Rc<Expression>>) -> EvalResult),
+        _ => false
+    }
+}
+
+pub fn expect_arity(name: &str, args: &[Rc<Value>], min: usize, max: usize) -> Result<(), RuntimeError> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else if max == usize::MAX {
+            format!("at least {}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(RuntimeError::ArityError(format!("{} expects {} arguments, got {}", name, expected, args.len())));
+    }
+    Ok(())
+}
+
+pub fn expect_str<'a>(name: &str, val: &'a Value) -> Result<&'a String, RuntimeError> {
+    match val {
+        Value::Str(s) => Ok(s),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a string, got {}", name, val)))
+    }
+}
+
+pub fn expect_int(name: &str, val: &Value) -> Result<i32, RuntimeError> {
+    match val {
+        Value::Int(i) => Ok(*i),
+        _ => Err(RuntimeError::TypeError(format!("{} expects an integer, got {}", name, val)))
+    }
+}
+
+pub fn expect_list(name: &str, val: &Rc<Value>) -> Result<Vec<Rc<Value>>, RuntimeError> {
+    Value::list_items(val)
+        .ok_or_else(|| RuntimeError::TypeError(format!("{} expects a list, got {}", name, val)))
+}
+
+fn fn_cons(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("cons", &args, 2, 2)?;
+    Ok((env, Rc::new(Value::Pair(args[0].clone(), args[1].clone()))))
+}
+
+fn fn_car(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("car", &args, 1, 1)?;
+    match &*args[0] {
+        Value::Pair(car, _) => Ok((env.clone(), car.clone())),
+        other => Err(RuntimeError::TypeError(format!("car expects a pair, got {}", other)))
+    }
+}
+
+fn fn_cdr(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("cdr", &args, 1, 1)?;
+    match &*args[0] {
+        Value::Pair(_, cdr) => Ok((env.clone(), cdr.clone())),
+        other => Err(RuntimeError::TypeError(format!("cdr expects a pair, got {}", other)))
+    }
+}
+
+
+// evaluates a file into the calling environment, so its definitions stay visible
+fn fn_load(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    match args.first().map(|v| &**v) {
+        Some(Value::Str(path)) if args.len() == 1 => {
+            let (env, _) = env.eval_file(&resolve_path(path))?;
+            Ok((env, Rc::new(Value::Nil)))
         },
-        _ => env.lookup(&String::from("false"))
+        _ => Err(RuntimeError::TypeError(String::from("load expects a file name")))
+    }
+}
+
+fn fn_true(env: Rc<Env>, args: Vec<Rc<Expression>>) -> EvalResult {
+    if args.len() != 2 {
+        return Err(RuntimeError::ArityError(String::from("true expects 2 branches")));
+    }
+    let (env, res) = env.eval(&args[0])?;
+    Ok((env, res))
+}
+
+fn fn_false(env: Rc<Env>, args: Vec<Rc<Expression>>) -> EvalResult {
+    if args.len() != 2 {
+        return Err(RuntimeError::ArityError(String::from("false expects 2 branches")));
+    }
+    let (env, res) = env.eval(&args[1])?;
+    Ok((env, res))
+}
+
+// numbers compare by value, even an integer with a float, strings by content, and anything else with eqv?
+fn fn_eq(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("=", &args, 2, 2)?;
+    let same = match (&*args[0], &*args[1]) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Int(a), Value::Float(b)) => *a as f32 == *b,
+        (Value::Float(a), Value::Int(b)) => *a == *b as f32,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        _ => is_eqv(&args[0], &args[1])
     };
-    return (env, res);
+    let res = boolean(&env, same);
+    Ok((env, res))
 }
 
-pub fn build_standard_library() -> Rc<Env> {
+pub fn build_standard_library(prelude: Prelude) -> Result<Rc<Env>, String> {
     // load in runtime builtins
 
     let mut table = HashMap::new();
@@ -163,42 +283,47 @@ pub fn build_standard_library() -> Rc<Env> {
     table.insert(String::from("*"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(wrapped_mul))));
     table.insert(String::from("/"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(wrapped_div))));
     table.insert(String::from("="), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_eq))));
-    table.insert(String::from("exit"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(wrapped_exit))));
+    table.insert(String::from("load"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_load))));
+    table.insert(String::from("command-line"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_command_line))));
     table.insert(String::from("true"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(fn_true))));
     table.insert(String::from("false"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(fn_false))));
     table.insert(String::from("nil"), Rc::new(Value::Nil));
+    table.insert(String::from("cons"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_cons))));
+    table.insert(String::from("car"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_car))));
+    table.insert(String::from("cdr"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_cdr))));
+    strings::add_builtins(&mut table);
+    chars::add_builtins(&mut table);
+    vectors::add_builtins(&mut table);
+    bytevectors::add_builtins(&mut table);
+    equality::add_builtins(&mut table);
+    hashtables::add_builtins(&mut table);
+    persistent::add_builtins(&mut table);
+    records::add_builtins(&mut table);
+    ports::add_builtins(&mut table);
+    io::add_builtins(&mut table);
+    files::add_builtins(&mut table);
+    process::add_builtins(&mut table);
+    exceptions::add_builtins(&mut table);
+    control::add_builtins(&mut table);
+    values::add_builtins(&mut table);
+    promises::add_builtins(&mut table);
+    parameters::add_builtins(&mut table);
+    table.insert(String::from("define-library"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(modules::fn_define_library))));
+    table.insert(String::from("import"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Symbolic(modules::fn_import))));
 
-    // load our standard libray
+    printer::register_builtin_names(&table);
 
-    let path = Path::new("core.scm");
-    let mut file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", path.display(),
-                                                   why.description()),
-        Ok(file) => file,
-    };
-    let mut env = Rc::new(Env::from_table(table));
-    let mut buffer = String::new();
+    // load our standard libray
 
-    match file.read_to_string(&mut buffer) {
-        Err(why) => panic!("couldn't read {}: {}", path.display(),
-                                                   why.description()),
-        Ok(_) => {}
+    let env = Rc::new(Env::from_table(table));
+    let env = match prelude {
+        Prelude::Embedded => env.eval_source(CORE_PRELUDE, None)
+            .map_err(|err| format!("couldn't load core.scm: {}", err))?.0,
+        Prelude::File(path) => env.eval_file(&path)
+            .map_err(|err| format!("couldn't load {}: {}", path.display(), err))?.0,
+        Prelude::Empty => env
     };
-
-    let mut p = Parser::new(&buffer, 0);
-    while !p.is_finished() {
-        let tree = p.parse(false).unwrap();
-        match tree {
-            Either::Left(expr) => {
-                match env.eval(&expr) {
-                    (new_env, res) => {
-                        env = new_env;
-                    }
-                }
-                
-            },
-            Either::Right(_) => {}
-        }
-    }
-    return env;
+    // libraries are evaluated on top of the builtins and prelude
+    modules::set_root(env.clone());
+    Ok(env)
 }
@@ -0,0 +1,262 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::Machine;
+use super::stdlib::{boolean, is_false, expect_arity, expect_str, expect_int, expect_list};
+
+use std::rc::Rc;
+
+// strings are indexed by char, not by byte, so every index is checked against the char count
+fn expect_index(name: &str, val: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let index = expect_int(name, val)?;
+    if index < 0 || index as usize > len {
+        return Err(RuntimeError::RangeError(format!("{}: index {} not in 0..{}", name, index, len)));
+    }
+    Ok(index as usize)
+}
+
+fn char_value(c: char) -> Rc<Value> {
+    Rc::new(Value::Char(c))
+}
+
+fn fn_string_length(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-length", &args, 1, 1)?;
+    let s = expect_str("string-length", &args[0])?;
+    Ok((env, Rc::new(Value::Int(s.chars().count() as i32))))
+}
+
+fn fn_string_append(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    let mut res = String::new();
+    for arg in args.iter() {
+        res.push_str(expect_str("string-append", arg)?);
+    }
+    Ok((env, Rc::new(Value::Str(res))))
+}
+
+fn fn_substring(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("substring", &args, 2, 3)?;
+    let s = expect_str("substring", &args[0])?;
+    let len = s.chars().count();
+    let start = expect_index("substring", &args[1], len)?;
+    let end = match args.get(2) {
+        Some(end) => expect_index("substring", end, len)?,
+        None => len
+    };
+    if start > end {
+        return Err(RuntimeError::RangeError(format!("substring: start {} is after end {}", start, end)));
+    }
+    let res: String = s.chars().skip(start).take(end - start).collect();
+    Ok((env, Rc::new(Value::Str(res))))
+}
+
+fn fn_string_ref(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-ref", &args, 2, 2)?;
+    let s = expect_str("string-ref", &args[0])?;
+    let len = s.chars().count();
+    let k = expect_index("string-ref", &args[1], len)?;
+    match s.chars().nth(k) {
+        Some(c) => Ok((env, char_value(c))),
+        None => Err(RuntimeError::RangeError(format!("string-ref: index {} not in 0..{}", k, len)))
+    }
+}
+
+fn fn_string_upcase(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-upcase", &args, 1, 1)?;
+    let s = expect_str("string-upcase", &args[0])?;
+    Ok((env, Rc::new(Value::Str(s.to_uppercase()))))
+}
+
+fn fn_string_downcase(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-downcase", &args, 1, 1)?;
+    let s = expect_str("string-downcase", &args[0])?;
+    Ok((env, Rc::new(Value::Str(s.to_lowercase()))))
+}
+
+// (string-index s pred) where pred is a procedure or a character
+fn fn_string_index(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("string-index", &args, 2, 2)?;
+    let chars: Rc<Vec<char>> = Rc::new(expect_str("string-index", &args[0])?.chars().collect());
+    index_from(machine, chars, args[1].clone(), 0, env)
+}
+
+// the index of the first char from `start` on which `pred` matches, or #f
+fn index_from(machine: &mut Machine, chars: Rc<Vec<char>>, pred: Rc<Value>, start: usize, env: Rc<Env>) -> StepResult {
+    for (i, c) in chars.iter().enumerate().skip(start) {
+        match &*pred {
+            Value::Char(needle) if needle == c => return machine.ret(Rc::new(Value::Int(i as i32)), env),
+            Value::Char(_) => (),
+            _ => return machine.call_then(pred.clone(), vec![char_value(*c)], env, move |machine, matched, env| {
+                if is_false(&matched) {
+                    index_from(machine, chars.clone(), pred.clone(), i + 1, env)
+                } else {
+                    machine.ret(Rc::new(Value::Int(i as i32)), env)
+                }
+            })
+        }
+    }
+    let res = boolean(&env, false);
+    machine.ret(res, env)
+}
+
+// (string-split s [delimiter]) splits on a string or char delimiter, or on whitespace when none is given
+fn fn_string_split(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-split", &args, 1, 2)?;
+    let s = expect_str("string-split", &args[0])?;
+    let parts: Vec<&str> = match args.get(1) {
+        Some(delim) => {
+            let delim = match &**delim {
+                Value::Char(c) => c.to_string(),
+                _ => expect_str("string-split", delim)?.to_string()
+            };
+            if delim.is_empty() {
+                return Err(RuntimeError::RangeError(String::from("string-split: empty delimiter")));
+            }
+            s.split(delim.as_str()).collect()
+        },
+        None => s.split_whitespace().collect()
+    };
+    Ok((env, Value::list(parts.into_iter().map(|part| Rc::new(Value::Str(part.to_string()))).collect())))
+}
+
+// (string-join list [delimiter]) joins with a single space when no delimiter is given
+fn fn_string_join(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-join", &args, 1, 2)?;
+    let items = expect_list("string-join", &args[0])?;
+    let delim = match args.get(1) {
+        Some(delim) => expect_str("string-join", delim)?.as_str(),
+        None => " "
+    };
+    let parts: Result<Vec<&str>, RuntimeError> = items.iter()
+        .map(|item| expect_str("string-join", item).map(|s| s.as_str()))
+        .collect();
+    Ok((env, Rc::new(Value::Str(parts?.join(delim)))))
+}
+
+// the char index of the first occurrence of the needle, or false
+fn fn_string_contains(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-contains", &args, 2, 2)?;
+    let s = expect_str("string-contains", &args[0])?;
+    let needle = expect_str("string-contains", &args[1])?;
+    let res = match s.find(needle.as_str()) {
+        Some(pos) => Rc::new(Value::Int(s[..pos].chars().count() as i32)),
+        None => boolean(&env, false)
+    };
+    Ok((env, res))
+}
+
+fn fn_string_trim(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string-trim", &args, 1, 1)?;
+    let s = expect_str("string-trim", &args[0])?;
+    Ok((env, Rc::new(Value::Str(s.trim().to_string()))))
+}
+
+// the optional radix of the number conversions, 10 when left out
+fn expect_radix(name: &str, val: Option<&Rc<Value>>) -> Result<u32, RuntimeError> {
+    let radix = match val {
+        Some(radix) => expect_int(name, radix)?,
+        None => return Ok(10)
+    };
+    if !(2..=36).contains(&radix) {
+        return Err(RuntimeError::RangeError(format!("{}: radix {} not in 2..36", name, radix)));
+    }
+    Ok(radix as u32)
+}
+
+// (string->number s [radix]) gives false when s isn't a number; floats are only read in base 10
+fn fn_string_to_number(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string->number", &args, 1, 2)?;
+    let s = expect_str("string->number", &args[0])?.trim();
+    let radix = expect_radix("string->number", args.get(1))?;
+    let res = if let Ok(i) = i32::from_str_radix(s, radix) {
+        Rc::new(Value::Int(i))
+    } else if let (10, Ok(f)) = (radix, s.parse::<f32>()) {
+        Rc::new(Value::Float(f))
+    } else {
+        boolean(&env, false)
+    };
+    Ok((env, res))
+}
+
+// (number->string n [radix]) writes integers in any radix, floats only in base 10
+fn fn_number_to_string(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("number->string", &args, 1, 2)?;
+    let radix = expect_radix("number->string", args.get(1))?;
+    let res = match &*args[0] {
+        Value::Int(i) => {
+            let mut n = i.unsigned_abs();
+            let mut digits = Vec::new();
+            loop {
+                digits.push(std::char::from_digit(n % radix, radix).unwrap_or('?'));
+                n /= radix;
+                if n == 0 {
+                    break;
+                }
+            }
+            if *i < 0 {
+                digits.push('-');
+            }
+            digits.into_iter().rev().collect()
+        },
+        Value::Float(f) if radix == 10 => format!("{:?}", f),
+        Value::Float(_) => return Err(RuntimeError::RangeError(format!("number->string: floats can't be written in radix {}", radix))),
+        other => return Err(RuntimeError::TypeError(format!("number->string expects a number, got {}", other)))
+    };
+    Ok((env, Rc::new(Value::Str(res))))
+}
+
+fn fn_string_to_list(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("string->list", &args, 1, 1)?;
+    let s = expect_str("string->list", &args[0])?;
+    Ok((env, Value::list(s.chars().map(char_value).collect())))
+}
+
+// the string comparison predicates hold when every adjacent pair is ordered by `cmp`
+fn compare_strings(env: Rc<Env>, args: Vec<Rc<Value>>, name: &str, cmp: fn (&String, &String) -> bool) -> EvalResult {
+    expect_arity(name, &args, 1, usize::MAX)?;
+    let strings: Result<Vec<&String>, RuntimeError> = args.iter().map(|arg| expect_str(name, arg)).collect();
+    let ordered = strings?.windows(2).all(|pair| cmp(pair[0], pair[1]));
+    let res = boolean(&env, ordered);
+    Ok((env, res))
+}
+
+fn fn_string_eq(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_strings(env, args, "string=?", |a, b| a == b)
+}
+
+fn fn_string_lt(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_strings(env, args, "string<?", |a, b| a < b)
+}
+
+fn fn_string_gt(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_strings(env, args, "string>?", |a, b| a > b)
+}
+
+fn fn_string_le(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_strings(env, args, "string<=?", |a, b| a <= b)
+}
+
+fn fn_string_ge(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    compare_strings(env, args, "string>=?", |a, b| a >= b)
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("string-length"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_length))));
+    table.insert(String::from("string-append"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_append))));
+    table.insert(String::from("substring"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_substring))));
+    table.insert(String::from("string-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_ref))));
+    table.insert(String::from("string-upcase"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_upcase))));
+    table.insert(String::from("string-downcase"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_downcase))));
+    table.insert(String::from("string-index"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_string_index))));
+    table.insert(String::from("string-split"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_split))));
+    table.insert(String::from("string-join"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_join))));
+    table.insert(String::from("string-contains"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_contains))));
+    table.insert(String::from("string-trim"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_trim))));
+    table.insert(String::from("string->number"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_to_number))));
+    table.insert(String::from("number->string"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_number_to_string))));
+    table.insert(String::from("string->list"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_to_list))));
+    table.insert(String::from("string=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_eq))));
+    table.insert(String::from("string<?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_lt))));
+    table.insert(String::from("string>?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_gt))));
+    table.insert(String::from("string<=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_le))));
+    table.insert(String::from("string>=?"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_string_ge))));
+}
@@ -0,0 +1,146 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult};
+use super::machine::Machine;
+use super::compiler::Form;
+use super::stdlib::expect_arity;
+use super::super::parser::expressions::{Expression, Expression::*};
+use super::super::parser::main::SyntaxError;
+
+use std::rc::Rc;
+
+// what `(values v ...)` returns: a single value stands for itself, anything else is wrapped
+pub fn values(mut vals: Vec<Rc<Value>>) -> Rc<Value> {
+    if vals.len() == 1 {
+        return vals.remove(0);
+    }
+    Rc::new(Value::Values(vals))
+}
+
+// the values a procedure returned, as arguments for another
+pub fn spread(val: Rc<Value>) -> Vec<Rc<Value>> {
+    match &*val {
+        Value::Values(vals) => vals.clone(),
+        _ => vec![val]
+    }
+}
+
+// the names values are bound to: `(a b c)` takes exactly three, and a bare `rest` takes any
+// number as a list
+#[derive(Debug)]
+pub enum Formals {
+    Fixed(Vec<String>),
+    Rest(String)
+}
+
+impl Formals {
+    fn parse(expr: &Expression) -> Option<Formals> {
+        match expr {
+            LookupExpr(name) => Some(Formals::Rest(name.to_string())),
+            _ => items(expr)?.iter()
+                .map(|item| match &**item {
+                    LookupExpr(name) => Some(name.to_string()),
+                    _ => None
+                })
+                .collect::<Option<Vec<String>>>()
+                .map(Formals::Fixed)
+        }
+    }
+    pub fn bind(&self, val: Rc<Value>) -> Result<HashMap<String, Rc<Value>>, RuntimeError> {
+        let vals = spread(val);
+        let mut bindings = HashMap::new();
+        match self {
+            Formals::Fixed(names) if names.len() != vals.len() =>
+                return Err(RuntimeError::ArityError(format!("expected {} values, got {}", names.len(), vals.len()))),
+            Formals::Fixed(names) => names.iter().zip(vals).for_each(|(name, val)| {
+                bindings.insert(name.clone(), val);
+            }),
+            Formals::Rest(name) => {
+                bindings.insert(name.clone(), Value::list(vals));
+            }
+        }
+        Ok(bindings)
+    }
+}
+
+// the elements of a parenthesised form, which parses as an s-expression or, when empty, a list
+pub fn items(expr: &Expression) -> Option<Vec<Rc<Expression>>> {
+    match expr {
+        SExpr(head, rest) => {
+            let mut items = vec![head.clone()];
+            items.extend(rest.iter().cloned());
+            Some(items)
+        },
+        ListExpr(items) => Some(items.to_vec()),
+        _ => None
+    }
+}
+
+fn malformed(expr: &Expression) -> RuntimeError {
+    RuntimeError::Syntax(SyntaxError::MalformedValues(expr.to_string()))
+}
+
+fn fn_values(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, values(args)))
+}
+
+// (call-with-values producer consumer) calls consumer with the values producer returns
+fn fn_call_with_values(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> Result<(), RuntimeError> {
+    expect_arity("call-with-values", &args, 2, 2)?;
+    machine.call_with_values(args[0].clone(), args[1].clone(), env)
+}
+
+// (receive formals expr body ...)
+fn fn_receive(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> Result<(), RuntimeError> {
+    let (formals, producer) = match &form.parts[..] {
+        [_, formals, producer, _, ..] => (Formals::parse(&formals.expr).ok_or_else(|| malformed(&form.expr))?, producer.clone()),
+        _ => return Err(malformed(&form.expr))
+    };
+    machine.receive(Rc::new(formals), producer, form, env)
+}
+
+// the `((formals init) ...)` of a let-values
+fn value_bindings(form: &Form) -> Result<Vec<(Formals, Rc<Form>)>, RuntimeError> {
+    let bindings = match &form.parts[..] {
+        [_, bindings, _, ..] if items(&bindings.expr).is_some() => &bindings.parts,
+        _ => return Err(malformed(&form.expr))
+    };
+    bindings.iter()
+        .map(|binding| match &binding.parts[..] {
+            [formals, init] => Formals::parse(&formals.expr).map(|formals| (formals, init.clone())).ok_or_else(|| malformed(&form.expr)),
+            _ => Err(malformed(&form.expr))
+        })
+        .collect()
+}
+
+// (let-values (((a b) init) ...) body ...) evaluates every init before binding any of them
+fn fn_let_values(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> Result<(), RuntimeError> {
+    let bindings = value_bindings(&form)?;
+    machine.let_values(Rc::new(bindings), form, false, env)
+}
+
+// (let*-values (((a b) init) ...) body ...) binds each in turn, so later inits can see earlier names
+fn fn_let_star_values(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> Result<(), RuntimeError> {
+    let bindings = value_bindings(&form)?;
+    machine.let_values(Rc::new(bindings), form, true, env)
+}
+
+// (define-values formals expr)
+fn fn_define_values(machine: &mut Machine, env: Rc<Env>, form: Rc<Form>) -> Result<(), RuntimeError> {
+    match &form.parts[..] {
+        [_, formals, producer] => {
+            let formals = Formals::parse(&formals.expr).ok_or_else(|| malformed(&form.expr))?;
+            machine.define_values(Rc::new(formals), producer.clone(), env)
+        },
+        _ => Err(malformed(&form.expr))
+    }
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_values))));
+    table.insert(String::from("call-with-values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_call_with_values))));
+    table.insert(String::from("receive"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_receive))));
+    table.insert(String::from("let-values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_let_values))));
+    table.insert(String::from("let*-values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_let_star_values))));
+    table.insert(String::from("define-values"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Syntax(fn_define_values))));
+}
@@ -0,0 +1,184 @@
+extern crate im;
+use im::hashmap::HashMap;
+use super::main::{Env, Value, RuntimeFunctionWrapper, RuntimeError, EvalResult, StepResult};
+use super::machine::Machine;
+use super::stdlib::{expect_arity, expect_int, expect_list};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Slots = Rc<RefCell<Vec<Rc<Value>>>>;
+
+fn new_vector(items: Vec<Rc<Value>>) -> Rc<Value> {
+    Rc::new(Value::Vector(Rc::new(RefCell::new(items))))
+}
+
+fn expect_vector(name: &str, val: &Value) -> Result<Slots, RuntimeError> {
+    match val {
+        Value::Vector(slots) => Ok(slots.clone()),
+        _ => Err(RuntimeError::TypeError(format!("{} expects a vector, got {}", name, val)))
+    }
+}
+
+fn expect_slot(name: &str, val: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let index = expect_int(name, val)?;
+    if index < 0 || index as usize >= len {
+        return Err(RuntimeError::RangeError(format!("{}: index {} not in 0..{}", name, index, len)));
+    }
+    Ok(index as usize)
+}
+
+// the optional `[start [end]]` arguments of vector-copy and vector-fill!
+pub fn expect_range(name: &str, args: &[Rc<Value>], len: usize) -> Result<(usize, usize), RuntimeError> {
+    let bound = |val: &Value| {
+        let index = expect_int(name, val)?;
+        if index < 0 || index as usize > len {
+            return Err(RuntimeError::RangeError(format!("{}: index {} not in 0..{}", name, index, len)));
+        }
+        Ok(index as usize)
+    };
+    let start = match args.first() {
+        Some(start) => bound(start)?,
+        None => 0
+    };
+    let end = match args.get(1) {
+        Some(end) => bound(end)?,
+        None => len
+    };
+    if start > end {
+        return Err(RuntimeError::RangeError(format!("{}: start {} is after end {}", name, start, end)));
+    }
+    Ok((start, end))
+}
+
+fn fn_vector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    Ok((env, new_vector(args)))
+}
+
+fn fn_make_vector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("make-vector", &args, 1, 2)?;
+    let k = expect_int("make-vector", &args[0])?;
+    if k < 0 {
+        return Err(RuntimeError::RangeError(format!("make-vector: negative length {}", k)));
+    }
+    let fill = args.get(1).cloned().unwrap_or_else(|| Rc::new(Value::Nil));
+    Ok((env, new_vector(vec![fill; k as usize])))
+}
+
+fn fn_vector_ref(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector-ref", &args, 2, 2)?;
+    let slots = expect_vector("vector-ref", &args[0])?;
+    let slots = slots.borrow();
+    let k = expect_slot("vector-ref", &args[1], slots.len())?;
+    Ok((env, slots[k].clone()))
+}
+
+fn fn_vector_set(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector-set!", &args, 3, 3)?;
+    let slots = expect_vector("vector-set!", &args[0])?;
+    let mut slots = slots.borrow_mut();
+    let k = expect_slot("vector-set!", &args[1], slots.len())?;
+    slots[k] = args[2].clone();
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_vector_length(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector-length", &args, 1, 1)?;
+    let slots = expect_vector("vector-length", &args[0])?;
+    let len = slots.borrow().len();
+    Ok((env, Rc::new(Value::Int(len as i32))))
+}
+
+fn fn_vector_to_list(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector->list", &args, 1, 3)?;
+    let slots = expect_vector("vector->list", &args[0])?;
+    let slots = slots.borrow();
+    let (start, end) = expect_range("vector->list", &args[1..], slots.len())?;
+    Ok((env, Value::list(slots[start..end].to_vec())))
+}
+
+fn fn_list_to_vector(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("list->vector", &args, 1, 1)?;
+    let items = expect_list("list->vector", &args[0])?;
+    Ok((env, new_vector(items)))
+}
+
+// the argument lists for calling `proc` across several vectors, stopping at the shortest
+fn transpose(name: &str, vectors: &[Rc<Value>]) -> Result<Vec<Vec<Rc<Value>>>, RuntimeError> {
+    let vectors: Result<Vec<Vec<Rc<Value>>>, RuntimeError> = vectors.iter()
+        .map(|vector| expect_vector(name, vector).map(|slots| slots.borrow().clone()))
+        .collect();
+    let vectors = vectors?;
+    let len = vectors.iter().map(|slots| slots.len()).min().unwrap_or(0);
+    Ok((0..len).map(|i| vectors.iter().map(|slots| slots[i].clone()).collect()).collect())
+}
+
+type Calls = Rc<Vec<Vec<Rc<Value>>>>;
+
+// calls `proc` with each argument list from `index` on, returning a vector of what it returned
+// after `done`. Each step keeps its own results, so resuming a continuation captured in one
+// doesn't disturb the vector another has made
+fn map_from(machine: &mut Machine, proc: Rc<Value>, calls: Calls, index: usize, done: im::Vector<Rc<Value>>, env: Rc<Env>) -> StepResult {
+    if index == calls.len() {
+        return machine.ret(new_vector(done.into_iter().collect()), env);
+    }
+    machine.call_then(proc.clone(), calls[index].clone(), env, move |machine, val, env| {
+        let mut done = done.clone();
+        done.push_back(val);
+        map_from(machine, proc.clone(), calls.clone(), index + 1, done, env)
+    })
+}
+
+fn for_each_from(machine: &mut Machine, proc: Rc<Value>, calls: Calls, index: usize, env: Rc<Env>) -> StepResult {
+    if index == calls.len() {
+        return machine.ret(Rc::new(Value::Nil), env);
+    }
+    machine.call_then(proc.clone(), calls[index].clone(), env, move |machine, _, env| {
+        for_each_from(machine, proc.clone(), calls.clone(), index + 1, env)
+    })
+}
+
+fn fn_vector_map(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("vector-map", &args, 2, usize::MAX)?;
+    let calls = Rc::new(transpose("vector-map", &args[1..])?);
+    map_from(machine, args[0].clone(), calls, 0, im::Vector::new(), env)
+}
+
+fn fn_vector_for_each(machine: &mut Machine, env: Rc<Env>, args: Vec<Rc<Value>>) -> StepResult {
+    expect_arity("vector-for-each", &args, 2, usize::MAX)?;
+    let calls = Rc::new(transpose("vector-for-each", &args[1..])?);
+    for_each_from(machine, args[0].clone(), calls, 0, env)
+}
+
+fn fn_vector_fill(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector-fill!", &args, 2, 4)?;
+    let slots = expect_vector("vector-fill!", &args[0])?;
+    let mut slots = slots.borrow_mut();
+    let (start, end) = expect_range("vector-fill!", &args[2..], slots.len())?;
+    for slot in slots[start..end].iter_mut() {
+        *slot = args[1].clone();
+    }
+    Ok((env, Rc::new(Value::Nil)))
+}
+
+fn fn_vector_copy(env: Rc<Env>, args: Vec<Rc<Value>>) -> EvalResult {
+    expect_arity("vector-copy", &args, 1, 3)?;
+    let slots = expect_vector("vector-copy", &args[0])?;
+    let slots = slots.borrow();
+    let (start, end) = expect_range("vector-copy", &args[1..], slots.len())?;
+    Ok((env, new_vector(slots[start..end].to_vec())))
+}
+
+pub fn add_builtins(table: &mut HashMap<String, Rc<Value>>) {
+    table.insert(String::from("vector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector))));
+    table.insert(String::from("make-vector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_make_vector))));
+    table.insert(String::from("vector-ref"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_ref))));
+    table.insert(String::from("vector-set!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_set))));
+    table.insert(String::from("vector-length"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_length))));
+    table.insert(String::from("vector->list"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_to_list))));
+    table.insert(String::from("list->vector"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_list_to_vector))));
+    table.insert(String::from("vector-map"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_vector_map))));
+    table.insert(String::from("vector-for-each"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Control(fn_vector_for_each))));
+    table.insert(String::from("vector-fill!"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_fill))));
+    table.insert(String::from("vector-copy"), Rc::new(Value::RuntimeFunction(RuntimeFunctionWrapper::Immediate(fn_vector_copy))));
+}
@@ -0,0 +1,534 @@
+// Runs the interpreter binary over the scripts in tests/scripts and one-liners given with -e,
+// checking what it prints and the status it exits with.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn scripts_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("scripts")
+}
+
+// runs from the scripts directory, so scripts and libraries can be named relative to it
+fn lisp(args: &[&str]) -> Output {
+    lisp_with_input(args, "")
+}
+
+fn lisp_with_input(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust"))
+        .args(args)
+        .current_dir(scripts_dir())
+        .env_remove("RUST_LISP_PRELUDE")
+        .env_remove("RUST_LISP_PATH")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("couldn't run the interpreter");
+    child.stdin.take().expect("stdin is piped").write_all(input.as_bytes()).expect("couldn't write stdin");
+    child.wait_with_output().expect("couldn't wait for the interpreter")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// the printed results of a successful -e, one per line
+fn eval(source: &str) -> String {
+    eval_with(&[], source)
+}
+
+fn eval_with(options: &[&str], source: &str) -> String {
+    let output = lisp(&[options, &["-e", source]].concat());
+    assert!(output.status.success(), "{} failed: {}", source, stderr(&output));
+    stdout(&output)
+}
+
+// the message of a failed -e
+fn eval_err(source: &str) -> String {
+    let output = lisp(&["-e", source]);
+    assert_eq!(output.status.code(), Some(1), "{} didn't fail: {}", source, stdout(&output));
+    stderr(&output)
+}
+
+#[test]
+fn script_gets_command_line_and_exit_status() {
+    let output = lisp(&["command-line.scm", "one", "two"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(eval("(len (command-line))"), "1\n");
+    let output = lisp(&["-e", "(car (cdr (command-line)))", "hello"]);
+    assert_eq!(stdout(&output), "\"hello\"\n");
+}
+
+#[test]
+fn eval_option_prints_each_result() {
+    assert_eq!(eval("(+ 1 2) (let x 4) x"), "3\n4\n");
+    assert_eq!(lisp(&["-e", "(exit 4) 5"]).status.code(), Some(4));
+}
+
+#[test]
+fn syntax_errors_fail_with_a_message() {
+    assert_eq!(eval_err("(let x)"), "syntax error: malformed let\n");
+    let output = lisp(&["unbalanced.scm"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "unbalanced.scm: syntax error: unexpected token: Eof\n");
+}
+
+#[test]
+fn missing_option_argument_is_a_usage_error() {
+    let output = lisp(&["-e"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("usage:"));
+}
+
+#[test]
+fn embedded_prelude_is_loaded_from_anywhere() {
+    assert_eq!(eval("(fact 5)"), "120\n");
+}
+
+#[test]
+fn prelude_can_be_replaced_or_left_out() {
+    assert_eq!(eval_with(&["--prelude", "prelude.scm"], "(twice 21) fact"), "42\n()\n");
+    assert_eq!(eval_with(&["--no-prelude"], "fact (+ 1 2)"), "()\n3\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_rust"))
+        .args(["-e", "(twice 4)"])
+        .current_dir(scripts_dir())
+        .env("RUST_LISP_PRELUDE", "prelude.scm")
+        .output()
+        .expect("couldn't run the interpreter");
+    assert_eq!(stdout(&output), "8\n");
+    let output = lisp(&["--prelude", "missing.scm", "-e", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("couldn't load missing.scm: i/o error: couldn't read missing.scm: "));
+    let output = lisp(&["--prelude", "unbalanced.scm", "-e", "1"]);
+    assert_eq!(stderr(&output), "couldn't load unbalanced.scm: syntax error: unexpected token: Eof\n");
+}
+
+#[test]
+fn import_sees_only_exported_names() {
+    assert_eq!(eval("(import (our utils)) (double 4) (thrice 2) hidden square"), "()\n8\n12\n()\n()\n");
+    assert_eq!(eval("(import (prefix (our utils) u:)) (u:double 4)"), "()\n8\n");
+    assert_eq!(eval("(import (except (our math) cube)) (square 3) cube"), "()\n9\n()\n");
+    assert_eq!(eval("(import (rename (our math) (square sq))) (sq 5)"), "()\n25\n");
+    assert_eq!(eval("(import (only (our math) square)) (square 2) cube"), "()\n4\n()\n");
+}
+
+#[test]
+fn bad_imports_are_module_errors() {
+    assert_eq!(eval_err("(import (our loop))"), "module error: cyclic import: (our loop) -> (our loop)\n");
+    assert_eq!(eval_err("(import (our missing))"), "module error: library (our missing) not found in search path\n");
+    assert_eq!(eval_err("(import (only (our math) nope))"), "module error: cannot import missing name nope\n");
+}
+
+#[test]
+fn include_and_load_resolve_relative_to_the_file() {
+    assert_eq!(lisp(&["include.scm"]).status.code(), Some(42));
+    assert_eq!(eval("(include \"include/helper.scm\") (helper 1)"), "()\n41\n");
+    assert_eq!(eval("(load \"include/loaded.scm\") loaded"), "()\n2\n");
+}
+
+#[test]
+fn bad_includes_and_loads_fail_with_a_message() {
+    let output = lisp(&["include/cycle-a.scm"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "include/cycle-a.scm: syntax error: cyclic include: include/cycle-a.scm is already being included\n"
+    );
+    assert!(eval_err("(include \"nope.scm\")").starts_with("syntax error: bad include: nope.scm: "));
+    assert!(eval_err("(load \"nope.scm\")").starts_with("i/o error: couldn't read nope.scm: "));
+    assert_eq!(eval_err("(load 1)"), "type error: load expects a file name\n");
+}
+
+#[test]
+fn string_builtins_index_by_char() {
+    assert_eq!(eval("(string-length \"héllo\") (string-ref \"héllo\" 1) (substring \"héllo\" 1 3)"), "5\n#\\é\n\"él\"\n");
+    assert_eq!(eval("(string-append \"a\" \"b\" \"c\") (string-upcase \"straße\")"), "\"abc\"\n\"STRASSE\"\n");
+    assert_eq!(eval("(string-index \"héllo\" #\\l) (string-contains \"héllo\" \"lo\")"), "2\n3\n");
+    assert_eq!(eval("(string-join (string-split \" a b  c \") \"-\") (car (cdr (string->list \"héllo\")))"), "\"a-b-c\"\n#\\é\n");
+    assert_eq!(eval("((string<? \"a\" \"b\" \"c\") 1 2) ((string=? \"a\" \"b\") 1 2)"), "1\n2\n");
+}
+
+#[test]
+fn number_conversions_take_a_radix() {
+    assert_eq!(eval("(string->number \"42\") (string->number \"2.5\") (string->number \"ff\" 16)"), "42\n2.5\n255\n");
+    assert_eq!(eval("((string->number \"2.5\" 16) 1 2) ((string->number \"12\" 2) 1 2)"), "2\n2\n");
+    assert_eq!(eval("(number->string 255 16) (number->string (- 5 0) 2) (number->string 0 8) (number->string 1.5)"), "\"ff\"\n\"-101\"\n\"0\"\n\"1.5\"\n");
+}
+
+#[test]
+fn bad_string_arguments_are_errors() {
+    assert_eq!(eval_err("(substring \"abc\" 2 5)"), "out of range: substring: index 5 not in 0..3\n");
+    assert_eq!(eval_err("(string-length 1)"), "type error: string-length expects a string, got 1\n");
+    assert_eq!(eval_err("(string-ref \"abc\")"), "arity error: string-ref expects 2 arguments, got 1\n");
+    assert_eq!(eval_err("(number->string 1 37)"), "out of range: number->string: radix 37 not in 2..36\n");
+    assert_eq!(eval_err("(number->string 1.5 2)"), "out of range: number->string: floats can't be written in radix 2\n");
+    assert_eq!(eval_err("(string-join 5)"), "type error: string-join expects a list, got 5\n");
+}
+
+#[test]
+fn string_escapes() {
+    assert_eq!(eval(r#""a\tb\x41;\n" (string-length "\x3bb;\\\"") "\u{3bb}""#), "\"a\\tbA\\n\"\n3\n\"λ\"\n");
+    assert_eq!(eval("\"one \\\n    two\""), "\"one two\"\n");
+    assert_eq!(eval_err(r#""\q""#), "syntax error: bad escape: \\q\n");
+    assert_eq!(eval_err(r#""\x41""#), "syntax error: bad escape: \\x41 missing ';'\n");
+    assert_eq!(eval_err(r#""abc"#), "syntax error: unterminated string\n");
+}
+
+#[test]
+fn characters() {
+    assert_eq!(eval(r"#\a #\space #\x41 (char->integer #\A) (char-upcase #\a) ((char<? #\a #\b) 1 2)"), "#\\a\n#\\space\n#\\A\n65\n#\\A\n1\n");
+    assert_eq!(eval(r#"(string-ref "abc" 1) (car (string->list "hi")) (string-index "héllo" #\l) ((= #\a #\a) 1 2)"#), "#\\b\n#\\h\n2\n1\n");
+    assert_eq!(eval_err(r"#\bogus"), "syntax error: bad character: bogus\n");
+    assert_eq!(eval_err(r"(char->integer 1)"), "type error: char->integer expects a character, got 1\n");
+}
+
+#[test]
+fn vectors() {
+    assert_eq!(eval("#(1 2) (vector-ref (vector-map + #(1 2) #(10 20 30)) 1) (vector-length (make-vector 3 0))"), "#(1 2)\n22\n3\n");
+    assert_eq!(eval("(let v (vector 1 2 3)) (vector-set! v 0 9) (vector-ref v 0) (car (cdr (vector->list v))) (vector-ref (vector-copy v 1) 0)"), "()\n9\n2\n2\n");
+    assert_eq!(eval("(vector-ref (list->vector (vector->list #(4 5))) 1)"), "5\n");
+}
+
+#[test]
+fn bad_vector_arguments_are_errors() {
+    assert_eq!(eval_err("(vector-ref #(1 2) 2)"), "out of range: vector-ref: index 2 not in 0..2\n");
+    assert_eq!(eval_err("(vector-ref 1 2)"), "type error: vector-ref expects a vector, got 1\n");
+    assert_eq!(eval_err("(make-vector (- 1 0))"), "out of range: make-vector: negative length -1\n");
+    assert_eq!(eval_err("(list->vector 3)"), "type error: list->vector expects a list, got 3\n");
+}
+
+#[test]
+fn hash_tables() {
+    let setup = r#"(let h (make-hash-table)) (hash-table-set! h "a" 1) (hash-table-set! h (vector 1) 2) "#;
+    assert_eq!(eval(&format!("{}{}", setup, r#"(hash-table-ref h "a") (hash-table-ref h (vector 1)) ((hash-table-contains? h "b") 1 2)"#)), "()\n()\n1\n2\n2\n");
+    assert_eq!(eval(&format!("{}{}", setup, r#"(hash-table-update! h "a" (lambda (x) (+ x 10))) (hash-table-ref h "a") (hash-table-delete! h "a") (hash-table-count h)"#)), "()\n()\n()\n11\n()\n1\n");
+    assert_eq!(eval(&format!("{}{}", setup, r#"(hash-table-delete! h "a") (cdr (car (hash-table->alist h))) (hash-table-ref h "zz" (lambda () 7))"#)), "()\n()\n()\n2\n7\n");
+    assert_eq!(eval("(let e (make-hash-table eq?)) (hash-table-set! e (vector 1) 1) ((hash-table-contains? e (vector 1)) 1 2)"), "()\n2\n");
+}
+
+#[test]
+fn bad_hash_table_arguments_are_errors() {
+    assert_eq!(eval_err("(hash-table-ref (make-hash-table) \"zz\")"), "out of range: hash-table-ref: no value for key \"zz\"\n");
+    assert_eq!(eval_err("(hash-table-set! 1 2 3)"), "type error: hash-table-set! expects a hash table, got 1\n");
+}
+
+#[test]
+fn persistent_maps_and_sets() {
+    assert_eq!(eval("(let m {1 \"one\" \"k\" 2}) (map-get m \"k\") (map-get (map-assoc m 3 4) 3) ((map-contains? (map-dissoc m 1) 1) 1 2) (map-get m 3)"), "2\n4\n2\n()\n");
+    assert_eq!(eval("((set-contains? (set-add #{1 2} (vector 3)) (vector 3)) 1 2) (car (set->list (set-intersection #{1 2} #{2 3})))"), "1\n2\n");
+    assert_eq!(eval_err("{1}"), "syntax error: malformed map literal\n");
+    assert_eq!(eval_err("(map-get 1 2)"), "type error: map-get expects a map, got 1\n");
+}
+
+#[test]
+fn sorted_map_keys_compare_like_equal() {
+    assert_eq!(eval("(let s (sorted-map (vector 1 2) 2 {1 2} 3 #{1 2} 4 \"a\" 5)) (map-get s (vector 1 2)) (map-get s {1 2}) (map-get s #{2 1}) (car (map-keys s))"), "2\n3\n4\n\"a\"\n");
+    assert_eq!(eval("(map-get (sorted-map car 1) car)"), "1\n");
+}
+
+#[test]
+fn bytevectors() {
+    assert_eq!(eval("#u8(1 255) (bytevector-u8-ref (bytevector-append #u8(1) #u8(2)) 1) (bytevector-length (bytevector-copy #u8(1 2 3) 1))"), "#u8(1 255)\n2\n2\n");
+    assert_eq!(eval("(let b (make-bytevector 4 0)) (bytevector-u16-le-set! b 0 258) (bytevector-u8-ref b 0) (bytevector-u16-be-ref b 0) (bytevector-s32-le-ref #u8(255 255 255 255) 0)"), "()\n2\n513\n-1\n");
+    assert_eq!(eval("(utf8->string (string->utf8 \"h\u{e9}\")) ((equal? #u8(1 2) #u8(1 2)) 1 2) (map-get (sorted-map #u8(1 2) 1 (vector 1 2) 2) #u8(1 2))"), "\"h\u{e9}\"\n1\n1\n");
+    assert_eq!(eval_err("#u8(256)"), "syntax error: bad byte: 256\n");
+    assert_eq!(eval_err("(bytevector-u8-ref #u8(1) 1)"), "out of range: bytevector-u8-ref: 1 bytes at index 1 don't fit in 0..1\n");
+}
+
+#[test]
+fn records() {
+    let point = "(define-record-type point (make-point x y) point? (x point-x set-point-x!) (y point-y)) ";
+    let run = |source: &str| eval(&format!("{}{}", point, source));
+    assert_eq!(run("(let p (make-point 1 2)) (point-x p) (set-point-x! p 5) p ((point? p) 1 2) ((point? 1) 1 2)"), "()\n1\n()\n#<point x: 5 y: 2>\n1\n2\n");
+    let fail = |source: &str| lisp(&["-e", &format!("{}{}", point, source)]);
+    assert_eq!(stderr(&fail("(make-point 1)")), "arity error: make-point expects 2 arguments, got 1\n");
+    assert_eq!(stderr(&fail("(point-x (make-point 1 2) 3)")), "arity error: point-x expects 1 arguments, got 2\n");
+    assert_eq!(stderr(&fail("(set-point-x! (make-point 1 2) 3 4)")), "arity error: set-point-x! expects 2 arguments, got 3\n");
+    assert_eq!(stderr(&fail("(point-x 5)")), "type error: point-x expects a point, got 5\n");
+}
+
+#[test]
+fn lambdas_ignore_extra_and_missing_arguments() {
+    assert_eq!(eval("((lambda (x y) x) 1) ((lambda (x) x) 1 2)"), "1\n1\n");
+}
+
+#[test]
+fn equality_predicates() {
+    assert_eq!(eval("((eq? (vector) (vector)) 1 2) ((eqv? 1.5 1.5) 1 2) ((eq? 1.5 1.5) 1 2) ((equal? (vector 1 \"a\") (vector 1 \"a\")) 1 2) ((equal? {1 2} (sorted-map 1 2)) 1 2)"), "2\n1\n2\n1\n1\n");
+    assert_eq!(eval("((equal? (cons 1 (cons 2 nil)) (cons 1 (cons 2 nil))) 1 2) ((eq? (cons 1 nil) (cons 1 nil)) 1 2) ((= 1 1.0) 1 2)"), "1\n2\n1\n");
+    let cycles = "(let v (vector 1)) (vector-set! v 0 v) (let w (vector 1)) (vector-set! w 0 w) ((equal? v w) 1 2)";
+    assert_eq!(eval(cycles), "()\n()\n1\n");
+}
+
+#[test]
+fn native_pairs_hold_builtin_lists() {
+    assert_eq!(eval("(car (cdr (string-split \"a b c\")))"), "\"b\"\n");
+    assert_eq!(eval("(cdr (cons 1 2)) (string-join (vector->list #(\"x\" \"y\")) \",\")"), "2\n\"x,y\"\n");
+    assert_eq!(eval_err("(car 1)"), "type error: car expects a pair, got 1\n");
+    assert_eq!(eval_err("(cons 1)"), "arity error: cons expects 2 arguments, got 1\n");
+}
+
+#[test]
+fn sorted_maps_order_pairs_and_records() {
+    let point = "(define-record-type point (make-point x y) point? (x point-x) (y point-y)) ";
+    assert_eq!(eval(&format!("{}{}", point, "(map-get (sorted-map (make-point 1 2) \"a\" (make-point 1 3) \"b\") (make-point 1 3))")), "()\n\"b\"\n");
+    assert_eq!(eval("(map-get (sorted-map (cons 1 (cons 2 nil)) 1 (cons 1 (cons 3 nil)) 2) (cons 1 (cons 3 nil)))"), "2\n");
+}
+
+#[test]
+fn values_print_like_write() {
+    assert_eq!(eval(r#""a\nb" #\a (cons 1 (cons 2 3)) 1.5 car + fact (lambda (x) x) (= 1 1)"#), "\"a\\nb\"\n#\\a\n(1 2 . 3)\n1.5\n#<builtin car>\n#<builtin +>\n#<procedure fact>\n#<procedure>\n#t\n");
+    assert_eq!(eval("(let v (vector 1)) (vector-set! v 0 v) v (cons v (cons v nil))"), "()\n#0=#(#0#)\n(#0=#(#0#) #0#)\n");
+}
+
+#[test]
+fn errors_quote_the_source_they_came_from() {
+    assert_eq!(eval_err("(\"s\" 1)"), "not a procedure: cannot evaluate rator for s-expr \"s\"\n");
+    assert_eq!(eval_err("#u8(1 #\\a)"), "syntax error: bad byte: #\\a\n");
+    assert_eq!(eval_err("(define-record-type p)"), "syntax error: malformed define-record-type: (define-record-type p)\n");
+}
+
+#[test]
+fn console_input_and_output() {
+    let output = lisp_with_input(&["-e", "(read-line) (read) (read-char) (peek-char) (read-line) ((eof-object? (read-char)) 1 2)"], "line one\n(1 \"two\" #\\3) rest");
+    assert_eq!(stdout(&output), "\"line one\"\n(1 \"two\" #\\3)\n#\\space\n#\\r\n\"rest\"\n1\n");
+    assert_eq!(eval("(display \"a\") (write-string \"b\") (write \"c\") (write-char #\\d) (newline)"), "a()\nb()\n\"c\"()\nd()\n\n()\n");
+    let output = lisp_with_input(&["-e", "(read)"], "(1 2");
+    assert_eq!(stderr(&output), "read error: input ends partway through a datum\n");
+    assert_eq!(eval_err("(write-string 1)"), "type error: write-string expects a string, got 1\n");
+}
+
+#[test]
+fn string_and_bytevector_ports() {
+    assert_eq!(eval(r#"(let p (open-input-string "ab (1 2) cdef")) (read-char p) (read p) (read p) (read-string 3 p) (eof-object? (read p))"#), "#\\a\nb\n(1 2)\n\" cd\"\n#f\n");
+    assert_eq!(eval(r#"(let o (open-output-string)) (write "x" o) (display 1 o) (get-output-string o) (with-output-to-string (lambda () (display "hi" (current-output-port))))"#), "()\n()\n\"\\\"x\\\"1\"\n\"hi\"\n");
+    assert_eq!(eval("(let b (open-input-bytevector #u8(1 2))) (read-u8 b) (read-bytevector 5 b) (let o (open-output-bytevector)) (write-u8 7 o) (get-output-bytevector o)"), "1\n#u8(2)\n()\n#u8(7)\n");
+    assert_eq!(eval_err(r#"(let p (open-input-string "a")) (close-port p) (read-char p)"#), "i/o error: read-char: port is closed\n");
+    assert_eq!(eval_err("(read-u8 (open-input-string \"a\"))"), "type error: read-u8 expects a binary input port\n");
+}
+
+#[test]
+fn file_ports_and_file_system() {
+    let dir = std::env::temp_dir().join(format!("rust-lisp-files-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("couldn't make a scratch directory");
+    let source = format!(r#"(let dir "{}/")
+        (call-with-output-file (string-append dir "a.txt") (lambda (port) (display "file text" port)))
+        (call-with-input-file (string-append dir "a.txt") read-line)
+        (with-output-to-file (string-append dir "b.txt") (lambda () (display "12")))
+        (with-input-from-file (string-append dir "b.txt") read)
+        (file-size (string-append dir "b.txt"))
+        (make-directory (string-append dir "d"))
+        (rename-file (string-append dir "b.txt") (string-append dir "d/c.txt"))
+        (delete-file (string-append dir "a.txt"))
+        (file-exists? (string-append dir "a.txt"))
+        (directory-list dir)"#, dir.display());
+    assert_eq!(eval(&source), "()\n\"file text\"\n()\n12\n2\n()\n()\n()\n#f\n(\"d\")\n");
+    let missing = format!(r#"(delete-file "{}/missing")"#, dir.display());
+    assert!(eval_err(&missing).starts_with("i/o error: delete-file:"));
+    let missing = format!(r#"(call-with-input-file "{}/missing" read-line)"#, dir.display());
+    assert!(eval_err(&missing).starts_with("i/o error:"));
+    std::fs::remove_dir_all(&dir).expect("couldn't clean up the scratch directory");
+}
+
+#[test]
+fn exit_status_and_processes() {
+    let output = lisp(&["-e", "(display \"before\") (exit 4) (display \"after\")"]);
+    assert_eq!((output.status.code(), stdout(&output)), (Some(4), String::from("before()\n")));
+    assert_eq!(lisp(&["-e", "(exit #f)"]).status.code(), Some(1));
+    assert_eq!(lisp(&["-e", "(emergency-exit 5)"]).status.code(), Some(5));
+    assert_eq!(eval_err("(exit \"x\")"), "type error: exit: bad exit status: \"x\"\n");
+    assert_eq!(eval("(run-process \"echo\" \"hi\")"), "(0 \"hi\\n\" \"\")\n");
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets the manifest dir");
+    assert_eq!(eval("(get-environment-variable \"CARGO_MANIFEST_DIR\")"), format!("\"{}\"\n", manifest_dir));
+}
+
+#[test]
+fn errors_raise_and_guard() {
+    assert_eq!(eval("(guard (e ((error-object? e) (error-object-message e)) (else e)) (car 5))"), "\"car expects a pair, got 5\"\n");
+    assert_eq!(eval("(guard (e ((error-object? e) 0) (else (string-append \"caught \" e))) (raise \"it\"))"), "\"caught it\"\n");
+    assert_eq!(eval("(guard (e (#t (error-object-irritants e))) (error \"bad\" 1 2))"), "(1 2)\n");
+    assert_eq!(eval("(guard (e ((= e 5) => (lambda (x) (cons x e)))) (raise 5))"), "(#t . 5)\n");
+    assert_eq!(eval("(with-exception-handler (lambda (e) 10) (lambda () (+ 1 (raise-continuable 5))))"), "11\n");
+    assert_eq!(eval_err("(error \"bad thing\" 7)"), "error: bad thing 7\n");
+}
+
+#[test]
+fn unhandled_and_uncaught_errors() {
+    assert_eq!(eval_err("(guard (e (#f 0)) (car 5))"), "type error: car expects a pair, got 5\n");
+    assert_eq!(eval("(guard (outer (#t (cons 1 outer))) (guard (inner ((= inner 1) 0)) (raise 2)))"), "(1 . 2)\n");
+    assert_eq!(eval_err("(with-exception-handler (lambda (e) 10) (lambda () (raise 5)))"), "error: handler returned from non-continuable raise 5\n");
+    assert_eq!(eval_err("(guard)"), "syntax error: malformed guard: (guard)\n");
+}
+
+#[test]
+fn division_by_zero_is_a_range_error() {
+    assert_eq!(eval("(/ 2 10) (/ 0.0 1)"), "5\n+inf.0\n");
+    assert_eq!(eval_err("(/ 0 1)"), "out of range: /: division of 1 by zero\n");
+    assert_eq!(eval("(guard (e ((error-object? e) (error-object-message e))) (/ 0 1))"), "\"/: division of 1 by zero\"\n");
+}
+
+#[test]
+fn read_error_is_true_for_reader_failures() {
+    assert_eq!(eval(r##"(guard (e (#t (cons (read-error? e) (file-error? e)))) (read (open-input-string "#\\bogus")))"##), "(#t . #f)\n");
+    assert_eq!(eval(r#"(guard (e (#t (read-error? e))) (read (open-input-string "(1 2")))"#), "#t\n");
+    assert_eq!(eval(r#"(guard (e (#t (read-error? e))) (load "unbalanced.scm"))"#), "#t\n");
+    assert_eq!(eval(r#"(guard (e (#t (file-error? e))) (load "missing.scm"))"#), "#t\n");
+    assert_eq!(eval(r#"(guard (e (#t (read-error? e))) (error "not a read"))"#), "#f\n");
+    assert_eq!(eval_err(r#"(read (open-input-string "(1 2"))"#), "read error: input ends partway through a datum\n");
+}
+
+#[test]
+fn reentrant_continuations_and_deep_recursion() {
+    let reentry = "(let k (vector #f)) (+ 1 (call/cc (lambda (c) (begin (vector-set! k 0 c) 1)))) ((vector-ref k 0) 10)";
+    assert_eq!(eval(reentry), "2\n11\n");
+    assert_eq!(eval("(let (count n) ((= n 0) 0 (+ 1 (count (- 1 n))))) (count 100000)"), "100000\n");
+    let wind = "(with-output-to-string (lambda () (call/cc (lambda (k) (dynamic-wind (lambda () (display \"in \")) (lambda () (k 1)) (lambda () (display \"out\")))))))";
+    assert_eq!(eval(wind), "\"in out\"\n");
+    assert_eq!(eval("(+ 1 (call/ec (lambda (k) (+ 10 (k 5)))))"), "6\n");
+    assert_eq!(eval_err("(let k (vector #f)) (call/ec (lambda (c) (vector-set! k 0 c))) ((vector-ref k 0) 1)"),
+               "control error: escape continuation called after its call/ec returned\n");
+}
+
+#[test]
+fn builtins_calling_procedures_keep_continuations() {
+    let map = "(let k (vector #f)) (vector-map (lambda (x) (+ x (call/cc (lambda (c) (begin (vector-set! k 0 c) 0))))) (vector 1 2)) ((vector-ref k 0) 10)";
+    assert_eq!(eval(map), "#(1 2)\n#(1 12)\n");
+    let update = "(let t (make-hash-table)) (hash-table-set! t 1 5) (call/ec (lambda (k) (hash-table-update! t 1 (lambda (v) (k (* v 2)))))) (hash-table-ref t 1)";
+    assert_eq!(eval(update), "()\n10\n5\n");
+    let output = "(let k (vector #f)) (with-output-to-string (lambda () (begin (display \"a\") (call/cc (lambda (c) (vector-set! k 0 c))) (display \"b\")))) ((vector-ref k 0) 0)";
+    assert_eq!(eval(output), "\"ab\"\n\"abb\"\n");
+    assert_eq!(eval("(string-index \"abc\" (lambda (c) (char=? c #\\c))) (hash-table-ref (make-hash-table) 3 (lambda () \"none\"))"), "2\n\"none\"\n");
+}
+
+#[test]
+fn leaving_a_redirected_thunk_restores_the_output_port() {
+    assert_eq!(eval("(call/ec (lambda (k) (with-output-to-string (lambda () (begin (display \"lost\") (k 1)))))) (display \"kept\")"), "1\nkept()\n");
+    assert_eq!(eval("(guard (e (#t (error-object-message e))) (with-output-to-string (lambda () (begin (display \"lost\") (error \"oops\"))))) (display \"kept\")"),
+               "\"oops\"\nkept()\n");
+    assert_eq!(eval_err("(vector-map car (vector 1))"), "type error: car expects a pair, got 1\n");
+}
+
+#[test]
+fn delimited_continuations() {
+    assert_eq!(eval("(+ 1 (reset (+ 10 (shift k (k (k 100))))))"), "121\n");
+    assert_eq!(eval("(call-with-prompt \"tag\" (lambda () (+ 1 (abort-to-prompt \"tag\" 5))) (lambda (k v) (k (* v 2))))"), "11\n");
+    assert_eq!(eval("(reset (vector-map (lambda (x) (shift k (cons x (k (* x 10))))) (vector 1 2)))"), "(1 2 . #(10 20))\n");
+    let abort = "(call-with-prompt 1 (lambda () (with-output-to-string (lambda () (begin (display \"lost\") (abort-to-prompt 1 2))))) (lambda (k v) v)) (display \"kept\")";
+    assert_eq!(eval(abort), "2\nkept()\n");
+}
+
+#[test]
+fn prompt_errors() {
+    assert_eq!(eval_err("(abort-to-prompt \"nowhere\" 1)"), "control error: no prompt tagged \"nowhere\"\n");
+    assert_eq!(eval_err("(shift 5 1)"), "syntax error: malformed shift: (shift 5 1)\n");
+    assert_eq!(eval("(guard (e (#t (error-object-message e))) (abort-to-prompt \"missing\"))"), "\"no prompt tagged \\\"missing\\\"\"\n");
+}
+
+#[test]
+fn multiple_values() {
+    assert_eq!(eval("(receive (q r) (values 17 3) (+ q r)) (call-with-values (lambda () (values 1 2)) +)"), "20\n3\n");
+    assert_eq!(eval("(let-values (((a b) (values 1 2)) (all (values 3 4))) all) (let*-values (((a) (values 1)) ((b) (values (+ a 1)))) b)"), "(3 4)\n2\n");
+    assert_eq!(eval("(define-values (x y) (values 5 6)) (+ x y) (values 1 2)"), "11\n1\n2\n");
+}
+
+#[test]
+fn values_through_continuations() {
+    assert_eq!(eval("(call-with-values (lambda () (call/cc (lambda (k) (k 1 2)))) cons)"), "(1 . 2)\n");
+    assert_eq!(eval("(call-with-values (lambda () (call/ec (lambda (k) (k 1 2)))) cons)"), "(1 . 2)\n");
+    assert_eq!(eval("(call-with-values (lambda () (reset (values 1 (shift k (k 2))))) cons)"), "(1 . 2)\n");
+}
+
+#[test]
+fn values_errors() {
+    assert_eq!(eval_err("(receive (a b) (values 1) a)"), "arity error: expected 2 values, got 1\n");
+    assert_eq!(eval_err("(let-values (((a b) (values 1 2 3))) a)"), "arity error: expected 2 values, got 3\n");
+    assert_eq!(eval_err("(receive 5 (values 1) 1)"), "syntax error: malformed multiple-values form: (receive 5 (values 1) 1)\n");
+    assert_eq!(eval("(guard (e (#t (error-object-message e))) (receive (a b) (values 1) a))"), "\"expected 2 values, got 1\"\n");
+}
+
+#[test]
+fn promises_and_streams_without_the_prelude() {
+    let ints = "(let (ints n) (stream-cons n (ints (+ n 1)))) ";
+    let output = lisp(&["--no-prelude", "-e", &format!("{}{}", ints, "(stream-take 3 (stream-filter (lambda (x) (= x (* 2 (/ 2 x)))) (ints 1))) (stream-car (stream-cdr (ints 5)))")]);
+    assert_eq!(stdout(&output), "(2 4 6)\n6\n");
+    assert_eq!(eval("(stream-take 5 (stream-cons 1 (stream-cons 2 nil))) (force (delay (+ 1 2))) (force (make-promise 4)) (promise? (delay 1))"), "(1 2)\n3\n4\n#t\n");
+    assert_eq!(eval("(let p (delay (begin (display \"once \") 5))) (force p) (force p)"), "once 5\n5\n");
+    let chain = "(let (loop n) (delay-force ((= n 0) (make-promise 0) (loop (- 1 n))))) (force (loop 20000))";
+    assert_eq!(eval(chain), "0\n");
+    assert_eq!(eval(&format!("{}{}", ints, "(stream-car (stream-filter (lambda (x) (= x 20000)) (ints 1)))")), "20000\n");
+}
+
+#[test]
+fn streams_keep_continuations() {
+    let reentry = "(let k (vector #f)) (stream-take 5 (stream-filter (lambda (x) (call/cc (lambda (c) (begin ((= x 1) (vector-set! k 0 c) 0) #t)))) (stream-cons 1 (stream-cons 2 nil)))) ((vector-ref k 0) #f)";
+    assert_eq!(eval(reentry), "(1 2)\n(2)\n");
+    let escape = "(let (ints n) (stream-cons n (ints (+ n 1)))) (call/ec (lambda (k) (stream-take 3 (stream-filter (lambda (x) ((= x 3) (k x) #f)) (ints 1)))))";
+    assert_eq!(eval(escape), "3\n");
+}
+
+#[test]
+fn promise_and_stream_errors() {
+    assert_eq!(eval_err("(stream-car nil)"), "type error: stream-car expects a non-empty stream, got ()\n");
+    assert_eq!(eval_err("(stream-take 2 5)"), "type error: stream-take expects a non-empty stream, got 5\n");
+    assert_eq!(eval_err("(delay)"), "syntax error: malformed promise: delay expects 1 expression, got (delay)\n");
+    assert_eq!(eval_err("(stream-cons 1)"), "syntax error: malformed promise: stream-cons expects 2 expressions, got (stream-cons 1)\n");
+    assert_eq!(eval("(guard (e (#t (error-object-message e))) (stream-take 2 (stream-cons 1 (car 5))))"), "\"car expects a pair, got 5\"\n");
+}
+
+#[test]
+fn parameters() {
+    let width = "(let width (make-parameter 10)) (let (show) (width)) ";
+    assert_eq!(eval(&format!("{}{}", width, "(parameterize ((width 20)) (show)) (show) (guard (e (#t (width))) (parameterize ((width 30)) (raise 1)))")), "20\n10\n10\n");
+    assert_eq!(eval("(let p (make-parameter 1 (lambda (x) (* x 10)))) (p) (parameterize ((p 2)) (p))"), "10\n20\n");
+    assert_eq!(eval("(parameterize ((current-output-port (open-output-string))) (display \"unseen\"))"), "()\n");
+    let nested = "(with-output-to-string (lambda () (begin (parameterize ((current-output-port (open-output-string))) (display \"lost\")) (display \"kept\"))))";
+    assert_eq!(eval(nested), "\"kept\"\n");
+}
+
+#[test]
+fn parameters_follow_continuations() {
+    let reentry = "(let p (make-parameter 1)) (let k (vector #f)) (parameterize ((p 2)) (+ (p) (call/cc (lambda (c) (begin (vector-set! k 0 c) 0))))) (p) ((vector-ref k 0) 10)";
+    assert_eq!(eval(reentry), "2\n1\n12\n");
+    assert_eq!(eval("(let p (make-parameter 1)) (call/ec (lambda (k) (parameterize ((p 2)) (k (p))))) (p)"), "2\n1\n");
+}
+
+#[test]
+fn parameter_errors() {
+    assert_eq!(eval_err("((make-parameter 1) 5)"), "arity error: a parameter expects no arguments, got 1\n");
+    assert_eq!(eval_err("(parameterize (5) 1)"), "syntax error: malformed parameterize: (parameterize (5) 1)\n");
+    assert_eq!(eval_err("(parameterize ((current-output-port 5)) 1)"), "type error: current port expects a port, got 5\n");
+    let converter = "(let p (make-parameter (cons 1 2) (lambda (x) (car x)))) (guard (e (#t (error-object-message e))) (parameterize ((p 5)) (p)))";
+    assert_eq!(eval(converter), "\"car expects a pair, got 5\"\n");
+}
+
+// evaluator.out is what the tree-walking evaluator printed for evaluator.scm before expressions
+// were compiled, so this checks the compiler finds variables, captures, definitions and tail
+// calls the way it did
+#[test]
+fn compiled_code_matches_the_tree_walker() {
+    let output = lisp(&["evaluator.scm"]);
+    let expected = std::fs::read_to_string(scripts_dir().join("evaluator.out")).expect("couldn't read evaluator.out");
+    assert!(output.status.success(), "evaluator.scm failed: {}", stderr(&output));
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn special_forms_run_again_and_again() {
+    let source = "(let p (make-parameter 0))
+        (let (step n) (receive (a b) (values (parameterize ((p n)) (p)) (guard (e (#t e)) (raise n))) (+ a b)))
+        (let (loop n acc) ((= n 0) (+ acc (p)) (loop (- 1 n) (+ acc (step n)))))
+        (loop 2000 0)";
+    assert_eq!(eval(source), "4002000\n");
+}
+
+#[test]
+fn booleans_jump_to_their_branch() {
+    assert_eq!(eval("(let (fib n) ((= n 0) 0 ((= n 1) 1 (+ (fib (- 1 n)) (fib (- 2 n)))))) (fib 20)"), "6765\n");
+    assert_eq!(eval("(let (f x) (+ 1 ((= x 0) 10 20))) (cons (f 0) (f 1))"), "(11 . 21)\n");
+    assert_eq!(eval_err("(let (f x) ((= x 0) 1)) (f 0)"), "arity error: true expects 2 branches\n");
+}
@@ -0,0 +1,2 @@
+#!/usr/bin/env rust-lisp
+(exit (len (command-line)))
@@ -0,0 +1,62 @@
+(let (show x) (begin (write x) (newline)))
+
+(let x 1)
+(let (shadow x) (+ x 100))
+(show (shadow 5))
+(show x)
+(let (adder n) (lambda (m) (+ n m)))
+(let add3 (adder 3))
+(show (add3 4))
+(show ((adder 10) ((adder 20) 1)))
+(let (curry3 a) (lambda (b) (lambda (c) (+ a (* b c)))))
+(show (((curry3 1) 2) 3))
+
+(let (late) later)
+(let later "defined afterwards")
+(show (late))
+
+(let (body n) (begin (let y (* n 2)) (let y (+ y 1)) (let (twice z) (* 2 z)) (twice y)))
+(show (body 5))
+(let y "global y")
+(show y)
+
+(let (counter start) (begin (let box (vector start)) (lambda () (begin (vector-set! box 0 (+ 1 (vector-ref box 0))) (vector-ref box 0)))))
+(let tick (counter 10))
+(tick)
+(show (tick))
+
+(let (fact n) ((= n 0) 1 (* n (fact (- 1 n)))))
+(show (fact 10))
+(let (loop n acc) ((= n 0) acc (loop (- 1 n) (+ acc n))))
+(show (loop 50000 0))
+(let (parity n) ((= n 0) "even" ((= n 1) "odd" (parity (- 2 n)))))
+(show (parity 1001))
+
+(show (map (lambda (n) (* n n)) (cons 1 (cons 2 (cons 3 nil)))))
+(show (fold (lambda (s n) (+ s n)) 0 (cons 1 (cons 2 (cons 3 nil)))))
+(show (vector-map (lambda (v) (adder v)) #(1 2)))
+(show (vector-map (lambda (f) (f 1)) (vector-map adder #(1 2))))
+
+(let (choose b) (b (show "yes") (show "no")))
+(choose true)
+(show ((= 1 2) "then" "else"))
+
+(let (guarded n) (guard (e (#t (+ n e))) (raise (* n 10))))
+(show (guarded 4))
+(let (split n) (receive (a b) (values n (* n n)) (+ a b)))
+(show (split 3))
+(let (lazily n) (begin (let p (delay (* n 3))) (force p)))
+(show (lazily 7))
+(let depth (make-parameter 0))
+(let (nested n) (parameterize ((depth n)) (+ (depth) n)))
+(show (nested 21))
+(show (depth))
+(let (escape n) (+ 1 (call/cc (lambda (k) (* 100 (k n))))))
+(show (escape 41))
+(let (prompt n) (reset (+ n (shift k (k (k 1))))))
+(show (prompt 10))
+
+(define-record-type pair-of (make-pair-of a b) pair-of? (a pair-a) (b pair-b))
+(let (swap p) (make-pair-of (pair-b p) (pair-a p)))
+(show (pair-a (swap (make-pair-of "left" "right"))))
+(show (string-append "a" (number->string (fact 5)) "b"))
@@ -0,0 +1,3 @@
+(include "include/helper.scm")
+(load "include/loaded.scm")
+(exit (helper loaded))
@@ -0,0 +1,2 @@
+(let b 1)
+(include "cycle-a.scm")
@@ -0,0 +1 @@
+(let (helper x) (+ x 40))
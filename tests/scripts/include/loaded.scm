@@ -0,0 +1 @@
+(let loaded 2)
@@ -0,0 +1 @@
+(let (twice x) (* 2 x))